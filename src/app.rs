@@ -3,18 +3,39 @@ use egui_toast::{Toast, ToastKind, ToastOptions, Toasts};
 use egui_modal::Modal;
 use image::RgbaImage;
 use arboard::Clipboard;
-use std::sync::mpsc;
-use std::{thread, time};
-
+use std::sync::Arc;
+use std::time;
+
+mod animation_utils;
+mod capture_job;
+mod capture_utils;
+mod color_utils;
+mod coords_utils;
 mod crop_utils;
+mod cursor_utils;
+mod history_utils;
 mod image_utils;
+mod interval;
+mod loupe_utils;
 mod painting_utils;
 mod path_utils;
+mod preset_utils;
+mod recording;
+mod redact_utils;
+mod render_utils;
 mod save_utils;
 mod screenshot_utils;
+mod scroll_capture;
 mod screenshot_view;
+mod selection_utils;
+mod shape_edit_utils;
+mod shape_utils;
+mod stitch_utils;
+mod text_utils;
 mod hotkeys_utils;
+mod window_utils;
 
+use crate::app::capture_utils::CaptureError;
 use crate::app::save_utils::SavePath;
 
 use self::save_utils::check_filename;
@@ -24,18 +45,24 @@ pub enum Views {
     Settings,
     Screenshot,
     Save,
+    Recording, // Anteprima della registrazione appena finita, prima di salvarla
 }
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum ScreenshotType {
     FullScreen,
     PartialScreen,
+    Monitor(u32), // id del monitor da catturare
+    Window(coords_utils::PhysicalRect), // Il rettangolo della finestra scelta, in pixel fisici
+    Region(coords_utils::PhysicalRect), // Una regione esatta in pixel fisici, es. da un preset a dimensione fissa
+    Scrolling(coords_utils::PhysicalRect), // Cattura a scorrimento della regione, vedi scroll_capture
+    Recording(coords_utils::PhysicalRect), // Registrazione animata della regione, vedi recording
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ImgFormats {
-    PNG,
-    JPEG,
-    GIF,
+    Png,
+    Jpeg,
+    Gif,
 }
 
 pub struct QuickCaptureApp {
@@ -46,9 +73,18 @@ pub struct QuickCaptureApp {
     painted_screenshot: Option<egui::TextureHandle>, // egui wants TextureHandles for painting on things. However, this cannot be used to save the image.
     pub save_path: SavePath,
     screenshot_view: screenshot_view::ScreenshotView,
-    update_counter: u8,     // Serve per chiamare _frame.set_visible(). Una volta chiamato, la finestra diventa trasparente all'update successivo. Per questo motivo bisogna contare a quale update siamo arrivati.
+    capture_backend: Arc<dyn capture_utils::CaptureBackend>, // Chi cattura davvero gli schermi, vedi capture_utils
+    capture_job: Option<capture_job::CaptureJob>, // La cattura in corso, se ce n'è una
+    freezing: bool, // capture_job sta catturando il fotogramma congelato, non lo screenshot finale
+    cursor_mode: cursor_utils::CursorMode, // Se e come mostrare il puntatore negli screenshot
+    repeating_region: bool, // capture_job è partito dalla Home per rifare l'ultima regione, senza maschera
+    scroll_capture: Option<scroll_capture::ScrollCapture>, // La cattura a scorrimento in corso
+    recording: Option<recording::Recording>, // La registrazione in corso
+    recording_preview: Option<recording::RecordingPreview>, // La registrazione finita, da salvare o buttare
+    interval_capture: Option<interval::IntervalCapture>, // La serie di screenshot a intervalli in corso
     keyboard_shortcuts: hotkeys_utils::AllKeyboardShortcuts,
     clipboard: Option<Clipboard>,
+    annotation_color: Option<Color32>, // Colore preso con il contagocce della maschera, per le prossime annotazioni
     toasts: Toasts,
     which_shortcut_field: String,
     modifier: Modifiers,
@@ -65,12 +101,21 @@ impl Default for QuickCaptureApp {
             painted_screenshot: None,
             save_path: SavePath::new(
                 std::env::current_dir().unwrap().join("target"),
-                ImgFormats::PNG,
+                ImgFormats::Png,
             ), // Salva in <app_directory>/target/
             screenshot_view: screenshot_view::ScreenshotView::new(),
-            update_counter: 0,
+            capture_backend: capture_utils::default_backend(),
+            capture_job: None,
+            freezing: false,
+            cursor_mode: cursor_utils::CursorMode::default(),
+            repeating_region: false,
+            scroll_capture: None,
+            recording: None,
+            recording_preview: None,
+            interval_capture: None,
             keyboard_shortcuts: hotkeys_utils::AllKeyboardShortcuts::default(),
             clipboard: Clipboard::new().ok(),
+            annotation_color: None,
             toasts: Toasts::new(),
             which_shortcut_field: "".to_string(),
             modifier: Modifiers::CTRL,
@@ -109,6 +154,19 @@ impl QuickCaptureApp {
                         self.view = Views::Screenshot;
                     }
 
+                    // Rifà lo screenshot dell'ultima regione, senza passare dalla maschera
+                    let last_region = self.screenshot_view.last_region;
+                    if ui
+                        .add_enabled(last_region.is_some(), Button::new("↺ Previous region").small())
+                        .on_hover_text("Capture the same region as the last partial screenshot")
+                        .clicked()
+                        || ctx.input_mut(|i| i.consume_shortcut(&self.keyboard_shortcuts.capture_previous_region.unwrap()))
+                    {
+                        if let Some(region) = last_region {
+                            self.capture_previous_region(region, _frame);
+                        }
+                    }
+
                     if self.screenshot_image_buffer.is_some() {
                         // Se è stato fatto uno screenshot, mostra i bottoni per aggiungere modifiche e salvarlo
 
@@ -187,6 +245,9 @@ impl QuickCaptureApp {
                                     self.painted_screenshot.clone(),
                                     self.screenshot_image_buffer.clone(),
                                 ));
+                                if let Some(color) = self.annotation_color {
+                                    self.painting.as_mut().unwrap().set_stroke_color(color);
+                                }
                             }
 
                             let painting = self.painting.as_mut().unwrap();
@@ -195,34 +256,26 @@ impl QuickCaptureApp {
                             painting.ui_control(ui);
                             // Aggiunge un livello che ha come sfondo lo screenshot su cui sopra è possibile disegnare
                             painting.ui_content(ui);
-                            if self.screenshot_image_buffer.clone().unwrap().dimensions()
-                                != painting
-                                    .screenshot_image_buffer
-                                    .clone()
-                                    .unwrap()
-                                    .dimensions()
-                            {
-                                // Se le dimensioni dello screenshot sono cambiate, aggiorna il buffer
-                                self.screenshot_image_buffer =
-                                   painting.screenshot_image_buffer.clone();
-                                    self.painted_screenshot = Some(ui.ctx().load_texture(
-                                        "painted_screenshot",
-                                        image_utils::load_image_from_memory(
-                                            self.screenshot_image_buffer.clone().unwrap(),
-                                        ),
-                                        Default::default(),
-                                    ));
-                                    self.painting = Some(painting_utils::Painting::new_crop(
-                                        self.painted_screenshot.clone(),
-                                        self.screenshot_image_buffer.clone(),
-                                        painting.shapes.clone()
-                                    ));
-
-                                    _frame.set_window_size(Vec2::new((self.screenshot_image_buffer.clone().unwrap().width() as f32) / 1.5 + 50., self.screenshot_image_buffer.clone().unwrap().height() as f32 / 1.5 + 50.));
-
-                                    ctx.request_repaint();
-                            }else{
-                                self.painting = Some(painting.clone());
+                            let picked = painting.picked.take();
+                            if painting.image_changed {
+                                // Ritaglio, rotazione o annullamento hanno cambiato l'immagine: aggiorna il buffer e la texture
+                                painting.image_changed = false;
+                                self.screenshot_image_buffer = painting.screenshot_image_buffer.clone();
+                                self.painted_screenshot = Some(ui.ctx().load_texture(
+                                    "painted_screenshot",
+                                    image_utils::load_image_from_memory(
+                                        self.screenshot_image_buffer.clone().unwrap(),
+                                    ),
+                                    Default::default(),
+                                ));
+                                painting.set_texture(self.painted_screenshot.clone());
+
+                                _frame.set_window_size(Vec2::new((self.screenshot_image_buffer.clone().unwrap().width() as f32) / 1.5 + 50., self.screenshot_image_buffer.clone().unwrap().height() as f32 / 1.5 + 50.));
+
+                                ctx.request_repaint();
+                            }
+                            if let Some(picked) = picked {
+                                self.copy_color(picked);
                             }
                         };
                     });
@@ -274,7 +327,7 @@ impl QuickCaptureApp {
                 if ui.small_button("💾 Save").clicked() {
                     // Genera la shortcut, dopo controlla se è vera
 
-                    let shortcut = KeyboardShortcut::new(self.modifier, self.keyboard_shortcuts.from_name(&self.key_var));
+                    let shortcut = KeyboardShortcut::new(self.modifier, self.keyboard_shortcuts.key_from_name(&self.key_var));
 
                     if self.keyboard_shortcuts.check_if_valid(&shortcut).0 {
                         // Shortcut valida -> rimpiazza
@@ -327,6 +380,28 @@ impl QuickCaptureApp {
 
             ui.separator();
 
+            cursor_utils::ui_settings(ui, &mut self.cursor_mode);
+
+            ui.horizontal(|ui| {
+                ui.label("Minimum selection size:");
+                ui.add(egui::DragValue::new(&mut self.screenshot_view.min_selection_size).clamp_range(1.0..=500.0).suffix(" px"))
+                    .on_hover_text("Smaller selections are discarded, so that a stray click doesn't take a screenshot");
+            });
+
+            ui.separator();
+
+            let presets_edit = preset_utils::ui_settings(ui, &mut self.screenshot_view.presets);
+            if presets_edit.changed {
+                self.screenshot_view.preset = None;
+            }
+            if presets_edit.committed {
+                if let Err(e) = preset_utils::save_presets(&self.screenshot_view.presets) {
+                    println!("Could not save the region presets: {}", e);
+                }
+            }
+
+            ui.separator();
+
             ui.push_id(2, |ui| {
                 let table = egui_extras::TableBuilder::new(ui)
             .striped(true)
@@ -355,12 +430,14 @@ impl QuickCaptureApp {
                         ui.label("Copy image to clipboard");
                         ui.label("Print shortcuts debug info");
                         ui.label("Take a screenshot");
+                        ui.label("Capture the previous region");
                     });
                     row.col(|ui| {
                         ui.label(self.keyboard_shortcuts.human_readable_shorcut("save"));
                         ui.label(self.keyboard_shortcuts.human_readable_shorcut("copy_to_clipboard"));
                         ui.label(self.keyboard_shortcuts.human_readable_shorcut("test"));
                         ui.label(self.keyboard_shortcuts.human_readable_shorcut("take_screenshot"));
+                        ui.label(self.keyboard_shortcuts.human_readable_shorcut("capture_previous_region"));
                     });
                     row.col(|ui| {
                         // let mut new_shortcut = "".to_string();
@@ -380,6 +457,10 @@ impl QuickCaptureApp {
                             self.which_shortcut_field = "take_screenshot".to_string();
                             modal.open();
                         }
+                        if ui.small_button("Edit").clicked() {
+                            self.which_shortcut_field = "capture_previous_region".to_string();
+                            modal.open();
+                        }
                     });
                 });
             });
@@ -401,75 +482,387 @@ impl QuickCaptureApp {
     pub fn screenshot_view(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Prima hai scelto che screenshot fare, adesso fai lo screenshot
         // println!("screenshot_view");
+        self.toasts.show(ctx);
 
-        // Perché il contatore? Perché chiamare _frame.set_visible rendere trasparente la finestra soltanto al prossimo update. Se non controllassi
-        // con un contatore, rimarrebbe la maschera nello screenshot perché non è stata nascosta.
-        if self.screenshot_type.is_none() {
-            self.update_counter = 0;
-        } else {
-            self.update_counter += 1;
+        if let Some(scroll) = self.scroll_capture.as_mut() {
+            match scroll.ui(ctx) {
+                scroll_capture::ScrollAction::Stop => scroll.stop(),
+                scroll_capture::ScrollAction::Cancel => {
+                    scroll.stop();
+                    self.scroll_capture = None;
+                    self.screenshot_type = None;
+                    self.view = Views::Home;
+                    self.restore_window(_frame);
+                    return;
+                }
+                scroll_capture::ScrollAction::None => {}
+            }
+            if let Some(result) = scroll.poll() {
+                let region = scroll.region;
+                self.scroll_capture = None;
+                match result {
+                    Ok(Some(image)) => {
+                        let capture = screenshot_utils::Capture {
+                            image,
+                            region,
+                            skipped: vec![],
+                            cursor: None,
+                        };
+                        self.finish_capture(Ok(capture), _frame);
+                    }
+                    // Done prima del primo fotogramma: non c'è niente da mostrare, come con Cancel
+                    Ok(None) => {
+                        self.screenshot_type = None;
+                        self.view = Views::Home;
+                        self.restore_window(_frame);
+                    }
+                    Err(e) => self.finish_capture(Err(e), _frame),
+                }
+            }
+            return;
         }
 
-        // Questa parte è stata anticipata altrimenti si vedrebbe la maschera disegnata nelle righe successive
-        self.screenshot_view
-            .ui(ctx, _frame, &mut self.view, &mut self.screenshot_type);
-        if self.screenshot_type.is_some() {
-            // println!("Update counter {}", self.update_counter);
-            // It's not the screenshot, but the data describing it. It needs to be converted to an image.
-
-            if self.update_counter == 2 {
-                
-                // 150 è fisso perché nascondere la maschera richiede un po' di tempo.
-                thread::sleep(time::Duration::from_millis(150 + self.screenshot_view.get_timer_delay() as u64));
-
-                let (tx_screenshot_buffer, rx_screenshot_buffer) = mpsc::channel();
-                let tmp_screenshot_type = self.screenshot_type.clone();
-                let ctx1 = ctx.clone();
-                if self.screenshot_type.clone().unwrap() == ScreenshotType::FullScreen {
-                    // Take the screenshot and wait until it's done
-                    thread::spawn(move || {
-                        let screenshot_image_buffer =
-                            screenshot_utils::take_screenshot(tmp_screenshot_type, None, &ctx1);
-                        tx_screenshot_buffer.send(screenshot_image_buffer).unwrap();
-                    });
-                } else if self.screenshot_type.clone().unwrap() == ScreenshotType::PartialScreen {
-                    //TODO\
-                    let grab = self.screenshot_view.clone();
-                    thread::spawn(move || {
-                        let screenshot_image_buffer =
-                            screenshot_utils::take_screenshot(tmp_screenshot_type, Some(grab), &ctx1);
-                        tx_screenshot_buffer.send(screenshot_image_buffer).unwrap();
-                    });
-                }
-    
-                self.screenshot_image_buffer = rx_screenshot_buffer.recv().unwrap();
-    
-                if self.screenshot_image_buffer.is_some() {
-                    self.save_path.name = save_utils::generate_filename();
-                    // println!("default filename is: {}", self.save_path.name);
+        if let Some(recording) = self.recording.as_mut() {
+            match recording.ui(ctx) {
+                recording::RecordingAction::Stop => recording.stop(),
+                recording::RecordingAction::Cancel => {
+                    recording.stop();
+                    self.recording = None;
+                    self.screenshot_type = None;
+                    self.view = Views::Home;
+                    self.restore_window(_frame);
+                    return;
                 }
-                self.view = Views::Home;
+                recording::RecordingAction::None => {}
+            }
+            if let Some(result) = recording.poll() {
+                self.recording = None;
                 self.screenshot_type = None;
-                self.painting = None;
-                // _frame.set_window_size(vec2(640.0, 400.0));
-                _frame.set_window_size(egui::Vec2::new(self.screenshot_image_buffer.as_mut().unwrap().width() as f32, self.screenshot_image_buffer.as_mut().unwrap().height() as f32));
-                _frame.set_centered();
+                match result {
+                    Ok(recorded) => {
+                        if let Some(e) = recorded.stopped_by {
+                            self.notify(format!("Recording stopped early. {}", e), ToastKind::Warning);
+                        }
+                        self.save_path.name = save_utils::generate_filename();
+                        self.recording_preview = Some(recording::RecordingPreview::new(recorded.frames));
+                        self.view = Views::Recording;
+                    }
+                    Err(e) => {
+                        println!("Recording failed: {}", e);
+                        self.notify(format!("Recording failed. {}", e), ToastKind::Error);
+                        self.view = Views::Home;
+                    }
+                }
+                self.restore_window(_frame);
+            }
+            return;
+        }
+
+        if let Some(interval) = self.interval_capture.as_mut() {
+            if matches!(interval.ui(ctx), interval::IntervalAction::Stop) {
+                interval.stop();
+            }
+            if let Some(result) = interval.poll(ctx, _frame, &self.capture_backend) {
+                self.finish_interval_capture(result, _frame);
+            }
+            return;
+        }
+
+        if self.capture_job.is_none() {
+            if self.screenshot_view.monitors.is_empty() {
+                self.screenshot_view.refresh_monitors(self.capture_backend.as_ref());
+            }
+
+            let needs_frozen_frame = self.screenshot_view.frozen_frame && self.screenshot_view.frozen.is_none();
+            if needs_frozen_frame || self.screenshot_view.freeze_requested {
+                // Prima si cattura tutto il desktop, poi l'utente sceglie cosa tenere sul fotogramma congelato
+                self.screenshot_view.freeze_requested = false;
+                self.start_capture_job(screenshot_utils::CaptureTarget::Desktop, true, _frame);
+                return;
+            }
+
+            // Mostra UI per generare screenshot: maschera sopra lo schermo per scegliere il tipo di screenshot
+            self.screenshot_view
+                .ui(ctx, _frame, &mut self.view, &mut self.screenshot_type);
+            if let Some(picked) = self.screenshot_view.picked.take() {
+                if picked.set_stroke {
+                    self.annotation_color = Some(picked.color);
+                }
+                self.copy_color(picked);
+            }
+
+            if let Some(ScreenshotType::Scrolling(region)) = self.screenshot_type {
+                self.start_scroll_capture(ctx, region, _frame);
+                return;
+            }
+            if let Some(ScreenshotType::Recording(region)) = self.screenshot_type {
+                self.start_recording(ctx, region, _frame);
+                return;
+            }
+
+            if let Some(screenshot_type) = self.screenshot_type.clone() {
+                // L'utente ha scelto: parte il job. La cattura vera e propria avviene nei prossimi update
+                let target = match screenshot_type {
+                    ScreenshotType::FullScreen => screenshot_utils::CaptureTarget::Desktop,
+                    ScreenshotType::PartialScreen => {
+                        screenshot_utils::CaptureTarget::LogicalRegion(self.screenshot_view.selected_rect())
+                    }
+                    ScreenshotType::Monitor(id) => screenshot_utils::CaptureTarget::Monitor(id),
+                    ScreenshotType::Window(rect)
+                    | ScreenshotType::Region(rect)
+                    | ScreenshotType::Scrolling(rect)
+                    | ScreenshotType::Recording(rect) => {
+                        screenshot_utils::CaptureTarget::PhysicalRegion(rect)
+                    }
+                };
+                if self.screenshot_view.interval_mode {
+                    self.start_interval_capture(target, _frame);
+                    return;
+                }
+                if let Some(result) = self.screenshot_view.crop_frozen(&target, &self.cursor_mode) {
+                    // C'è già il fotogramma congelato: basta ritagliarlo, esattamente quello che l'utente ha visto
+                    self.finish_capture(result, _frame);
+                    return;
+                }
+                self.start_capture_job(target, false, _frame);
+            } else {
                 _frame.set_visible(true);
-                _frame.set_decorations(true); // Preparing for the next update in which we'll go back Home
             }
+        }
+
+        if let Some(job) = self.capture_job.as_mut() {
+            job.poll(ctx, _frame, &self.capture_backend);
 
+            if job.countdown_ui(ctx) {
+                // Annullato durante il timer: si torna alla maschera per scegliere il tipo di screenshot,
+                // o alla Home se la maschera non c'è mai stata
+                if self.freezing {
+                    self.screenshot_view.frozen_frame = false;
+                    self.freezing = false;
+                }
+                self.capture_job = None;
+                self.screenshot_type = None;
+                if self.repeating_region {
+                    self.repeating_region = false;
+                    self.view = Views::Home;
+                    self.restore_window(_frame);
+                }
+                return;
+            }
         }
 
-        // Mostra UI per generare screenshot
+        if self.capture_job.as_ref().map_or(false, |job| job.is_finished()) {
+            let result = self
+                .capture_job
+                .take()
+                .and_then(|job| job.into_result())
+                .unwrap_or(Err(CaptureError::Interrupted));
+            if self.freezing {
+                self.freezing = false;
+                match result {
+                    Ok(capture) => self.screenshot_view.freeze(ctx, capture),
+                    Err(e) => {
+                        // Senza fotogramma si continua con la selezione sullo schermo dal vivo
+                        self.screenshot_view.frozen_frame = false;
+                        self.notify(format!("Could not freeze the screen. {}", e), ToastKind::Error);
+                    }
+                }
+            } else {
+                self.finish_capture(result, _frame);
+            }
+        }
+    }
 
-        // Se l'utente non ha scelto che tipo di screenshot fare (tra FullScreen e PartialScreen)
-        // Questa funzione viene chiamata ad ogni update() che può avvenire più volte in un secondo.
-        // Siccome noi vogliamo che lo per chiamata di "screenshot_view", si fa un controllo con un contatore
+    // Cattura la regione dell'ultimo screenshot parziale. È in pixel fisici: resta la stessa anche se
+    // la finestra dell'app si sposta, e il timer funziona come per gli altri screenshot.
+    fn capture_previous_region(&mut self, region: coords_utils::PhysicalRect, _frame: &mut eframe::Frame) {
+        self.view = Views::Screenshot;
+        self.screenshot_type = Some(ScreenshotType::Region(region));
+        self.repeating_region = true;
+        self.start_capture_job(screenshot_utils::CaptureTarget::PhysicalRegion(region), false, _frame);
+    }
 
-        if self.screenshot_type.is_none() {
-            _frame.set_visible(true);
+    // La cattura a scorrimento non passa da capture_job: va avanti finché l'utente non la ferma.
+    // La finestra diventa una piccola barra di controllo fuori dalla regione.
+    fn start_scroll_capture(&mut self, ctx: &egui::Context, region: coords_utils::PhysicalRect, _frame: &mut eframe::Frame) {
+        self.screenshot_view.frozen = None;
+        let layout = coords_utils::DesktopLayout::new(&self.screenshot_view.monitors);
+        capture_job::prepare_control_window(_frame, &layout, &region, scroll_capture::CONTROL_WINDOW_SIZE);
+        self.scroll_capture = Some(scroll_capture::ScrollCapture::start(
+            ctx,
+            &self.capture_backend,
+            region,
+            time::Duration::from_millis(self.screenshot_view.get_timer_delay() as u64),
+            self.screenshot_view.auto_scroll,
+        ));
+    }
+
+    // Come la cattura a scorrimento: una barra di controllo fuori dalla regione finché l'utente non ferma la registrazione
+    fn start_recording(&mut self, ctx: &egui::Context, region: coords_utils::PhysicalRect, _frame: &mut eframe::Frame) {
+        self.screenshot_view.frozen = None;
+        let layout = coords_utils::DesktopLayout::new(&self.screenshot_view.monitors);
+        capture_job::prepare_control_window(_frame, &layout, &region, recording::CONTROL_WINDOW_SIZE);
+        self.recording = Some(recording::Recording::start(
+            ctx,
+            &self.capture_backend,
+            region,
+            time::Duration::from_millis(self.screenshot_view.get_timer_delay() as u64),
+            recording::RecordingOptions {
+                cursor: self.cursor_mode,
+                ..self.screenshot_view.recording_options
+            },
+        ));
+    }
+
+    fn start_interval_capture(&mut self, target: screenshot_utils::CaptureTarget, _frame: &mut eframe::Frame) {
+        // Ogni scatto è una cattura nuova: il fotogramma congelato non serve
+        self.screenshot_view.frozen = None;
+        let mut save_path = self.save_path.clone();
+        save_path.name = save_utils::generate_filename();
+        let interval = interval::IntervalCapture::new(
+            target,
+            time::Duration::from_millis(self.screenshot_view.get_timer_delay() as u64),
+            self.screenshot_view.interval_options,
+            self.screenshot_view.skip_failed_monitors,
+            self.cursor_mode,
+            save_path,
+        );
+        interval.prepare_control_window(_frame);
+        self.interval_capture = Some(interval);
+    }
 
-            // Maschera sopra lo schermo per scegliere il tipo di screenshot
+    fn finish_interval_capture(&mut self, result: Result<(), String>, _frame: &mut eframe::Frame) {
+        let Some(interval) = self.interval_capture.take() else {
+            return;
+        };
+        let summary = format!(
+            "{} shots saved in {} ({} identical skipped).",
+            interval.saved,
+            self.save_path.path.display(),
+            interval.skipped
+        );
+        match result {
+            Ok(()) => self.notify(summary, ToastKind::Success),
+            Err(e) => {
+                println!("Interval capture failed: {}", e);
+                self.notify(format!("Interval capture stopped. {} {}", e, summary), ToastKind::Error);
+            }
+        }
+        // Nella Home resta l'ultimo scatto salvato
+        if let Some(last) = interval.last {
+            self.screenshot_image_buffer = Some(last);
+            self.save_path.name = save_utils::generate_filename();
+            self.painting = None;
+        }
+        self.view = Views::Home;
+        self.screenshot_type = None;
+        self.restore_window(_frame);
+    }
+
+    fn start_capture_job(&mut self, target: screenshot_utils::CaptureTarget, freezing: bool, _frame: &mut eframe::Frame) {
+        let mut job = capture_job::CaptureJob::new(
+            target,
+            time::Duration::from_millis(self.screenshot_view.get_timer_delay() as u64),
+            self.screenshot_view.skip_failed_monitors,
+            self.cursor_mode,
+        );
+        if freezing {
+            job = job.freezing();
+        }
+        job.prepare_countdown_window(_frame);
+        self.capture_job = Some(job);
+        self.freezing = freezing;
+    }
+
+    fn finish_capture(&mut self, result: Result<screenshot_utils::Capture, CaptureError>, _frame: &mut eframe::Frame) {
+        match result {
+            Ok(capture) => {
+                if !capture.skipped.is_empty() {
+                    let skipped: Vec<String> = capture.skipped.iter().map(|e| e.to_string()).collect();
+                    self.notify(format!("{}. Left out of the screenshot.", skipped.join("; ")), ToastKind::Warning);
+                }
+                if matches!(self.screenshot_type, Some(ScreenshotType::PartialScreen | ScreenshotType::Region(_))) {
+                    self.screenshot_view.last_region = Some(capture.region);
+                }
+                self.screenshot_image_buffer = Some(capture.image);
+                self.save_path.name = save_utils::generate_filename();
+                self.painting = None;
+                // println!("default filename is: {}", self.save_path.name);
+            }
+            Err(e) => {
+                // L'app continua a funzionare: si torna alla Home con lo screenshot precedente (se c'era)
+                println!("Screenshot failed: {}", e);
+                self.notify(format!("Screenshot failed. {}", e), ToastKind::Error);
+            }
+        }
+
+        self.view = Views::Home;
+        self.screenshot_type = None;
+        self.repeating_region = false;
+        self.screenshot_view.frozen = None;
+        self.restore_window(_frame);
+    }
+
+    // Riporta la finestra come serve alla Home, grande quanto lo screenshot se c'è
+    fn restore_window(&mut self, _frame: &mut eframe::Frame) {
+        match self.screenshot_image_buffer.as_ref() {
+            Some(image) => _frame.set_window_size(egui::Vec2::new(image.width() as f32, image.height() as f32)),
+            None => _frame.set_window_size(vec2(640.0, 400.0)),
+        }
+        _frame.set_centered();
+        _frame.set_visible(true);
+        _frame.set_decorations(true); // Preparing for the next update in which we'll go back Home
+    }
+
+    // Copia negli appunti il colore preso con il contagocce, nel formato scelto
+    fn copy_color(&mut self, picked: color_utils::PickedColor) {
+        let copied = self
+            .clipboard
+            .as_mut()
+            .map_or(false, |clip| clip.set_text(picked.text.clone()).is_ok());
+        if copied {
+            self.notify(format!("Copied {} to clipboard", picked.text), ToastKind::Success);
+        } else {
+            self.notify(format!("Could not copy {} to clipboard", picked.text), ToastKind::Error);
+        }
+    }
+
+    fn notify(&mut self, text: String, kind: ToastKind) {
+        // Mostra un toast in basso al centro. Viene disegnato dalla prossima view che chiama self.toasts.show()
+        self.toasts = Toasts::new()
+            .anchor(Align2::CENTER_BOTTOM, (0.0, -20.0))
+            .direction(egui::Direction::BottomUp);
+
+        self.toasts.add(Toast {
+            text: text.into(),
+            kind,
+            options: ToastOptions::default()
+                .duration_in_seconds(5.0)
+                .show_progress(true)
+        });
+    }
+
+    pub fn recording_view(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.toasts.show(ctx);
+        let Some(preview) = self.recording_preview.as_mut() else {
+            self.view = Views::Home;
+            return;
+        };
+        match preview.ui(ctx, &self.save_path) {
+            recording::PreviewAction::Saved(path) => {
+                self.recording_preview = None;
+                self.view = Views::Home;
+                self.notify(format!("Recording saved to {}", path.display()), ToastKind::Success);
+            }
+            recording::PreviewAction::Failed(e) => {
+                println!("Could not save the recording: {}", e);
+                self.notify(format!("Could not save the recording. {}", e), ToastKind::Error);
+            }
+            recording::PreviewAction::Discard => {
+                self.recording_preview = None;
+                self.view = Views::Home;
+            }
+            recording::PreviewAction::None => {}
         }
     }
 
@@ -482,20 +875,26 @@ impl QuickCaptureApp {
                 ui.add_enabled(check_filename(&self.save_path.name), Button::new("Save"));
             if save_button.clicked() {
                 println!("Save button pressed");
-                save_utils::save_image(
+                let saved = save_utils::save_image(
                     &self.save_path,
                     self.painting.as_mut().unwrap().generate_rgba_image(),
                 );
-                
-                self.toasts.add(Toast {
-                    text: "Image saved successfully!".into(),
-                    kind: ToastKind::Success,
-                    options: ToastOptions::default()
-                    .duration_in_seconds(3.0)
-                    .show_progress(true)
-                });
-                
-                self.view = Views::Home;
+                match saved {
+                    Ok(_) => {
+                        self.toasts.add(Toast {
+                            text: "Image saved successfully!".into(),
+                            kind: ToastKind::Success,
+                            options: ToastOptions::default()
+                            .duration_in_seconds(3.0)
+                            .show_progress(true)
+                        });
+                        self.view = Views::Home;
+                    }
+                    Err(e) => {
+                        println!("Could not save the image: {}", e);
+                        self.notify(format!("Could not save the image: {}", e), ToastKind::Error);
+                    }
+                }
             };
             if ui.button("Go back").clicked() {
                 self.view = Views::Home;
@@ -0,0 +1,131 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+use std::io::Write;
+use std::time::Duration;
+
+// Un fotogramma di una registrazione, con quanto resta sullo schermo
+#[derive(Clone)]
+pub struct RecordedFrame {
+    pub image: RgbaImage,
+    pub delay: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+        }
+    }
+}
+
+// GIF animata in loop. Ogni fotogramma viene ridotto a una palette di 256 colori con NeuQuant:
+// speed va da 1 (palette migliore, lentissimo) a 30 (palette peggiore, veloce).
+pub fn encode_gif<W: Write>(writer: W, frames: &[RecordedFrame], speed: i32) -> Result<(), String> {
+    check_sizes(frames)?;
+    let mut encoder = GifEncoder::new_with_speed(writer, speed.clamp(1, 30));
+    encoder.set_repeat(Repeat::Infinite).map_err(|e| e.to_string())?;
+    for frame in frames {
+        // Il GIF conta il tempo in centesimi di secondo
+        let delay = Delay::from_numer_denom_ms(frame.delay.as_millis().max(10) as u32, 1);
+        encoder
+            .encode_frame(Frame::from_parts(frame.image.clone(), 0, 0, delay))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// PNG animato in loop: nessuna perdita di colori, file più grandi del GIF
+pub fn encode_apng<W: Write>(writer: W, frames: &[RecordedFrame]) -> Result<(), String> {
+    let first = frames.first().ok_or("no frames to encode")?;
+    check_sizes(frames)?;
+    let mut encoder = png::Encoder::new(writer, first.image.width(), first.image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0).map_err(|e| e.to_string())?;
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    for frame in frames {
+        let (numerator, denominator) = apng_delay(frame.delay);
+        writer.set_frame_delay(numerator, denominator).map_err(|e| e.to_string())?;
+        writer.write_image_data(frame.image.as_raw()).map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())
+}
+
+// Il ritardo di un fotogramma APNG è una frazione di secondo con numeratore e denominatore a 16 bit.
+// In millesimi si arriva a 65 s: oltre si passa ai centesimi (fino a 655 s), poi si taglia.
+fn apng_delay(delay: Duration) -> (u16, u16) {
+    let millis = delay.as_millis().max(1);
+    if millis <= u16::MAX as u128 {
+        (millis as u16, 1000)
+    } else {
+        (((millis + 5) / 10).min(u16::MAX as u128) as u16, 100)
+    }
+}
+
+// Tutti i fotogrammi devono essere grandi come il primo: gli encoder non sanno cosa fare degli altri
+fn check_sizes(frames: &[RecordedFrame]) -> Result<(), String> {
+    let Some(first) = frames.first() else {
+        return Ok(());
+    };
+    match frames.iter().position(|f| f.image.dimensions() != first.image.dimensions()) {
+        Some(index) => Err(format!(
+            "frame {} is {}×{}, but the recording is {}×{}",
+            index + 1,
+            frames[index].image.width(),
+            frames[index].image.height(),
+            first.image.width(),
+            first.image.height()
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, delay: Duration) -> RecordedFrame {
+        RecordedFrame {
+            image: RgbaImage::from_pixel(width, height, image::Rgba([10, 20, 30, 255])),
+            delay,
+        }
+    }
+
+    #[test]
+    fn apng_keeps_long_delays() {
+        let frames = [frame(4, 3, Duration::from_millis(40)), frame(4, 3, Duration::from_secs(120))];
+        let mut apng = vec![];
+        encode_apng(&mut apng, &frames).unwrap();
+
+        let mut reader = png::Decoder::new(apng.as_slice()).read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let mut delays = vec![];
+        while reader.next_frame(&mut buffer).is_ok() {
+            let control = reader.info().frame_control().unwrap();
+            delays.push(control.delay_num as f32 / control.delay_den as f32);
+        }
+        assert_eq!(delays, vec![0.04, 120.0]);
+    }
+
+    #[test]
+    fn apng_delay_is_clamped() {
+        assert_eq!(apng_delay(Duration::ZERO), (1, 1000));
+        assert_eq!(apng_delay(Duration::from_millis(65_535)), (65_535, 1000));
+        assert_eq!(apng_delay(Duration::from_millis(65_536)), (6554, 100));
+        assert_eq!(apng_delay(Duration::from_secs(3600)), (u16::MAX, 100));
+    }
+
+    #[test]
+    fn frames_of_another_size_are_rejected() {
+        let frames = [frame(4, 3, Duration::from_millis(100)), frame(4, 2, Duration::from_millis(100))];
+        assert!(encode_apng(vec![], &frames).is_err());
+        assert!(encode_gif(vec![], &frames, 10).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use super::capture_utils::{CaptureBackend, CaptureError};
+use super::coords_utils::{DesktopLayout, PhysicalRect};
+use super::cursor_utils::{CursorMode, CursorReader};
+use super::screenshot_utils::{self, Capture, CaptureTarget};
+use egui::*;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Nascondere la finestra richiede un po' di tempo, altrimenti la maschera finisce nello screenshot
+const HIDE_WINDOW_DELAY: Duration = Duration::from_millis(150);
+
+// Dimensione della finestra mentre si aspetta il timer: abbastanza per il conto alla rovescia e il bottone
+const COUNTDOWN_WINDOW_SIZE: Vec2 = vec2(300.0, 48.0);
+
+// Una cattura passa da questi stati, uno per update. Nessuno di questi blocca il thread della UI:
+// la cattura vera e propria avviene in un altro thread, e ad ogni update si controlla se ha finito.
+pub enum JobState {
+    Scheduled { fire_at: Instant },
+    HidingWindow { since: Instant, frames: u8 },
+    Capturing { receiver: mpsc::Receiver<Result<Capture, CaptureError>> },
+    Done(Capture),
+    Failed(CaptureError),
+}
+
+pub struct CaptureJob {
+    pub state: JobState,
+    target: CaptureTarget,
+    skip_failed_monitors: bool,
+    cursor: CursorMode,
+    freezing: bool, // Il cursore non va disegnato ma tenuto in Capture::cursor
+}
+
+impl CaptureJob {
+    pub fn new(
+        target: CaptureTarget,
+        delay: Duration,
+        skip_failed_monitors: bool,
+        cursor: CursorMode,
+    ) -> Self {
+        Self {
+            state: JobState::Scheduled {
+                fire_at: Instant::now() + delay,
+            },
+            target,
+            skip_failed_monitors,
+            cursor,
+            freezing: false,
+        }
+    }
+
+    // Per il fotogramma congelato: è soltanto lo sfondo della maschera, il cursore ci starebbe sopra.
+    // Viene letto comunque, e disegnato sul ritaglio della selezione.
+    pub fn freezing(mut self) -> Self {
+        self.freezing = true;
+        self
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, JobState::Done(_) | JobState::Failed(_))
+    }
+
+    // Quanto manca allo scatto, se il timer sta ancora andando
+    pub fn remaining(&self) -> Option<Duration> {
+        match self.state {
+            JobState::Scheduled { fire_at } => {
+                Some(fire_at.saturating_duration_since(Instant::now()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn into_result(self) -> Option<Result<Capture, CaptureError>> {
+        match self.state {
+            JobState::Done(capture) => Some(Ok(capture)),
+            JobState::Failed(e) => Some(Err(e)),
+            _ => None,
+        }
+    }
+
+    // Va chiamata ad ogni update: fa avanzare il job allo stato successivo quando è il momento
+    pub fn poll(
+        &mut self,
+        ctx: &Context,
+        _frame: &mut eframe::Frame,
+        backend: &Arc<dyn CaptureBackend>,
+    ) {
+        match &mut self.state {
+            JobState::Scheduled { fire_at } => {
+                if Instant::now() >= *fire_at {
+                    _frame.set_visible(false);
+                    self.state = JobState::HidingWindow {
+                        since: Instant::now(),
+                        frames: 0,
+                    };
+                }
+                // Il conto alla rovescia va aggiornato anche se l'utente non muove il mouse
+                ctx.request_repaint_after(Duration::from_millis(50));
+            }
+            JobState::HidingWindow { since, frames } => {
+                // set_visible ha effetto soltanto dall'update successivo, per questo si contano anche i frame
+                *frames = frames.saturating_add(1);
+                if *frames >= 2 && since.elapsed() >= HIDE_WINDOW_DELAY {
+                    let (tx_screenshot_buffer, rx_screenshot_buffer) = mpsc::channel();
+                    let backend = backend.clone();
+                    let ctx1 = ctx.clone();
+                    let target = self.target.clone();
+                    let skip_failed_monitors = self.skip_failed_monitors;
+                    let (cursor, freezing) = (self.cursor, self.freezing);
+                    thread::spawn(move || {
+                        let mut reader = CursorReader::default();
+                        let screenshot_image_buffer = if freezing {
+                            screenshot_utils::capture_with_cursor(
+                                backend.as_ref(),
+                                &target,
+                                skip_failed_monitors,
+                                cursor != CursorMode::Hidden,
+                                &mut reader,
+                            )
+                        } else {
+                            screenshot_utils::take_screenshot(
+                                backend.as_ref(),
+                                &target,
+                                skip_failed_monitors,
+                                &cursor,
+                                &mut reader,
+                            )
+                        };
+                        let _ = tx_screenshot_buffer.send(screenshot_image_buffer);
+                        ctx1.request_repaint(); // Sveglia la UI, che sta aspettando il risultato
+                    });
+                    self.state = JobState::Capturing {
+                        receiver: rx_screenshot_buffer,
+                    };
+                }
+                ctx.request_repaint();
+            }
+            JobState::Capturing { receiver } => match receiver.try_recv() {
+                Ok(Ok(capture)) => self.state = JobState::Done(capture),
+                Ok(Err(e)) => self.state = JobState::Failed(e),
+                Err(TryRecvError::Empty) => ctx.request_repaint_after(Duration::from_millis(16)),
+                // Il thread è morto senza mandare niente
+                Err(TryRecvError::Disconnected) => {
+                    self.state = JobState::Failed(CaptureError::Interrupted)
+                }
+            },
+            JobState::Done(_) | JobState::Failed(_) => {}
+        }
+    }
+
+    // Rimpicciolisce la finestra, così durante il timer l'utente può usare il resto dello schermo
+    pub fn prepare_countdown_window(&self, _frame: &mut eframe::Frame) {
+        if self.remaining().map_or(false, |remaining| !remaining.is_zero()) {
+            _frame.set_decorations(false);
+            _frame.set_window_size(COUNTDOWN_WINDOW_SIZE);
+            _frame.set_window_pos(pos2(20.0, 20.0));
+            _frame.set_visible(true);
+        }
+    }
+
+    // Mostra il conto alla rovescia finché il timer non scade. Ritorna true se l'utente ha annullato la cattura.
+    pub fn countdown_ui(&self, ctx: &Context) -> bool {
+        let mut cancelled = false;
+        if let Some(remaining) = self.remaining() {
+            Window::new("Countdown")
+                .title_bar(false)
+                .resizable(false)
+                .fixed_pos(Pos2::ZERO)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("📷 Capturing in {:.1} s", remaining.as_secs_f32()));
+                        ui.separator();
+                        if ui.button("✖ Cancel").clicked() {
+                            cancelled = true;
+                        }
+                    });
+                });
+        }
+        cancelled
+    }
+}
+
+// Prepara la finestra di controllo di una cattura che dura nel tempo (a scorrimento, registrazione...):
+// piccola, senza decorazioni e fuori dalla regione, altrimenti finirebbe nei fotogrammi
+pub fn prepare_control_window(_frame: &mut eframe::Frame, layout: &DesktopLayout, region: &PhysicalRect, size: Vec2) {
+    _frame.set_decorations(false);
+    _frame.set_window_size(size);
+    _frame.set_window_pos(control_window_pos(layout, region, size));
+    _frame.set_visible(true);
+}
+
+// Sopra la regione, sotto, a destra o a sinistra: la prima posizione che sta tutta in un monitor.
+// Altrimenti in un angolo di un monitor che non contiene la regione, o come ultima spiaggia in alto a sinistra.
+fn control_window_pos(layout: &DesktopLayout, region: &PhysicalRect, size: Vec2) -> Pos2 {
+    const MARGIN: f32 = 12.0;
+    let Some(region) = layout.physical_rect_to_logical(region).map(|r| r.expand(MARGIN)) else {
+        return pos2(20.0, 20.0);
+    };
+    let candidates = [
+        pos2(region.min.x, region.min.y - size.y),
+        pos2(region.min.x, region.max.y),
+        pos2(region.max.x, region.min.y),
+        pos2(region.min.x - size.x, region.min.y),
+    ];
+    candidates
+        .into_iter()
+        .find(|pos| {
+            let window = Rect::from_min_size(*pos, size);
+            layout.monitors.iter().any(|m| m.logical.contains_rect(window))
+        })
+        .or_else(|| {
+            layout
+                .monitors
+                .iter()
+                .find(|m| !m.logical.intersects(region))
+                .map(|m| m.logical.min + vec2(20.0, 20.0))
+        })
+        .unwrap_or(pos2(20.0, 20.0))
+}
@@ -0,0 +1,295 @@
+use image::RgbaImage;
+use screenshots::Screen;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+
+// Tutto quello che può andare storto durante una cattura. Nessuna di queste deve far crashare l'app:
+// vengono mostrate all'utente con un toast.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureError {
+    NoDisplays { reason: String },                   // Nessun monitor trovato, oppure non si è riusciti a elencarli
+    MonitorCapture { monitor: u32, reason: String }, // La cattura di un singolo monitor è fallita
+    Decode { monitor: u32, reason: String },         // Cattura riuscita, ma i pixel non sono stati decodificati
+    OutsideDisplays,                                 // La regione richiesta non cade su nessun monitor
+    Interrupted,                                     // Il thread della cattura è finito senza restituire niente
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::NoDisplays { reason } => write!(f, "No displays found: {}", reason),
+            CaptureError::MonitorCapture { monitor, reason } => {
+                write!(f, "Could not capture display {}: {}", monitor, reason)
+            }
+            CaptureError::Decode { monitor, reason } => {
+                write!(f, "Could not decode the capture of display {}: {}", monitor, reason)
+            }
+            CaptureError::OutsideDisplays => {
+                write!(f, "The selected region is outside every display")
+            }
+            CaptureError::Interrupted => write!(f, "The capture was interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+// Tutto quello che take_screenshot deve sapere su un monitor, indipendentemente da chi lo cattura.
+// Le coordinate sono quelle restituite da display_info: origine e dimensioni non scalate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Monitor {
+    pub id: u32,
+    pub output: Option<String>, // Il nome dell'uscita video (es. "HDMI-1"), se il sistema lo conosce
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+    pub is_primary: bool,
+}
+
+impl Monitor {
+    // Il nome dell'uscita, o l'id se non lo conosciamo: è stabile finché il monitor resta collegato
+    pub fn name(&self) -> String {
+        match &self.output {
+            Some(output) => output.clone(),
+            None => format!("Display {}", self.id),
+        }
+    }
+}
+
+// Chi implementa questo trait sa elencare i monitor e catturarne il contenuto.
+// take_screenshot si occupa soltanto di comporre le immagini e ritagliarle.
+pub trait CaptureBackend: Send + Sync {
+    fn monitors(&self) -> Result<Vec<Monitor>, CaptureError>;
+    fn capture(&self, monitor: &Monitor) -> Result<RgbaImage, CaptureError>;
+
+    // Cattura più monitor in parallelo, un thread per monitor; i risultati sono nello stesso ordine di monitors.
+    // Un backend che deve preparare qualcosa prima di ogni cattura lo può fare qui una volta sola.
+    fn capture_all(&self, monitors: &[Monitor]) -> Vec<Result<RgbaImage, CaptureError>> {
+        in_parallel(monitors, |monitor| self.capture(monitor))
+    }
+}
+
+pub fn in_parallel<F>(monitors: &[Monitor], capture: F) -> Vec<Result<RgbaImage, CaptureError>>
+where
+    F: Fn(&Monitor) -> Result<RgbaImage, CaptureError> + Sync,
+{
+    let capture = &capture;
+    thread::scope(|scope| {
+        let handles: Vec<_> = monitors.iter().map(|monitor| scope.spawn(move || capture(monitor))).collect();
+        monitors
+            .iter()
+            .zip(handles)
+            .map(|(monitor, handle)| {
+                handle.join().unwrap_or(Err(CaptureError::MonitorCapture {
+                    monitor: monitor.id,
+                    reason: "the capture thread panicked".to_string(),
+                }))
+            })
+            .collect()
+    })
+}
+
+// Il backend reale, basato sul crate screenshots
+pub struct ScreenshotsBackend;
+
+impl ScreenshotsBackend {
+    fn all_screens(&self) -> Result<Vec<Screen>, CaptureError> {
+        Screen::all().map_err(|e| CaptureError::NoDisplays {
+            reason: e.to_string(),
+        })
+    }
+
+    // Lo schermo del monitor fra quelli già elencati
+    fn screen<'a>(screens: &'a [Screen], monitor: &Monitor) -> Result<&'a Screen, CaptureError> {
+        screens
+            .iter()
+            .find(|screen| screen.display_info.id == monitor.id)
+            .ok_or(CaptureError::MonitorCapture {
+                monitor: monitor.id,
+                reason: "the display has been disconnected".to_string(),
+            })
+    }
+
+    fn capture_screen(screen: &Screen, monitor: &Monitor) -> Result<RgbaImage, CaptureError> {
+        let image = screen.capture().map_err(|e| CaptureError::MonitorCapture {
+            monitor: monitor.id,
+            reason: e.to_string(),
+        })?;
+        // screenshots restituisce già i pixel in RGBA: il buffer si sposta nel RgbaImage, senza copiarlo
+        let (width, height) = (image.width(), image.height());
+        RgbaImage::from_raw(width, height, image.into()).ok_or(CaptureError::Decode {
+            monitor: monitor.id,
+            reason: format!("the buffer does not contain {}x{} RGBA pixels", width, height),
+        })
+    }
+}
+
+impl CaptureBackend for ScreenshotsBackend {
+    fn monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
+        let names = output_names();
+        let monitors: Vec<Monitor> = self
+            .all_screens()?
+            .into_iter()
+            .map(|screen| Monitor {
+                id: screen.display_info.id,
+                output: names.get(&screen.display_info.id).cloned(),
+                x: screen.display_info.x,
+                y: screen.display_info.y,
+                width: screen.display_info.width,
+                height: screen.display_info.height,
+                scale_factor: screen.display_info.scale_factor,
+                is_primary: screen.display_info.is_primary,
+            })
+            .collect();
+
+        if monitors.is_empty() {
+            return Err(CaptureError::NoDisplays {
+                reason: "the system reported zero displays".to_string(),
+            });
+        }
+        Ok(monitors)
+    }
+
+    fn capture(&self, monitor: &Monitor) -> Result<RgbaImage, CaptureError> {
+        let screens = self.all_screens()?;
+        Self::capture_screen(Self::screen(&screens, monitor)?, monitor)
+    }
+
+    // Gli schermi si elencano una volta sola per tutti i monitor
+    fn capture_all(&self, monitors: &[Monitor]) -> Vec<Result<RgbaImage, CaptureError>> {
+        match self.all_screens() {
+            Ok(screens) => in_parallel(monitors, |monitor| Self::capture_screen(Self::screen(&screens, monitor)?, monitor)),
+            Err(error) => monitors.iter().map(|_| Err(error.clone())).collect(),
+        }
+    }
+}
+
+// Il backend finto: non tocca lo schermo e restituisce per ogni monitor un'immagine sintetica
+// e deterministica, così la composizione e il ritaglio si possono provare anche senza display.
+pub struct FakeBackend {
+    monitors: Vec<Monitor>,
+    failing: Vec<u32>, // id dei monitor la cui cattura fallisce sempre
+}
+
+impl FakeBackend {
+    pub fn new(monitors: Vec<Monitor>) -> Self {
+        Self {
+            monitors,
+            failing: vec![],
+        }
+    }
+
+    // Fa fallire ogni cattura del monitor indicato, per provare la gestione degli errori
+    pub fn failing_monitor(mut self, id: u32) -> Self {
+        self.failing.push(id);
+        self
+    }
+
+    // Due monitor 1080p affiancati, il secondo a destra di quello principale
+    pub fn dual_monitor() -> Self {
+        Self::new(vec![
+            Monitor {
+                id: 1,
+                output: None,
+                x: 0,
+                y: 0,
+                width: 1920,
+                height: 1080,
+                scale_factor: 1.0,
+                is_primary: true,
+            },
+            Monitor {
+                id: 2,
+                output: None,
+                x: 1920,
+                y: 0,
+                width: 1920,
+                height: 1080,
+                scale_factor: 1.0,
+                is_primary: false,
+            },
+        ])
+    }
+
+    // Ogni monitor ha un colore di base diverso, e ogni pixel codifica la sua posizione:
+    // guardando un pixel del risultato si capisce da quale monitor e da quale punto arriva.
+    pub fn pattern_pixel(monitor: &Monitor, x: u32, y: u32) -> image::Rgba<u8> {
+        let base = (monitor.id.wrapping_mul(67) % 256) as u8;
+        image::Rgba([base, (x % 256) as u8, (y % 256) as u8, 255])
+    }
+}
+
+impl CaptureBackend for FakeBackend {
+    fn monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
+        if self.monitors.is_empty() {
+            return Err(CaptureError::NoDisplays {
+                reason: "the fake backend has no monitors".to_string(),
+            });
+        }
+        Ok(self.monitors.clone())
+    }
+
+    fn capture(&self, monitor: &Monitor) -> Result<RgbaImage, CaptureError> {
+        if self.failing.contains(&monitor.id) {
+            return Err(CaptureError::MonitorCapture {
+                monitor: monitor.id,
+                reason: "simulated failure".to_string(),
+            });
+        }
+        let width = (monitor.width as f32 * monitor.scale_factor) as u32;
+        let height = (monitor.height as f32 * monitor.scale_factor) as u32;
+        Ok(RgbaImage::from_fn(width, height, |x, y| {
+            Self::pattern_pixel(monitor, x, y)
+        }))
+    }
+}
+
+// display_info non conosce il nome dei monitor. Su X11 l'id di un monitor è quello della sua uscita RandR,
+// che ha anche un nome; sugli altri sistemi si resta sull'id.
+fn output_names() -> HashMap<u32, String> {
+    #[cfg(target_os = "linux")]
+    {
+        x11::output_names().unwrap_or_else(|e| {
+            println!("Could not read the display names: {}", e);
+            HashMap::new()
+        })
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        HashMap::new()
+    }
+}
+
+// Se QUICKCAPTURE_FAKE_BACKEND è impostata, l'app usa il backend finto (utile su macchine senza display).
+// Con QUICKCAPTURE_FAKE_BACKEND=broken il secondo monitor fallisce sempre.
+pub fn default_backend() -> Arc<dyn CaptureBackend> {
+    match std::env::var("QUICKCAPTURE_FAKE_BACKEND") {
+        Ok(value) if value == "broken" => Arc::new(FakeBackend::dual_monitor().failing_monitor(2)),
+        Ok(_) => Arc::new(FakeBackend::dual_monitor()),
+        Err(_) => Arc::new(ScreenshotsBackend),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use std::collections::HashMap;
+    use std::error::Error;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::randr::ConnectionExt as RandrExt;
+
+    pub fn output_names() -> Result<HashMap<u32, String>, Box<dyn Error>> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+        let resources = conn.randr_get_screen_resources_current(root)?.reply()?;
+        let mut names = HashMap::new();
+        for output in resources.outputs {
+            let info = conn.randr_get_output_info(output, resources.config_timestamp)?.reply()?;
+            names.insert(output, String::from_utf8_lossy(&info.name).into_owned());
+        }
+        Ok(names)
+    }
+}
@@ -0,0 +1,171 @@
+use egui::{Color32, ComboBox, Ui};
+
+// Come copiare il colore preso con il contagocce
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorFormat {
+    Hex,     // #12A0D7
+    Rgb,     // rgb(18, 160, 215)
+    Hsl,     // hsl(197, 85%, 46%)
+    Color32, // Color32::from_rgb(18, 160, 215), da incollare nel codice egui
+}
+
+impl ColorFormat {
+    pub const ALL: [ColorFormat; 4] = [ColorFormat::Hex, ColorFormat::Rgb, ColorFormat::Hsl, ColorFormat::Color32];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ColorFormat::Hex => "HEX",
+            ColorFormat::Rgb => "rgb()",
+            ColorFormat::Hsl => "hsl()",
+            ColorFormat::Color32 => "Color32",
+        }
+    }
+}
+
+// Un colore preso con il contagocce, da copiare negli appunti
+#[derive(Clone, Debug, PartialEq)]
+pub struct PickedColor {
+    pub color: Color32,
+    pub text: String,     // Il colore già formattato
+    pub set_stroke: bool, // Va usato anche come colore delle annotazioni
+}
+
+impl PickedColor {
+    pub fn new(color: Color32, format: ColorFormat, set_stroke: bool) -> Self {
+        Self {
+            color,
+            text: format_color(color, format),
+            set_stroke,
+        }
+    }
+}
+
+// L'alpha viene ignorato: gli screenshot sono opachi
+pub fn format_color(color: Color32, format: ColorFormat) -> String {
+    let (r, g, b) = (color.r(), color.g(), color.b());
+    match format {
+        ColorFormat::Hex => format!("#{:02X}{:02X}{:02X}", r, g, b),
+        ColorFormat::Rgb => format!("rgb({}, {}, {})", r, g, b),
+        ColorFormat::Hsl => {
+            let (h, s, l) = rgb_to_hsl(r, g, b);
+            format!("hsl({}, {}%, {}%)", h.round() as u32 % 360, s.round(), l.round())
+        }
+        ColorFormat::Color32 => format!("Color32::from_rgb({}, {}, {})", r, g, b),
+    }
+}
+
+// Tonalità in gradi, saturazione e luminosità in percentuale
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        // Grigio: la tonalità non conta
+        return (0.0, 0.0, l * 100.0);
+    }
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (h, s * 100.0, l * 100.0)
+}
+
+// Le opzioni del contagocce: il formato da copiare e se usare il colore per le annotazioni
+pub fn ui_picker_options(ui: &mut Ui, id: &str, format: &mut ColorFormat, set_stroke: &mut bool) {
+    ComboBox::from_id_source(id)
+        .selected_text(format.label())
+        .show_ui(ui, |ui| {
+            for option in ColorFormat::ALL {
+                ui.selectable_value(format, option, option.label());
+            }
+        });
+    ui.checkbox(set_stroke, "Set as stroke color")
+        .on_hover_text("Also use the picked color for the annotations");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hsl(r: u8, g: u8, b: u8) -> String {
+        format_color(Color32::from_rgb(r, g, b), ColorFormat::Hsl)
+    }
+
+    #[test]
+    fn hex_is_uppercase_and_zero_padded() {
+        assert_eq!(format_color(Color32::from_rgb(18, 160, 215), ColorFormat::Hex), "#12A0D7");
+        assert_eq!(format_color(Color32::from_rgb(0, 10, 255), ColorFormat::Hex), "#000AFF");
+        assert_eq!(format_color(Color32::BLACK, ColorFormat::Hex), "#000000");
+    }
+
+    #[test]
+    fn rgb_lists_the_channels() {
+        assert_eq!(format_color(Color32::from_rgb(18, 160, 215), ColorFormat::Rgb), "rgb(18, 160, 215)");
+        assert_eq!(format_color(Color32::WHITE, ColorFormat::Rgb), "rgb(255, 255, 255)");
+    }
+
+    #[test]
+    fn hsl_of_primaries_and_mixes() {
+        assert_eq!(hsl(18, 160, 215), "hsl(197, 85%, 46%)");
+        assert_eq!(hsl(255, 0, 0), "hsl(0, 100%, 50%)");
+        assert_eq!(hsl(0, 255, 0), "hsl(120, 100%, 50%)");
+        assert_eq!(hsl(0, 0, 255), "hsl(240, 100%, 50%)");
+        assert_eq!(hsl(255, 255, 0), "hsl(60, 100%, 50%)");
+        assert_eq!(hsl(255, 0, 255), "hsl(300, 100%, 50%)");
+        assert_eq!(hsl(128, 64, 64), "hsl(0, 33%, 38%)");
+    }
+
+    #[test]
+    fn hsl_of_greys_has_no_hue_or_saturation() {
+        assert_eq!(hsl(0, 0, 0), "hsl(0, 0%, 0%)");
+        assert_eq!(hsl(128, 128, 128), "hsl(0, 0%, 50%)");
+        assert_eq!(hsl(255, 255, 255), "hsl(0, 0%, 100%)");
+    }
+
+    #[test]
+    fn hsl_hue_wraps_around_to_zero() {
+        // Un rosso appena spostato verso il magenta ha tonalità 359.8: arrotondata è 0, non 360
+        let (h, _, _) = rgb_to_hsl(255, 0, 1);
+        assert!(h > 359.5 && h < 360.0, "{}", h);
+        assert_eq!(hsl(255, 0, 1), "hsl(0, 100%, 50%)");
+        // Mai tonalità negative
+        assert_eq!(hsl(255, 0, 40), "hsl(351, 100%, 50%)");
+    }
+
+    #[test]
+    fn hsl_rounds_to_the_nearest_integer() {
+        // 201.0°, 100%, 49.8%: la luminosità arrotondata è 50
+        let (h, s, l) = rgb_to_hsl(0, 165, 254);
+        assert!((h - 201.0).abs() < 0.5 && (s - 100.0).abs() < 1e-3 && (l - 49.8).abs() < 0.05, "{} {} {}", h, s, l);
+        assert_eq!(hsl(0, 165, 254), "hsl(201, 100%, 50%)");
+        assert_eq!(hsl(1, 1, 2), "hsl(240, 33%, 1%)");
+    }
+
+    #[test]
+    fn color32_is_a_valid_egui_literal() {
+        let color = Color32::from_rgb(18, 160, 215);
+        let text = format_color(color, ColorFormat::Color32);
+        assert_eq!(text, "Color32::from_rgb(18, 160, 215)");
+        // I numeri letti dal testo danno lo stesso colore
+        let channels: Vec<u8> = text
+            .trim_start_matches("Color32::from_rgb(")
+            .trim_end_matches(')')
+            .split(", ")
+            .map(|c| c.parse().unwrap())
+            .collect();
+        assert_eq!(Color32::from_rgb(channels[0], channels[1], channels[2]), color);
+    }
+
+    #[test]
+    fn picked_color_uses_the_format() {
+        let picked = PickedColor::new(Color32::from_rgb(1, 2, 3), ColorFormat::Hex, true);
+        assert_eq!(picked.text, "#010203");
+        assert!(picked.set_stroke);
+    }
+}
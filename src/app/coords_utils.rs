@@ -0,0 +1,360 @@
+use super::capture_utils::Monitor;
+use egui::{Pos2, Rect};
+
+// Gli spazi di coordinate con cui abbiamo a che fare:
+// - punti logici: quelli di egui e del sistema operativo. display_info restituisce origine e dimensioni
+//   dei monitor in punti logici, e la maschera di selezione lavora in punti logici.
+// - pixel fisici: quelli delle immagini catturate. Un monitor con scale_factor 1.5 largo 1280 punti
+//   è largo 1920 pixel.
+// - desktop virtuale: lo spazio in pixel fisici che contiene tutti i monitor. Con scale diverse l'origine
+//   logica moltiplicata per lo scale_factor farebbe sovrapporre i monitor (o aprirebbe dei buchi), quindi
+//   DesktopLayout li dispone uno alla volta, attaccando ciascuno al bordo fisico del vicino già disposto.
+//   Il composito di take_screenshot ha l'angolo in alto a sinistra in DesktopLayout::bounds().min.
+
+// Un rettangolo in pixel fisici del desktop virtuale
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhysicalRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PhysicalRect {
+    pub fn right(&self) -> i32 {
+        self.x + self.width as i32
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height as i32
+    }
+
+    pub fn from_min_max(min: (i32, i32), max: (i32, i32)) -> Self {
+        let (x, y) = (min.0.min(max.0), min.1.min(max.1));
+        Self {
+            x,
+            y,
+            width: (min.0.max(max.0) - x) as u32,
+            height: (min.1.max(max.1) - y) as u32,
+        }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn intersect(&self, other: &PhysicalRect) -> Option<PhysicalRect> {
+        let min = (self.x.max(other.x), self.y.max(other.y));
+        let max = (self.right().min(other.right()), self.bottom().min(other.bottom()));
+        if max.0 <= min.0 || max.1 <= min.1 {
+            return None;
+        }
+        Some(Self::from_min_max(min, max))
+    }
+
+    // Lo stesso rettangolo, ma con le coordinate relative all'angolo in alto a sinistra di origin
+    pub fn relative_to(&self, origin: &PhysicalRect) -> PhysicalRect {
+        PhysicalRect {
+            x: self.x - origin.x,
+            y: self.y - origin.y,
+            ..*self
+        }
+    }
+}
+
+// Un monitor visto in entrambi gli spazi di coordinate
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonitorSpace {
+    pub monitor: Monitor,
+    pub logical: Rect,
+    pub physical: PhysicalRect,
+}
+
+// Il monitor in punti logici del desktop
+pub fn logical_rect(monitor: &Monitor) -> Rect {
+    Rect::from_min_size(
+        Pos2::new(monitor.x as f32, monitor.y as f32),
+        egui::vec2(monitor.width as f32, monitor.height as f32),
+    )
+}
+
+// Le dimensioni del monitor in pixel fisici.
+// Arrotondare e non troncare: con scale_factor 1.25 o 1.5 il cast a u32 perderebbe l'ultima riga
+pub fn physical_size(monitor: &Monitor) -> (u32, u32) {
+    let scale = monitor.scale_factor;
+    (
+        (monitor.width as f32 * scale).round() as u32,
+        (monitor.height as f32 * scale).round() as u32,
+    )
+}
+
+impl MonitorSpace {
+    fn new(monitor: Monitor, x: i32, y: i32) -> Self {
+        let logical = logical_rect(&monitor);
+        let (width, height) = physical_size(&monitor);
+        Self {
+            monitor,
+            logical,
+            physical: PhysicalRect { x, y, width, height },
+        }
+    }
+
+    // Dispone monitor accanto a self, che è già disposto
+    fn place_neighbour(&self, monitor: Monitor) -> Self {
+        let scale = self.monitor.scale_factor;
+        let (width, height) = physical_size(&monitor);
+        let x = place_on_axis(
+            (monitor.x as f32, monitor.x as f32 + monitor.width as f32),
+            (self.logical.min.x, self.logical.max.x),
+            (self.physical.x, self.physical.right()),
+            width,
+            scale,
+        );
+        let y = place_on_axis(
+            (monitor.y as f32, monitor.y as f32 + monitor.height as f32),
+            (self.logical.min.y, self.logical.max.y),
+            (self.physical.y, self.physical.bottom()),
+            height,
+            scale,
+        );
+        Self::new(monitor, x, y)
+    }
+
+    pub fn logical_to_physical(&self, point: Pos2) -> (f32, f32) {
+        let scale = self.monitor.scale_factor;
+        (
+            self.physical.x as f32 + (point.x - self.logical.min.x) * scale,
+            self.physical.y as f32 + (point.y - self.logical.min.y) * scale,
+        )
+    }
+
+    pub fn physical_to_logical(&self, x: f32, y: f32) -> Pos2 {
+        let scale = self.monitor.scale_factor;
+        Pos2::new(
+            self.logical.min.x + (x - self.physical.x as f32) / scale,
+            self.logical.min.y + (y - self.physical.y as f32) / scale,
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DesktopLayout {
+    pub monitors: Vec<MonitorSpace>,
+}
+
+impl DesktopLayout {
+    // Il primo monitor (il principale, se c'è) ha l'origine fisica uguale a quella logica scalata. Gli altri
+    // vengono disposti a partire dal più vicino a uno già disposto, così chi si tocca in punti logici si
+    // tocca anche in pixel fisici. I monitor restano nell'ordine di monitors.
+    pub fn new(monitors: &[Monitor]) -> Self {
+        let mut placed: Vec<Option<MonitorSpace>> = vec![None; monitors.len()];
+        let first = monitors.iter().position(|m| m.is_primary).unwrap_or(0);
+        if let Some(monitor) = monitors.get(first) {
+            let scale = monitor.scale_factor;
+            let x = (monitor.x as f32 * scale).round() as i32;
+            let y = (monitor.y as f32 * scale).round() as i32;
+            placed[first] = Some(MonitorSpace::new(monitor.clone(), x, y));
+        }
+        while placed.iter().any(|space| space.is_none()) {
+            let closest = monitors
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| placed[*index].is_none())
+                .flat_map(|(index, monitor)| {
+                    let logical = logical_rect(monitor);
+                    placed
+                        .iter()
+                        .flatten()
+                        .map(move |neighbour| (index, neighbour, rect_distance_sq(&logical, &neighbour.logical)))
+                })
+                .min_by(|a, b| a.2.total_cmp(&b.2));
+            // Il primo è sempre disposto, quindi finché ne manca uno c'è anche una coppia
+            let Some((index, neighbour, _)) = closest else {
+                break;
+            };
+            let space = neighbour.place_neighbour(monitors[index].clone());
+            placed[index] = Some(space);
+        }
+        Self {
+            monitors: placed.into_iter().flatten().collect(),
+        }
+    }
+
+    // Il desktop virtuale: il più piccolo rettangolo fisico che contiene tutti i monitor
+    pub fn bounds(&self) -> PhysicalRect {
+        let min_x = self.monitors.iter().map(|m| m.physical.x).min().unwrap_or(0);
+        let min_y = self.monitors.iter().map(|m| m.physical.y).min().unwrap_or(0);
+        let max_x = self.monitors.iter().map(|m| m.physical.right()).max().unwrap_or(0);
+        let max_y = self.monitors.iter().map(|m| m.physical.bottom()).max().unwrap_or(0);
+        PhysicalRect::from_min_max((min_x, min_y), (max_x, max_y))
+    }
+
+    // Il monitor che contiene il punto logico. Se il punto cade in un buco tra i monitor, quello più vicino.
+    pub fn monitor_at_logical(&self, point: Pos2) -> Option<&MonitorSpace> {
+        self.monitors
+            .iter()
+            .find(|m| m.logical.contains(point))
+            .or_else(|| {
+                self.monitors.iter().min_by(|a, b| {
+                    a.logical
+                        .distance_sq_to_pos(point)
+                        .total_cmp(&b.logical.distance_sq_to_pos(point))
+                })
+            })
+    }
+
+    pub fn monitor_at_physical(&self, x: i32, y: i32) -> Option<&MonitorSpace> {
+        self.monitors.iter().find(|m| m.physical.contains(x, y))
+    }
+
+    pub fn logical_to_physical(&self, point: Pos2) -> Option<(f32, f32)> {
+        self.monitor_at_logical(point)
+            .map(|m| m.logical_to_physical(point))
+    }
+
+    pub fn physical_to_logical(&self, x: f32, y: f32) -> Option<Pos2> {
+        self.monitor_at_physical(x.floor() as i32, y.floor() as i32)
+            .map(|m| m.physical_to_logical(x, y))
+    }
+
+    // Converte una selezione fatta in punti logici in un rettangolo fisico. Ogni angolo viene convertito
+    // con la scala del monitor su cui si trova, così una selezione a cavallo di due monitor con scale
+    // diverse finisce esattamente sui pixel che l'utente ha visto.
+    pub fn logical_rect_to_physical(&self, rect: Rect) -> Option<PhysicalRect> {
+        let min = self.logical_to_physical(rect.min)?;
+        // Il bordo destro/inferiore è escluso: va convertito con il monitor del pixel appena prima
+        let inside_max = rect.max - egui::vec2(0.01, 0.01);
+        let max_monitor = self.monitor_at_logical(inside_max)?;
+        let max = max_monitor.logical_to_physical(rect.max);
+        Some(PhysicalRect::from_min_max(
+            (min.0.round() as i32, min.1.round() as i32),
+            (max.0.round() as i32, max.1.round() as i32),
+        ))
+    }
+
+    pub fn physical_rect_to_logical(&self, rect: &PhysicalRect) -> Option<Rect> {
+        let min = self.physical_to_logical(rect.x as f32, rect.y as f32)?;
+        let max_monitor = self.monitor_at_physical(rect.right() - 1, rect.bottom() - 1)?;
+        let max = max_monitor.physical_to_logical(rect.right() as f32, rect.bottom() as f32);
+        Some(Rect::from_min_max(min, max))
+    }
+}
+
+// La coordinata fisica di un monitor lungo un asse, dato il vicino già disposto (estremi logici e fisici).
+// Se il monitor sta tutto da una parte del vicino parte dal suo bordo fisico, più l'eventuale distanza;
+// altrimenti lo scostamento lungo il bordo in comune. Le distanze logiche si misurano con la scala del vicino.
+fn place_on_axis(logical: (f32, f32), neighbour: (f32, f32), neighbour_physical: (i32, i32), length: u32, scale: f32) -> i32 {
+    if logical.0 >= neighbour.1 {
+        neighbour_physical.1 + ((logical.0 - neighbour.1) * scale).round() as i32
+    } else if logical.1 <= neighbour.0 {
+        neighbour_physical.0 - length as i32 - ((neighbour.0 - logical.1) * scale).round() as i32
+    } else {
+        neighbour_physical.0 + ((logical.0 - neighbour.0) * scale).round() as i32
+    }
+}
+
+// Il quadrato della distanza tra due rettangoli (0 se si toccano o si sovrappongono)
+fn rect_distance_sq(a: &Rect, b: &Rect) -> f32 {
+    let dx = (b.min.x - a.max.x).max(a.min.x - b.max.x).max(0.0);
+    let dy = (b.min.y - a.max.y).max(a.min.y - b.max.y).max(0.0);
+    dx * dx + dy * dy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(id: u32, x: i32, y: i32, width: u32, height: u32, scale_factor: f32) -> Monitor {
+        Monitor {
+            id,
+            output: None,
+            x,
+            y,
+            width,
+            height,
+            scale_factor,
+            is_primary: id == 1,
+        }
+    }
+
+    fn physical(layout: &DesktopLayout, id: u32) -> PhysicalRect {
+        layout.monitors.iter().find(|m| m.monitor.id == id).unwrap().physical
+    }
+
+    #[test]
+    fn hidpi_monitor_on_the_right_starts_at_the_physical_edge() {
+        // Con l'origine logica scalata il secondo monitor partirebbe da 2880, lasciando un buco
+        let layout = DesktopLayout::new(&[monitor(1, 0, 0, 1920, 1080, 1.0), monitor(2, 1920, 0, 1280, 720, 1.5)]);
+        assert_eq!(physical(&layout, 1), PhysicalRect { x: 0, y: 0, width: 1920, height: 1080 });
+        assert_eq!(physical(&layout, 2), PhysicalRect { x: 1920, y: 0, width: 1920, height: 1080 });
+        assert_eq!(layout.bounds(), PhysicalRect { x: 0, y: 0, width: 3840, height: 1080 });
+    }
+
+    #[test]
+    fn lowdpi_monitor_next_to_a_hidpi_primary_does_not_overlap() {
+        // Con l'origine logica scalata il secondo monitor partirebbe da 1280, sopra al primo
+        let layout = DesktopLayout::new(&[monitor(1, 0, 0, 1280, 720, 2.0), monitor(2, 1280, 0, 1920, 1080, 1.0)]);
+        assert_eq!(physical(&layout, 1), PhysicalRect { x: 0, y: 0, width: 2560, height: 1440 });
+        assert_eq!(physical(&layout, 2), PhysicalRect { x: 2560, y: 0, width: 1920, height: 1080 });
+        assert!(physical(&layout, 1).intersect(&physical(&layout, 2)).is_none());
+    }
+
+    #[test]
+    fn monitors_with_negative_origins() {
+        let layout = DesktopLayout::new(&[
+            monitor(1, 0, 0, 1920, 1080, 1.0),
+            monitor(2, -1280, 0, 1280, 720, 1.5),
+            monitor(3, 320, -720, 1280, 720, 1.25),
+        ]);
+        assert_eq!(physical(&layout, 2), PhysicalRect { x: -1920, y: 0, width: 1920, height: 1080 });
+        assert_eq!(physical(&layout, 3), PhysicalRect { x: 320, y: -900, width: 1600, height: 900 });
+        assert_eq!(layout.bounds(), PhysicalRect { x: -1920, y: -900, width: 3840, height: 1980 });
+        for (i, a) in layout.monitors.iter().enumerate() {
+            for b in layout.monitors[i + 1..].iter() {
+                assert!(a.physical.intersect(&b.physical).is_none(), "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn chain_of_monitors_is_placed_through_the_neighbours() {
+        // Il terzo tocca solo il secondo: va attaccato al suo bordo fisico, non a quello del primo
+        let layout = DesktopLayout::new(&[
+            monitor(3, 3200, 0, 1920, 1080, 1.0),
+            monitor(1, 0, 0, 1920, 1080, 1.0),
+            monitor(2, 1920, 0, 1280, 720, 1.5),
+        ]);
+        let ids: Vec<u32> = layout.monitors.iter().map(|m| m.monitor.id).collect();
+        assert_eq!(ids, vec![3, 1, 2]); // Lo stesso ordine dei monitor passati
+        assert_eq!(physical(&layout, 2).x, 1920);
+        assert_eq!(physical(&layout, 3).x, 3840);
+    }
+
+    #[test]
+    fn fractional_scale_is_rounded() {
+        let layout = DesktopLayout::new(&[monitor(1, 0, 0, 1536, 864, 1.25)]);
+        assert_eq!(physical(&layout, 1), PhysicalRect { x: 0, y: 0, width: 1920, height: 1080 });
+    }
+
+    #[test]
+    fn selection_across_mixed_scales() {
+        let layout = DesktopLayout::new(&[monitor(1, 0, 0, 1920, 1080, 1.0), monitor(2, 1920, 0, 1280, 720, 1.5)]);
+        let rect = Rect::from_min_max(Pos2::new(1900., 10.), Pos2::new(1940., 30.));
+        // L'angolo in basso a destra è sul secondo monitor: 20 punti a scala 1.5 dopo il bordo
+        assert_eq!(
+            layout.logical_rect_to_physical(rect),
+            Some(PhysicalRect { x: 1900, y: 10, width: 50, height: 35 })
+        );
+        let point = layout.physical_to_logical(1950., 45.).unwrap();
+        assert!((point - Pos2::new(1940., 30.)).length() < 0.001);
+    }
+
+    #[test]
+    fn selection_on_a_negative_monitor_round_trips() {
+        let layout = DesktopLayout::new(&[monitor(1, 0, 0, 1920, 1080, 1.0), monitor(2, -1280, 0, 1280, 720, 1.5)]);
+        let rect = Rect::from_min_max(Pos2::new(-1000., 100.), Pos2::new(-200., 600.));
+        let physical = layout.logical_rect_to_physical(rect).unwrap();
+        assert_eq!(physical, PhysicalRect { x: -1500, y: 150, width: 1200, height: 750 });
+        assert_eq!(layout.physical_rect_to_logical(&physical), Some(rect));
+    }
+}
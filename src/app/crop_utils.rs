@@ -53,10 +53,10 @@ impl Crop {
         // self.offset_y_down = 0.0;
         // self.limit_reached = false;
 
-        self.cut_rect.min.x = response.rect.min.x + (response.rect.width() * self.offset_x_left) as f32;
-        self.cut_rect.min.y = response.rect.min.y + (response.rect.height() * self.offset_y_up) as f32;
-        self.cut_rect.max.x = response.rect.max.x - (response.rect.width() * self.offset_x_right) as f32;
-        self.cut_rect.max.y = response.rect.max.y - (response.rect.height() * self.offset_y_down) as f32;
+        self.cut_rect.min.x = response.rect.min.x + response.rect.width() * self.offset_x_left;
+        self.cut_rect.min.y = response.rect.min.y + response.rect.height() * self.offset_y_up;
+        self.cut_rect.max.x = response.rect.max.x - response.rect.width() * self.offset_x_right;
+        self.cut_rect.max.y = response.rect.max.y - response.rect.height() * self.offset_y_down;
 
 
         if self.cut_rect.is_positive() {
@@ -67,8 +67,8 @@ impl Crop {
                 rounding: Rounding::none()
             }));
 
-            let xr = response.rect.min.x + (response.rect.width() * self.offset_x_left) as f32;
-            let yr = response.rect.min.y + (response.rect.height() * self.offset_y_up) as f32;
+            let xr = response.rect.min.x + response.rect.width() * self.offset_x_left;
+            let yr = response.rect.min.y + response.rect.height() * self.offset_y_up;
 
             let xthird = self.cut_rect.size().x / 3.0;
             let ythird = self.cut_rect.size().y / 3.0;
@@ -139,7 +139,7 @@ impl Crop {
                     Side::Up => {
                         if p_interact != self.last_click {
                             let tmp = compute_offset(response.rect.min, p_interact).y / response.rect.height();
-                            if (self.cut_rect.max.y - self.cut_rect.min.y) >= 50. || (self.limit_reached == true && tmp < self.offset_y_up) {
+                            if (self.cut_rect.max.y - self.cut_rect.min.y) >= 50. || (self.limit_reached && tmp < self.offset_y_up) {
                                 self.offset_y_up = tmp;
                                 self.limit_reached = false;
                             } else {
@@ -152,7 +152,7 @@ impl Crop {
                         if p_interact != self.last_click {
                             let tmp = compute_offset(response.rect.max, p_interact).y / response.rect.height();
 
-                            if (self.cut_rect.max.y - self.cut_rect.min.y) >= 50. || (self.limit_reached == true && tmp < self.offset_y_down) {
+                            if (self.cut_rect.max.y - self.cut_rect.min.y) >= 50. || (self.limit_reached && tmp < self.offset_y_down) {
                                 self.offset_y_down = tmp;
                                 self.limit_reached = false;
                             } else {
@@ -165,7 +165,7 @@ impl Crop {
                         if p_interact != self.last_click {
                             //println!("click destra");
                             let tmp = compute_offset(response.rect.max, p_interact).x / response.rect.width();
-                            if (self.cut_rect.max.x - self.cut_rect.min.x) >= 50. || (self.limit_reached == true && tmp < self.offset_x_right) {
+                            if (self.cut_rect.max.x - self.cut_rect.min.x) >= 50. || (self.limit_reached && tmp < self.offset_x_right) {
                                 self.offset_x_right = tmp;
                                 self.limit_reached = false;
                             } else {
@@ -177,7 +177,7 @@ impl Crop {
                     Side::Left => {
                         if p_interact != self.last_click {
                             let tmp = compute_offset(response.rect.min, p_interact).x / response.rect.width();
-                            if (self.cut_rect.max.x - self.cut_rect.min.x) >= 50. || (self.limit_reached == true && tmp < self.offset_x_left) {
+                            if (self.cut_rect.max.x - self.cut_rect.min.x) >= 50. || (self.limit_reached && tmp < self.offset_x_left) {
                                 self.offset_x_left = tmp;
                                 self.limit_reached = false;
                             } else {
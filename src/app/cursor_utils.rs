@@ -0,0 +1,245 @@
+use super::coords_utils::PhysicalRect;
+use egui::{Color32, ComboBox, DragValue, Ui};
+use image::{Rgba, RgbaImage};
+
+// Cosa mostrare del puntatore negli screenshot
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CursorMode {
+    #[default]
+    Hidden,
+    Cursor,                                             // L'immagine vera del cursore, sul suo hotspot
+    Ring { color: Color32, radius: f32, width: f32 },   // Un anello intorno al punto in cui si trova il puntatore
+}
+
+impl CursorMode {
+    pub fn default_ring() -> Self {
+        CursorMode::Ring {
+            color: Color32::from_rgba_unmultiplied(255, 200, 0, 200),
+            radius: 24.0,
+            width: 4.0,
+        }
+    }
+}
+
+// Il cursore in un certo momento, in pixel fisici del desktop virtuale
+#[derive(Clone, Debug, PartialEq)]
+pub struct CursorImage {
+    pub x: i32, // Dove si trova il puntatore
+    pub y: i32,
+    pub hot_x: i32, // Il punto dell'immagine che corrisponde al puntatore
+    pub hot_y: i32,
+    pub image: RgbaImage,
+}
+
+// Legge il cursore. Su X11 tramite l'estensione XFixes, sugli altri sistemi non è supportato.
+// La connessione al server si apre alla prima lettura e resta aperta: un job di cattura o una registrazione
+// ne usano una sola per tutti i loro fotogrammi.
+#[derive(Default)]
+pub struct CursorReader {
+    #[cfg(target_os = "linux")]
+    connection: Option<x11rb::rust_connection::RustConnection>,
+}
+
+impl CursorReader {
+    pub fn read(&mut self) -> Option<CursorImage> {
+        #[cfg(target_os = "linux")]
+        {
+            if self.connection.is_none() {
+                match x11::connect() {
+                    Ok(connection) => self.connection = Some(connection),
+                    Err(e) => {
+                        println!("Could not read the cursor: {}", e);
+                        return None;
+                    }
+                }
+            }
+            match x11::current_cursor(self.connection.as_ref()?) {
+                Ok(cursor) => Some(cursor),
+                Err(e) => {
+                    // La prossima lettura riprova con una connessione nuova
+                    println!("Could not read the cursor: {}", e);
+                    self.connection = None;
+                    None
+                }
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            println!("Including the cursor is only supported on X11");
+            None
+        }
+    }
+}
+
+// Disegna il cursore (o l'anello) sull'immagine, che copre region del desktop. Se il puntatore non è lì non fa niente.
+pub fn composite_cursor(image: &mut RgbaImage, region: &PhysicalRect, cursor: &CursorImage, mode: &CursorMode) {
+    let (x, y) = (cursor.x - region.x, cursor.y - region.y);
+    match *mode {
+        CursorMode::Hidden => {}
+        CursorMode::Cursor => blend_image(image, &cursor.image, x - cursor.hot_x, y - cursor.hot_y),
+        CursorMode::Ring { color, radius, width } => draw_ring(image, x as f32 + 0.5, y as f32 + 0.5, radius, width, color),
+    }
+}
+
+// Source-over di src su dst, con l'angolo in alto a sinistra in (left, top). I pixel fuori da dst vengono ignorati.
+fn blend_image(dst: &mut RgbaImage, src: &RgbaImage, left: i32, top: i32) {
+    for (sx, sy, pixel) in src.enumerate_pixels() {
+        let (dx, dy) = (left + sx as i32, top + sy as i32);
+        if dx >= 0 && dy >= 0 && (dx as u32) < dst.width() && (dy as u32) < dst.height() {
+            blend_pixel(dst.get_pixel_mut(dx as u32, dy as u32), *pixel, 1.0);
+        }
+    }
+}
+
+// Un anello antialiasato: la copertura di ogni pixel dipende da quanto dista dal bordo dell'anello
+fn draw_ring(dst: &mut RgbaImage, cx: f32, cy: f32, radius: f32, width: f32, color: Color32) {
+    let outer = radius + width / 2.0 + 1.0;
+    let min_x = (cx - outer).floor().max(0.0) as u32;
+    let min_y = (cy - outer).floor().max(0.0) as u32;
+    let max_x = ((cx + outer).ceil().max(0.0) as u32).min(dst.width());
+    let max_y = ((cy + outer).ceil().max(0.0) as u32).min(dst.height());
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let distance = ((x as f32 + 0.5 - cx).powi(2) + (y as f32 + 0.5 - cy).powi(2)).sqrt();
+            let coverage = (width / 2.0 + 0.5 - (distance - radius).abs()).clamp(0.0, 1.0);
+            if coverage > 0.0 {
+                blend_pixel(dst.get_pixel_mut(x, y), Rgba([r, g, b, a]), coverage);
+            }
+        }
+    }
+}
+
+// Source-over di un colore non premoltiplicato, con l'alpha moltiplicato per coverage
+pub fn blend_pixel(dst: &mut Rgba<u8>, src: Rgba<u8>, coverage: f32) {
+    let src_a = src[3] as f32 / 255.0 * coverage;
+    if src_a <= 0.0 {
+        return;
+    }
+    let dst_a = dst[3] as f32 / 255.0;
+    let out_a = src_a + dst_a * (1.0 - src_a);
+    for i in 0..3 {
+        let c = (src[i] as f32 * src_a + dst[i] as f32 * dst_a * (1.0 - src_a)) / out_a;
+        dst[i] = c.round().clamp(0.0, 255.0) as u8;
+    }
+    dst[3] = (out_a * 255.0).round() as u8;
+}
+
+// Impostazioni del cursore per la settings view
+pub fn ui_settings(ui: &mut Ui, mode: &mut CursorMode) {
+    ui.horizontal(|ui| {
+        ui.label("Mouse cursor in screenshots:");
+        let selected = match mode {
+            CursorMode::Hidden => "Hidden",
+            CursorMode::Cursor => "Cursor",
+            CursorMode::Ring { .. } => "Highlight ring",
+        };
+        ComboBox::from_id_source("cursor_mode")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(mode, CursorMode::Hidden, "Hidden");
+                ui.selectable_value(mode, CursorMode::Cursor, "Cursor");
+                if ui.selectable_label(matches!(mode, CursorMode::Ring { .. }), "Highlight ring").clicked()
+                    && !matches!(mode, CursorMode::Ring { .. })
+                {
+                    *mode = CursorMode::default_ring();
+                }
+            });
+        if let CursorMode::Ring { color, radius, width } = mode {
+            ui.color_edit_button_srgba(color);
+            ui.add(DragValue::new(radius).clamp_range(4.0..=200.0).prefix("Radius: ").suffix(" px"));
+            ui.add(DragValue::new(width).clamp_range(1.0..=50.0).prefix("Width: ").suffix(" px"));
+        }
+        if cfg!(not(target_os = "linux")) && *mode != CursorMode::Hidden {
+            ui.colored_label(Color32::LIGHT_RED, "X11 only");
+        }
+    });
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use super::CursorImage;
+    use image::RgbaImage;
+    use std::error::Error;
+    use x11rb::protocol::xfixes::ConnectionExt as XFixesExt;
+    use x11rb::rust_connection::RustConnection;
+
+    pub fn connect() -> Result<RustConnection, Box<dyn Error>> {
+        let (conn, _) = x11rb::connect(None)?;
+        // XFixes va "presentata" al server prima di usarla
+        conn.xfixes_query_version(4, 0)?.reply()?;
+        Ok(conn)
+    }
+
+    pub fn current_cursor(conn: &RustConnection) -> Result<CursorImage, Box<dyn Error>> {
+        let reply = conn.xfixes_get_cursor_image()?.reply()?;
+
+        // I pixel sono ARGB premoltiplicati, uno per u32
+        let mut image = RgbaImage::new(reply.width as u32, reply.height as u32);
+        for (pixel, argb) in image.pixels_mut().zip(reply.cursor_image.iter()) {
+            let a = (argb >> 24) as u8;
+            let unpremultiply = |c: u32| {
+                let c = (c & 0xff) as u16;
+                if a == 0 { 0 } else { ((c * 255 + a as u16 / 2) / a as u16).min(255) as u8 }
+            };
+            *pixel = image::Rgba([unpremultiply(argb >> 16), unpremultiply(argb >> 8), unpremultiply(*argb), a]);
+        }
+        Ok(CursorImage {
+            x: reply.x as i32,
+            y: reply.y as i32,
+            hot_x: reply.xhot as i32,
+            hot_y: reply.yhot as i32,
+            image,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Un cursore 3×3 rosso, con l'hotspot al centro, in (110, 220) sul desktop
+    fn cursor() -> CursorImage {
+        CursorImage {
+            x: 110,
+            y: 220,
+            hot_x: 1,
+            hot_y: 1,
+            image: RgbaImage::from_pixel(3, 3, Rgba([255, 0, 0, 255])),
+        }
+    }
+
+    #[test]
+    fn cursor_is_drawn_on_its_hotspot_relative_to_the_region() {
+        let region = PhysicalRect { x: 100, y: 200, width: 40, height: 40 };
+        let mut image = RgbaImage::from_pixel(40, 40, Rgba([0, 0, 0, 255]));
+        composite_cursor(&mut image, &region, &cursor(), &CursorMode::Cursor);
+        assert_eq!(*image.get_pixel(9, 19), Rgba([255, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(11, 21), Rgba([255, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(12, 20), Rgba([0, 0, 0, 255]));
+        assert_eq!(image.pixels().filter(|p| p[0] == 255).count(), 9);
+    }
+
+    #[test]
+    fn cursor_outside_the_region_and_hidden_mode_leave_the_image_alone() {
+        let untouched = RgbaImage::from_pixel(40, 40, Rgba([0, 0, 0, 255]));
+        let mut image = untouched.clone();
+        let elsewhere = PhysicalRect { x: 500, y: 500, width: 40, height: 40 };
+        composite_cursor(&mut image, &elsewhere, &cursor(), &CursorMode::Cursor);
+        assert_eq!(image, untouched);
+        let region = PhysicalRect { x: 100, y: 200, width: 40, height: 40 };
+        composite_cursor(&mut image, &region, &cursor(), &CursorMode::Hidden);
+        assert_eq!(image, untouched);
+    }
+
+    #[test]
+    fn ring_is_centered_on_the_pointer() {
+        let region = PhysicalRect { x: 100, y: 200, width: 40, height: 40 };
+        let mut image = RgbaImage::from_pixel(40, 40, Rgba([0, 0, 0, 255]));
+        let ring = CursorMode::Ring { color: Color32::WHITE, radius: 8.0, width: 2.0 };
+        composite_cursor(&mut image, &region, &cursor(), &ring);
+        assert_eq!(*image.get_pixel(10, 20), Rgba([0, 0, 0, 255])); // Il centro resta scoperto
+        assert_eq!(*image.get_pixel(18, 20), Rgba([255, 255, 255, 255]));
+        assert_eq!(*image.get_pixel(10, 12), Rgba([255, 255, 255, 255]));
+    }
+}
@@ -0,0 +1,315 @@
+use super::shape_utils::DrawObj;
+use image::{imageops, RgbaImage};
+
+// La cronologia delle modifiche al disegno. Ogni comando sa sia rifarsi che annullarsi, così si può
+// tornare a qualsiasi stato precedente. I comandi si registrano dopo averli applicati.
+
+#[derive(Clone)]
+enum Edit {
+    Add(usize, DrawObj),
+    Delete(Vec<(usize, DrawObj)>), // In ordine crescente di indice
+    Modify { before: Vec<(usize, DrawObj)>, after: Vec<(usize, DrawObj)> },
+    Clear(Vec<DrawObj>),
+    // Ritaglio e rotazione cambiano l'immagine e spostano tutte le forme. L'immagine dopo non si tiene:
+    // si ricalcola rifacendo la modifica. Quella prima serve solo al ritaglio, la rotazione si annulla girando all'indietro.
+    Image {
+        change: ImageChange,
+        original: Option<RgbaImage>,
+        before: Vec<DrawObj>,
+        after: Vec<DrawObj>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageChange {
+    Crop { x: u32, y: u32, width: u32, height: u32 },
+    Rotate { clockwise: bool },
+}
+
+impl ImageChange {
+    pub fn apply(&self, image: &RgbaImage) -> RgbaImage {
+        match *self {
+            ImageChange::Crop { x, y, width, height } => imageops::crop_imm(image, x, y, width, height).to_image(),
+            ImageChange::Rotate { clockwise: true } => imageops::rotate90(image),
+            ImageChange::Rotate { clockwise: false } => imageops::rotate270(image),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Command {
+    pub label: String, // Come appare nel pannello della cronologia
+    edit: Edit,
+}
+
+impl Command {
+    pub fn add(index: usize, shape: DrawObj) -> Self {
+        let label = format!("Add {}", shape.shape.name());
+        Self { label, edit: Edit::Add(index, shape) }
+    }
+
+    pub fn delete(shapes: Vec<(usize, DrawObj)>) -> Self {
+        let label = format!("Delete {}", count(shapes.len()));
+        Self { label, edit: Edit::Delete(shapes) }
+    }
+
+    // Move, resize, cambio di tratto o colore: le stesse forme prima e dopo
+    pub fn modify(label: &str, before: Vec<(usize, DrawObj)>, after: Vec<(usize, DrawObj)>) -> Self {
+        let label = format!("{} {}", label, count(before.len()));
+        Self { label, edit: Edit::Modify { before, after } }
+    }
+
+    pub fn clear(shapes: Vec<DrawObj>) -> Self {
+        Self { label: "Clear".to_string(), edit: Edit::Clear(shapes) }
+    }
+
+    // original è l'immagine prima del ritaglio, before e after le forme
+    pub fn crop(crop: ImageChange, original: RgbaImage, before: Vec<DrawObj>, after: Vec<DrawObj>) -> Self {
+        let edit = Edit::Image { change: crop, original: Some(original), before, after };
+        Self { label: "Crop".to_string(), edit }
+    }
+
+    pub fn rotate(clockwise: bool, before: Vec<DrawObj>, after: Vec<DrawObj>) -> Self {
+        let label = if clockwise { "Rotate clockwise" } else { "Rotate counterclockwise" };
+        let edit = Edit::Image { change: ImageChange::Rotate { clockwise }, original: None, before, after };
+        Self { label: label.to_string(), edit }
+    }
+
+    // Ritorna true se è cambiata l'immagine, non solo le forme
+    fn apply(&self, shapes: &mut Vec<DrawObj>, image: &mut RgbaImage) -> bool {
+        match &self.edit {
+            Edit::Add(index, shape) => shapes.insert(*index, shape.clone()),
+            Edit::Delete(deleted) => {
+                for (index, _) in deleted.iter().rev() {
+                    shapes.remove(*index);
+                }
+            }
+            Edit::Modify { after, .. } => replace(shapes, after),
+            Edit::Clear(_) => shapes.clear(),
+            Edit::Image { change, after, .. } => {
+                *image = change.apply(image);
+                *shapes = after.clone();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn revert(&self, shapes: &mut Vec<DrawObj>, image: &mut RgbaImage) -> bool {
+        match &self.edit {
+            Edit::Add(index, _) => {
+                shapes.remove(*index);
+            }
+            Edit::Delete(deleted) => {
+                for (index, shape) in deleted.iter() {
+                    shapes.insert(*index, shape.clone());
+                }
+            }
+            Edit::Modify { before, .. } => replace(shapes, before),
+            Edit::Clear(cleared) => *shapes = cleared.clone(),
+            Edit::Image { change, original, before, .. } => {
+                *image = match (change, original) {
+                    (_, Some(original)) => original.clone(),
+                    (ImageChange::Rotate { clockwise }, None) => ImageChange::Rotate { clockwise: !clockwise }.apply(image),
+                    (ImageChange::Crop { .. }, None) => unreachable!("a crop keeps the original image"),
+                };
+                *shapes = before.clone();
+                return true;
+            }
+        }
+        false
+    }
+
+    // Due modifiche di fila con la stessa etichetta sulle stesse forme diventano una sola
+    // (es. trascinando il valore dello spessore). Quando farlo lo decide History::record_merging.
+    fn merge(&mut self, next: &Command) -> bool {
+        match (&mut self.edit, &next.edit) {
+            (Edit::Modify { before, after }, Edit::Modify { before: next_before, after: next_after })
+                if self.label == next.label
+                    && before.iter().map(|(i, _)| i).eq(next_before.iter().map(|(i, _)| i)) =>
+            {
+                *after = next_after.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct History {
+    done: Vec<Command>,
+    undone: Vec<Command>, // L'ultimo annullato è in fondo
+    interaction: Option<egui::Id>, // Il widget che ha prodotto l'ultimo comando, finché è ancora in uso
+}
+
+impl History {
+    pub fn record(&mut self, command: Command) {
+        self.done.push(command);
+        self.undone.clear();
+        self.interaction = None;
+    }
+
+    // Come record, ma unisce il comando al precedente se viene dallo stesso widget mentre è ancora in uso
+    // (lo stesso trascinamento, lo stesso popup del colore) e modifica le stesse forme allo stesso modo
+    pub fn record_merging(&mut self, command: Command, interaction: egui::Id) {
+        let merged = self.interaction == Some(interaction)
+            && self.undone.is_empty()
+            && self.done.last_mut().is_some_and(|last| last.merge(&command));
+        if !merged {
+            self.record(command);
+        }
+        self.interaction = Some(interaction);
+    }
+
+    // Il widget è stato rilasciato: il prossimo comando non si unisce più al precedente
+    pub fn end_interaction(&mut self) {
+        self.interaction = None;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    // Come apply e revert: ritornano true se è cambiata l'immagine
+    pub fn undo(&mut self, shapes: &mut Vec<DrawObj>, image: &mut RgbaImage) -> bool {
+        let Some(command) = self.done.pop() else {
+            return false;
+        };
+        let image_changed = command.revert(shapes, image);
+        self.undone.push(command);
+        self.interaction = None;
+        image_changed
+    }
+
+    pub fn redo(&mut self, shapes: &mut Vec<DrawObj>, image: &mut RgbaImage) -> bool {
+        let Some(command) = self.undone.pop() else {
+            return false;
+        };
+        let image_changed = command.apply(shapes, image);
+        self.done.push(command);
+        self.interaction = None;
+        image_changed
+    }
+
+    // Porta il disegno allo stato dopo i primi position comandi (0 è lo screenshot senza modifiche)
+    pub fn jump_to(&mut self, position: usize, shapes: &mut Vec<DrawObj>, image: &mut RgbaImage) -> bool {
+        let mut image_changed = false;
+        while self.done.len() > position && self.can_undo() {
+            image_changed |= self.undo(shapes, image);
+        }
+        while self.done.len() < position && self.can_redo() {
+            image_changed |= self.redo(shapes, image);
+        }
+        image_changed
+    }
+
+    // L'elenco dei comandi, dal più vecchio: quelli annullati sono in grigio. Ritorna lo stato scelto.
+    pub fn ui(&self, ui: &mut egui::Ui) -> Option<usize> {
+        let mut jump = None;
+        let current = self.done.len();
+        egui::ScrollArea::vertical().max_height(300.).show(ui, |ui| {
+            let labels = std::iter::once("Screenshot")
+                .chain(self.done.iter().map(|c| c.label.as_str()))
+                .chain(self.undone.iter().rev().map(|c| c.label.as_str()));
+            for (position, label) in labels.enumerate() {
+                let text = if position > current {
+                    egui::RichText::new(label).weak()
+                } else {
+                    egui::RichText::new(label)
+                };
+                if ui.selectable_label(position == current, text).clicked() {
+                    jump = Some(position);
+                }
+            }
+        });
+        jump
+    }
+}
+
+fn replace(shapes: &mut [DrawObj], changed: &[(usize, DrawObj)]) {
+    for (index, shape) in changed {
+        shapes[*index] = shape.clone();
+    }
+}
+
+fn count(shapes: usize) -> String {
+    if shapes == 1 {
+        "shape".to_string()
+    } else {
+        format!("{} shapes", shapes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::shape_utils::Shape;
+    use egui::{pos2, Color32, Id, Stroke};
+
+    fn line(width: f32) -> DrawObj {
+        let shape = Shape::Line { from: pos2(1., 1.), to: pos2(3., 2.) };
+        DrawObj::new(shape, Stroke::new(width, Color32::RED), None)
+    }
+
+    fn restyle(from: f32, to: f32) -> Command {
+        Command::modify("Restyle", vec![(0, line(from))], vec![(0, line(to))])
+    }
+
+    fn image() -> RgbaImage {
+        RgbaImage::from_fn(4, 3, |x, y| image::Rgba([x as u8, y as u8, 0, 255]))
+    }
+
+    #[test]
+    fn one_drag_is_one_command() {
+        let mut history = History::default();
+        let width = Id::new("width");
+        for step in 1..5 {
+            history.record_merging(restyle(step as f32, step as f32 + 1.), width);
+        }
+        assert_eq!(history.done.len(), 1);
+
+        let (mut shapes, mut image) = (vec![line(5.)], image());
+        history.undo(&mut shapes, &mut image);
+        assert_eq!(shapes[0].stroke.width, 1.);
+    }
+
+    #[test]
+    fn separate_interactions_are_not_merged() {
+        let mut history = History::default();
+        let (width, color) = (Id::new("width"), Id::new("color"));
+        history.record_merging(restyle(1., 2.), width);
+        history.end_interaction();
+        history.record_merging(restyle(2., 3.), width);
+        history.record_merging(restyle(3., 4.), color);
+        assert_eq!(history.done.len(), 3);
+    }
+
+    #[test]
+    fn crop_and_rotate_round_trip() {
+        let original = image();
+        let (mut shapes, mut image) = (vec![line(1.)], original.clone());
+        let mut history = History::default();
+
+        let crop = ImageChange::Crop { x: 1, y: 1, width: 2, height: 2 };
+        image = crop.apply(&image);
+        history.record(Command::crop(crop, original.clone(), vec![line(1.)], vec![line(2.)]));
+        let cropped = image.clone();
+        image = ImageChange::Rotate { clockwise: true }.apply(&image);
+        history.record(Command::rotate(true, vec![line(2.)], vec![line(3.)]));
+        let rotated = image.clone();
+
+        assert!(history.jump_to(0, &mut shapes, &mut image));
+        assert_eq!((image.clone(), shapes[0].stroke.width), (original, 1.));
+        assert!(history.redo(&mut shapes, &mut image));
+        assert_eq!((image.clone(), shapes[0].stroke.width), (cropped.clone(), 2.));
+        assert!(history.redo(&mut shapes, &mut image));
+        assert_eq!((image.clone(), shapes[0].stroke.width), (rotated, 3.));
+        assert!(history.undo(&mut shapes, &mut image));
+        assert_eq!(image, cropped);
+    }
+}
@@ -13,6 +13,7 @@ pub struct AllKeyboardShortcuts {
     pub copy_to_clipboard: Option<KeyboardShortcut>,
     pub test: Option<KeyboardShortcut>,
     pub take_screenshot: Option<KeyboardShortcut>,
+    pub capture_previous_region: Option<KeyboardShortcut>,
 }
 
 impl Default for AllKeyboardShortcuts {
@@ -22,6 +23,7 @@ impl Default for AllKeyboardShortcuts {
             copy_to_clipboard: Some(KeyboardShortcut::new(Modifiers::CTRL, Key::C)),
             test: Some(KeyboardShortcut::new(Modifiers::CTRL, Key::T)),
             take_screenshot: Some(KeyboardShortcut::new(Modifiers::CTRL, Key::D)),
+            capture_previous_region: Some(KeyboardShortcut::new(Modifiers::CTRL, Key::R)),
         }
     }
 }
@@ -34,6 +36,7 @@ impl AllKeyboardShortcuts {
             "copy_to_clipboard" => self.copy_to_clipboard = Some(new_shortcut),
             "test" => self.test = Some(new_shortcut),
             "take_screenshot" => self.take_screenshot = Some(new_shortcut),
+            "capture_previous_region" => self.capture_previous_region = Some(new_shortcut),
             _ => panic!("Invalid field name"),
         };
     }
@@ -49,9 +52,11 @@ impl AllKeyboardShortcuts {
             return (false, "test".to_string());
         } else if shortcut.eq(self.take_screenshot.as_ref().unwrap()) {
             return (false, "take_screenshot".to_string());
+        } else if shortcut.eq(self.capture_previous_region.as_ref().unwrap()) {
+            return (false, "capture_previous_region".to_string());
         }
 
-        return (true, "none".to_string());
+        (true, "none".to_string())
     }
 
     pub fn human_readable_shorcut(&self, field: &str) -> String {
@@ -61,6 +66,7 @@ impl AllKeyboardShortcuts {
             "copy_to_clipboard" => self.copy_to_clipboard,
             "test" => self.test,
             "take_screenshot" => self.take_screenshot,
+            "capture_previous_region" => self.capture_previous_region,
             _ => panic!("Invalid field name"),
         };
 
@@ -80,10 +86,10 @@ impl AllKeyboardShortcuts {
 
         readable_shortcut.push_str(shortcut.unwrap().key.name());
 
-        return readable_shortcut;
+        readable_shortcut
     }
 
-    pub fn from_name(self, key: &str) -> Key {
+    pub fn key_from_name(&self, key: &str) -> Key {
         
         let mut_key = key.to_ascii_uppercase();
 
@@ -9,6 +9,6 @@ pub fn load_image_from_memory(image_data: RgbaImage) -> ImageData {
         [image_data.width() as _, image_data.height() as _],
         image_data.as_flat_samples().as_slice(),
     );
-    return ImageData::from(color_image);   
+    ImageData::from(color_image)
 }
 
@@ -0,0 +1,197 @@
+use super::capture_job::CaptureJob;
+use super::capture_utils::{CaptureBackend, CaptureError};
+use super::cursor_utils::CursorMode;
+use super::save_utils::{self, SavePath};
+use super::screenshot_utils::CaptureTarget;
+use egui::*;
+use image::RgbaImage;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONTROL_WINDOW_SIZE: Vec2 = vec2(420.0, 48.0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntervalOptions {
+    pub every_secs: u32,
+    pub max_shots: u32,       // 0 = finché l'utente non ferma
+    pub skip_identical: bool, // Non salvare gli scatti uguali al precedente
+}
+
+impl Default for IntervalOptions {
+    fn default() -> Self {
+        Self {
+            every_secs: 10,
+            max_shots: 0,
+            skip_identical: true,
+        }
+    }
+}
+
+// Quello che il thread di salvataggio manda indietro per ogni scatto: dove l'ha scritto, o perché non ci è riuscito
+type SaveResult = Result<PathBuf, String>;
+
+pub enum IntervalAction {
+    None,
+    Stop,
+}
+
+// Una serie di screenshot a intervalli regolari. Ogni scatto è un CaptureJob, che nasconde la finestra
+// durante la cattura come per uno screenshot normale; tra uno scatto e l'altro resta una piccola barra di controllo.
+// Il primo scatto arriva dopo il timer della maschera, i successivi ogni every_secs secondi.
+// La codifica e la scrittura di ogni scatto avvengono in un altro thread, come il salvataggio delle registrazioni:
+// la serie finisce solo quando sono stati scritti tutti.
+pub struct IntervalCapture {
+    target: CaptureTarget,
+    options: IntervalOptions,
+    skip_failed_monitors: bool,
+    cursor: CursorMode,
+    save_path: SavePath, // Cartella, formato e nome di base: ogni scatto aggiunge il suo numero
+    job: CaptureJob,
+    next_fire: Instant,
+    pub saved: u32,
+    pub skipped: u32,
+    pub last: Option<RgbaImage>,
+    saves: (Sender<SaveResult>, Receiver<SaveResult>),
+    pending: u32,   // Scatti ancora da scrivere
+    stopping: bool, // Niente più scatti, si aspetta solo che finiscano i salvataggi
+}
+
+impl IntervalCapture {
+    pub fn new(
+        target: CaptureTarget,
+        delay: Duration,
+        options: IntervalOptions,
+        skip_failed_monitors: bool,
+        cursor: CursorMode,
+        save_path: SavePath,
+    ) -> Self {
+        Self {
+            job: CaptureJob::new(target.clone(), delay, skip_failed_monitors, cursor),
+            next_fire: Instant::now() + delay,
+            target,
+            options,
+            skip_failed_monitors,
+            cursor,
+            save_path,
+            saved: 0,
+            skipped: 0,
+            last: None,
+            saves: mpsc::channel(),
+            pending: 0,
+            stopping: false,
+        }
+    }
+
+    // Ferma la serie: lo scatto in attesa non viene fatto, quelli già fatti finiscono di essere salvati
+    pub fn stop(&mut self) {
+        self.stopping = true;
+    }
+
+    pub fn shots(&self) -> u32 {
+        self.saved + self.skipped
+    }
+
+    pub fn prepare_control_window(&self, _frame: &mut eframe::Frame) {
+        _frame.set_decorations(false);
+        _frame.set_window_size(CONTROL_WINDOW_SIZE);
+        _frame.set_window_pos(pos2(20.0, 20.0));
+        _frame.set_visible(true);
+    }
+
+    // Va chiamata ad ogni update. Restituisce Some quando la serie è finita: Ok se sono stati fatti
+    // e salvati tutti gli scatti, Err se una cattura o un salvataggio sono falliti.
+    pub fn poll(
+        &mut self,
+        ctx: &Context,
+        _frame: &mut eframe::Frame,
+        backend: &Arc<dyn CaptureBackend>,
+    ) -> Option<Result<(), String>> {
+        while let Ok(saved) = self.saves.1.try_recv() {
+            self.pending -= 1;
+            if let Err(e) = saved {
+                return Some(Err(format!("Could not save a shot: {}", e)));
+            }
+        }
+        if self.stopping {
+            return (self.pending == 0).then_some(Ok(()));
+        }
+
+        self.job.poll(ctx, _frame, backend);
+        if !self.job.is_finished() {
+            return None;
+        }
+
+        let next_job = CaptureJob::new(self.target.clone(), Duration::ZERO, self.skip_failed_monitors, self.cursor);
+        let result = std::mem::replace(&mut self.job, next_job)
+            .into_result()
+            .unwrap_or(Err(CaptureError::Interrupted));
+        match result {
+            Ok(capture) => self.keep(ctx, capture.image),
+            Err(e) => return Some(Err(e.to_string())),
+        }
+        if self.options.max_shots > 0 && self.shots() >= self.options.max_shots {
+            self.stopping = true;
+            self.prepare_control_window(_frame);
+            return None;
+        }
+
+        // Il prossimo scatto è a intervalli fissi dal primo, anche se la cattura ha richiesto un po' di tempo
+        self.next_fire += Duration::from_secs(self.options.every_secs.max(1) as u64);
+        let delay = self.next_fire.saturating_duration_since(Instant::now());
+        self.job = CaptureJob::new(self.target.clone(), delay, self.skip_failed_monitors, self.cursor);
+        self.prepare_control_window(_frame);
+        None
+    }
+
+    // Gli scatti uguali al precedente sono contati in skipped, e la barra di controllo li mostra
+    fn keep(&mut self, ctx: &Context, image: RgbaImage) {
+        if self.options.skip_identical && self.last.as_ref() == Some(&image) {
+            self.skipped += 1;
+            return;
+        }
+        self.saved += 1;
+        self.pending += 1;
+        let mut save_path = self.save_path.clone();
+        save_path.name = format!("{}_{:04}", self.save_path.name, self.saved);
+        let (sender, picture, ctx) = (self.saves.0.clone(), image.clone(), ctx.clone());
+        thread::spawn(move || {
+            let _ = sender.send(save_utils::save_image(&save_path, picture));
+            ctx.request_repaint(); // Sveglia la UI, che può star aspettando gli ultimi salvataggi
+        });
+        self.last = Some(image);
+    }
+
+    pub fn ui(&self, ctx: &Context) -> IntervalAction {
+        let mut action = IntervalAction::None;
+        Window::new("Interval capture")
+            .title_bar(false)
+            .resizable(false)
+            .fixed_pos(Pos2::ZERO)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let total = match self.options.max_shots {
+                        0 => String::new(),
+                        max => format!("/{}", max),
+                    };
+                    ui.label(format!("⏱ {}{} shots", self.shots(), total));
+                    if self.skipped > 0 {
+                        ui.label(format!("({} skipped)", self.skipped));
+                    }
+                    if self.stopping {
+                        ui.spinner();
+                        ui.label("Saving...");
+                    } else if let Some(remaining) = self.job.remaining() {
+                        ui.label(format!("next in {:.0} s", remaining.as_secs_f32().ceil()));
+                    }
+                    ui.separator();
+                    if ui.button("⏹ Stop").clicked() {
+                        action = IntervalAction::Stop;
+                    }
+                });
+            });
+        action
+    }
+}
@@ -0,0 +1,94 @@
+use super::screenshot_view::FrozenFrame;
+use egui::*;
+
+// Quanti pixel mostra la lente per lato (dispari, così c'è un pixel centrale) e quanto è grande ognuno
+const LOUPE_PIXELS: i32 = 11;
+const CELL_SIZE: f32 = 10.0;
+const PADDING: f32 = 4.0;
+const LINE_HEIGHT: f32 = 16.0;
+const DISTANCE_FROM_POINTER: f32 = 24.0;
+
+// Disegna la lente accanto al puntatore: i pixel ingranditi intorno al puntatore, le sue coordinate
+// assolute in pixel fisici, la dimensione della selezione e il colore sotto il puntatore.
+// I pixel sono disponibili soltanto con il fotogramma congelato: senza, la lente mostra solo i numeri.
+pub fn paint_loupe(
+    painter: &Painter,
+    pointer: Pos2,
+    screen: Rect,
+    pixel: (i32, i32),
+    selection: Option<(u32, u32)>,
+    frozen: Option<&FrozenFrame>,
+) {
+    let grid_size = LOUPE_PIXELS as f32 * CELL_SIZE;
+    let size = vec2(grid_size + 2.0 * PADDING, grid_size + 2.0 * PADDING + 3.0 * LINE_HEIGHT);
+
+    // Accanto al puntatore, ma dal lato opposto se uscirebbe dallo schermo
+    let mut min = pointer + vec2(DISTANCE_FROM_POINTER, DISTANCE_FROM_POINTER);
+    if min.x + size.x > screen.max.x {
+        min.x = pointer.x - DISTANCE_FROM_POINTER - size.x;
+    }
+    if min.y + size.y > screen.max.y {
+        min.y = pointer.y - DISTANCE_FROM_POINTER - size.y;
+    }
+    let panel = Rect::from_min_size(min, size);
+    painter.rect_filled(panel, 4.0, Color32::from_black_alpha(220));
+
+    let grid = Rect::from_min_size(min + vec2(PADDING, PADDING), vec2(grid_size, grid_size));
+    let color_under_pointer = frozen.and_then(|frozen| sample(frozen, pixel.0, pixel.1));
+    match frozen {
+        Some(frozen) => {
+            let half = LOUPE_PIXELS / 2;
+            for dy in -half..=half {
+                for dx in -half..=half {
+                    let cell = Rect::from_min_size(
+                        grid.min + vec2((dx + half) as f32 * CELL_SIZE, (dy + half) as f32 * CELL_SIZE),
+                        vec2(CELL_SIZE, CELL_SIZE),
+                    );
+                    // Fuori dal desktop: grigio scuro
+                    let color = sample(frozen, pixel.0 + dx, pixel.1 + dy).unwrap_or(Color32::from_gray(40));
+                    painter.rect_filled(cell, 0.0, color);
+                }
+            }
+            // Il pixel centrale è quello sotto il puntatore
+            let center = Rect::from_center_size(grid.center(), vec2(CELL_SIZE, CELL_SIZE));
+            painter.rect_stroke(center.expand(1.0), 0.0, Stroke::new(1.0, Color32::BLACK));
+            painter.rect_stroke(center, 0.0, Stroke::new(1.0, Color32::WHITE));
+        }
+        None => {
+            painter.text(
+                grid.center(),
+                Align2::CENTER_CENTER,
+                "No preview\n(enable ❄ Frozen frame)",
+                FontId::proportional(12.0),
+                Color32::GRAY,
+            );
+        }
+    }
+
+    let mut lines = vec![format!("X: {}  Y: {}", pixel.0, pixel.1)];
+    if let Some((width, height)) = selection {
+        lines.push(format!("{} × {} px", width, height));
+    }
+    if let Some(color) = color_under_pointer {
+        lines.push(format!("#{:02X}{:02X}{:02X}", color.r(), color.g(), color.b()));
+    }
+    for (i, line) in lines.iter().enumerate() {
+        painter.text(
+            pos2(grid.min.x, grid.max.y + PADDING + i as f32 * LINE_HEIGHT),
+            Align2::LEFT_TOP,
+            line,
+            FontId::monospace(13.0),
+            Color32::WHITE,
+        );
+    }
+}
+
+// Il colore del pixel fisico (x, y) del desktop, se cade nel fotogramma congelato
+pub fn sample(frozen: &FrozenFrame, x: i32, y: i32) -> Option<Color32> {
+    let local = (x - frozen.region.x, y - frozen.region.y);
+    if local.0 < 0 || local.1 < 0 {
+        return None;
+    }
+    let [r, g, b, _] = frozen.image.get_pixel_checked(local.0 as u32, local.1 as u32)?.0;
+    Some(Color32::from_rgb(r, g, b))
+}
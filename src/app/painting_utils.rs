@@ -5,37 +5,15 @@ use egui::emath;
 use egui::widgets::DragValue;
 use egui::{Pos2, Rect, Vec2};
 use image::{imageops, RgbaImage};
-use std::{ops::Add, vec};
+use std::collections::HashMap;
 
+use super::color_utils::{self, ColorFormat, PickedColor};
 use super::crop_utils;
-
-#[derive(Clone)]
-
-pub struct DrawObj {
-    points: Vec<Pos2>,
-    stroke: egui::Stroke,
-}
-
-impl DrawObj {
-    fn new(points: Vec<Pos2>, stroke: egui::Stroke) -> Self {
-        Self {
-            points: points,
-            stroke: stroke,
-        }
-    }
-}
-
-impl Default for DrawObj {
-    fn default() -> Self {
-        Self {
-            points: vec![],
-            stroke: egui::Stroke::new(
-                1.0,
-                egui::Color32::from_rgba_unmultiplied(18, 160, 215, 255),
-            ),
-        }
-    }
-}
+use super::history_utils::{Command, History, ImageChange};
+use super::redact_utils::{self, RedactMode};
+use super::render_utils;
+use super::shape_edit_utils::ShapeSelection;
+use super::shape_utils::{self, ArrowHead, DrawObj, Primitive, Shape};
 
 #[derive(Clone)]
 pub struct Painting {
@@ -45,7 +23,16 @@ pub struct Painting {
     stroke: egui::Stroke,
     aspect_ratio: f32,
     pub screenshot_image_buffer: Option<RgbaImage>,
-    last_actions: Vec<DrawObj>, // Used to go back in time!
+    history: History, // Used to go back in time!
+    pub image_changed: bool, // Ritaglio, rotazione o annullamento hanno cambiato l'immagine: l'app ricarica la texture
+    drawing: Option<(Pos2, DrawObj)>, // La forma che si sta trascinando, con il punto di partenza
+    fill: Option<egui::Color32>, // Riempimento di rettangoli ed ellissi
+    arrow_head: ArrowHead,
+    text_size: f32, // In pixel dell'immagine
+    text_align: egui::Align,
+    text_background: Option<egui::Color32>,
+    editing_text: Option<DrawObj>, // Il testo che si sta scrivendo nel canvas, non ancora in shapes
+    focus_text: bool, // Il campo di testo deve prendere il focus appena compare
     ui_size: egui::Rect,
     ui_position: egui::Pos2,
     selected_shape: DrawingShape,
@@ -53,12 +40,86 @@ pub struct Painting {
     crop: Option<crop_utils::Crop>,
     active_shape: bool,
     original_size: (u32, u32),
+    picking: bool, // Contagocce attivo: un clic prende un colore invece di disegnare
+    pick_source: Option<RgbaImage>, // L'immagine con le annotazioni, da cui il contagocce legge i colori
+    color_format: ColorFormat,
+    pick_sets_stroke: bool,
+    pub picked: Option<PickedColor>, // L'ultimo colore preso: l'app lo copia negli appunti
+    selecting: bool, // Strumento di selezione: un clic seleziona le forme invece di disegnarne
+    selection: ShapeSelection,
+    redact_mode: RedactMode,
+    step_radius: f32, // In pixel dell'immagine
+    highlight_stroke: egui::Stroke,
+    spotlight_ellipse: bool,
+    spotlight_texture: Option<(SpotlightKey, egui::TextureHandle)>, // Il velo dello spotlight sul canvas
+    redaction_textures: HashMap<RedactionKey, egui::TextureHandle>, // Le anteprime delle redazioni sul canvas
+    highlight_base: Option<(HighlightBaseKey, RgbaImage)>, // Quello che c'è sotto l'evidenziatore, grande quanto il canvas
+    highlight_texture: Option<(HighlightKey, egui::TextureHandle)>, // L'evidenziatore sul canvas
+    stroke_textures: Vec<(StrokeKey, egui::TextureHandle, Rect)>, // I tratti sul canvas, con dove vanno sullo schermo
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Da cosa dipende il velo dello spotlight sul canvas: le sue dimensioni in pixel e le aree
+type SpotlightKey = ([u32; 2], Vec<Vec<Pos2>>);
+
+// Da cosa dipende l'anteprima di una redazione: i pixel che copre e come li nasconde
+type RedactionKey = ((u32, u32, u32, u32), RedactMode);
+
+// Da cosa dipende quello che c'è sotto l'evidenziatore sul canvas: le dimensioni in pixel, redazioni e spotlight
+type HighlightBaseKey = ([u32; 2], Vec<DrawObj>);
+
+// E da cosa dipende l'evidenziatore: in più, i suoi tratti
+type HighlightKey = (HighlightBaseKey, Vec<DrawObj>);
+
+// Da cosa dipende un tratto sul canvas: il primitivo, quanti pixel dello schermo per pixel dell'immagine
+// e dove cade il canvas sullo schermo
+type StrokeKey = (Primitive, [f32; 2], Pos2);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum DrawingShape {
+    Freehand,
     Line,
-    StraightLine,
+    Arrow,
+    Rectangle,
+    Ellipse,
+    Text,
+    Redact,
+    Step,
+    Highlighter,
+    Spotlight,
+}
+
+impl DrawingShape {
+    const ALL: [DrawingShape; 10] = [
+        DrawingShape::Freehand,
+        DrawingShape::Line,
+        DrawingShape::Arrow,
+        DrawingShape::Rectangle,
+        DrawingShape::Ellipse,
+        DrawingShape::Text,
+        DrawingShape::Redact,
+        DrawingShape::Step,
+        DrawingShape::Highlighter,
+        DrawingShape::Spotlight,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            DrawingShape::Freehand => "Freehand",
+            DrawingShape::Line => "Line",
+            DrawingShape::Arrow => "Arrow",
+            DrawingShape::Rectangle => "Rectangle",
+            DrawingShape::Ellipse => "Ellipse",
+            DrawingShape::Text => "Text",
+            DrawingShape::Redact => "Redact",
+            DrawingShape::Step => "Step",
+            DrawingShape::Highlighter => "Highlighter",
+            DrawingShape::Spotlight => "Spotlight",
+        }
+    }
+
+    fn can_fill(&self) -> bool {
+        matches!(self, DrawingShape::Rectangle | DrawingShape::Ellipse)
+    }
 }
 
 impl Default for Painting {
@@ -74,14 +135,39 @@ impl Default for Painting {
             texture: None,
             screenshot_image_buffer: None,
             aspect_ratio: 1.,
-            last_actions: vec![],
+            history: History::default(),
+            image_changed: false,
+            drawing: None,
+            fill: None,
+            arrow_head: ArrowHead::Filled,
+            text_size: 32.,
+            text_align: egui::Align::LEFT,
+            text_background: None,
+            editing_text: None,
+            focus_text: false,
             ui_size: egui::Rect::from_min_size(egui::Pos2::ZERO, egui::Vec2::ZERO),
             ui_position: egui::Pos2::ZERO,
-            selected_shape: DrawingShape::Line,
+            selected_shape: DrawingShape::Freehand,
             crop: None,
             to_screen: emath::RectTransform::identity(Rect::NOTHING),
             active_shape: true,
             original_size: (0, 0),
+            picking: false,
+            pick_source: None,
+            color_format: ColorFormat::Hex,
+            pick_sets_stroke: true,
+            picked: None,
+            selecting: false,
+            selection: ShapeSelection::default(),
+            redact_mode: RedactMode::Pixelate,
+            step_radius: 16.,
+            highlight_stroke: egui::Stroke::new(18., egui::Color32::from_rgba_unmultiplied(255, 225, 0, 200)),
+            spotlight_ellipse: false,
+            spotlight_texture: None,
+            redaction_textures: HashMap::new(),
+            highlight_base: None,
+            highlight_texture: None,
+            stroke_textures: Vec::new(),
         }
     }
 }
@@ -100,18 +186,18 @@ impl Painting {
         }
     }
 
-    pub fn new_crop(
-        texture: Option<egui::TextureHandle>,
-        screenshot_image_buffer: Option<RgbaImage>,
-        shapes: Vec<DrawObj>,
-    ) -> Self {
-        Self {
-            texture: texture.clone(),
-            aspect_ratio: texture.unwrap().aspect_ratio(),
-            screenshot_image_buffer: screenshot_image_buffer,
-            shapes: shapes,
-            ..Self::default()
-        }
+    // La texture dell'immagine dopo che è cambiata: le forme e la cronologia restano
+    pub fn set_texture(&mut self, texture: Option<egui::TextureHandle>) {
+        self.aspect_ratio = texture.as_ref().unwrap().aspect_ratio();
+        self.texture = texture;
+        // Erano calcolate sull'immagine di prima
+        self.redaction_textures.clear();
+        self.highlight_base = None;
+        self.highlight_texture = None;
+    }
+
+    pub fn set_stroke_color(&mut self, color: egui::Color32) {
+        self.stroke.color = color;
     }
 
     pub fn ui_control(&mut self, ui: &mut egui::Ui) -> egui::Response {
@@ -119,124 +205,250 @@ impl Painting {
             if self.texture.is_some() && self.crop.is_none() {
                 // Color and stroke buttons
                 ui.horizontal(|ui| {
-                    ui.add(
-                        DragValue::new(&mut self.stroke.width)
-                            .speed(1)
-                            .clamp_range(0..=12),
-                    )
-                    .on_hover_text("Width");
-                    ui.color_edit_button_srgba(&mut self.stroke.color);
-                    // ui.color_edit_button_srgb(&mut [self.stroke.color.r(), self.stroke.color.g(), self.stroke.color.b()]); // Magheggio per ignorare la trasparenza
-                    ui.label("Stroke");
-
-                    egui::ComboBox::from_label("Shape:")
-                        .selected_text(format!("{:?}", self.selected_shape))
-                        .show_ui(ui, |ui| {
-                            ui.selectable_value(
-                                &mut self.selected_shape,
-                                DrawingShape::Line,
-                                "Line",
-                            );
-                            ui.selectable_value(
-                                &mut self.selected_shape,
-                                DrawingShape::StraightLine,
-                                "Straight line",
-                            );
-                        });
-
-                    match self.selected_shape {
-                        DrawingShape::Line => {
-                            // stroke preview:
-                            let (_id, stroke_rect) = ui.allocate_space(ui.spacing().interact_size);
-                            let left = stroke_rect.left_center();
-                            let right = stroke_rect.right_center();
-                            ui.painter().line_segment(
-                                [left, right],
-                                (*&mut self.stroke.width, *&mut self.stroke.color),
-                            );
+                    if self.selecting {
+                        // Con la selezione, i controlli modificano le forme selezionate
+                        self.selection.ui_control(ui, &mut self.shapes, &mut self.history);
+                    } else {
+                        // Lo spessore è in pixel dell'immagine, come nell'export. L'evidenziatore ha un tratto suo.
+                        let stroke = if self.selected_shape == DrawingShape::Highlighter {
+                            &mut self.highlight_stroke
+                        } else {
+                            &mut self.stroke
+                        };
+                        ui.add(
+                            DragValue::new(&mut stroke.width)
+                                .speed(1)
+                                .clamp_range(1..=40),
+                        )
+                        .on_hover_text("Width");
+                        ui.color_edit_button_srgba(&mut stroke.color);
+                        ui.label("Stroke");
+
+                        egui::ComboBox::from_label("Shape:")
+                            .selected_text(self.selected_shape.label())
+                            .show_ui(ui, |ui| {
+                                for shape in DrawingShape::ALL {
+                                    ui.selectable_value(&mut self.selected_shape, shape, shape.label());
+                                }
+                            });
+
+                        if self.selected_shape == DrawingShape::Arrow {
+                            egui::ComboBox::from_id_source("arrow_head")
+                                .selected_text(format!("{:?}", self.arrow_head))
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut self.arrow_head, ArrowHead::Open, "Open");
+                                    ui.selectable_value(&mut self.arrow_head, ArrowHead::Filled, "Filled");
+                                    ui.selectable_value(&mut self.arrow_head, ArrowHead::Double, "Double");
+                                });
+                        }
+                        if self.selected_shape == DrawingShape::Text {
+                            ui.add(DragValue::new(&mut self.text_size).clamp_range(6..=300).prefix("Size: ").suffix(" px"));
+                            egui::ComboBox::from_id_source("text_align")
+                                .selected_text(match self.text_align {
+                                    egui::Align::Min => "Left",
+                                    egui::Align::Center => "Center",
+                                    egui::Align::Max => "Right",
+                                })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut self.text_align, egui::Align::LEFT, "Left");
+                                    ui.selectable_value(&mut self.text_align, egui::Align::Center, "Center");
+                                    ui.selectable_value(&mut self.text_align, egui::Align::RIGHT, "Right");
+                                });
+                            let mut boxed = self.text_background.is_some();
+                            if ui.checkbox(&mut boxed, "Background").changed() {
+                                self.text_background = if boxed { Some(egui::Color32::from_black_alpha(180)) } else { None };
+                            }
+                            if let Some(background) = self.text_background.as_mut() {
+                                ui.color_edit_button_srgba(background);
+                            }
+                        }
+                        if self.selected_shape == DrawingShape::Redact {
+                            egui::ComboBox::from_id_source("redact_mode")
+                                .selected_text(self.redact_mode.label())
+                                .show_ui(ui, |ui| {
+                                    for mode in RedactMode::ALL {
+                                        ui.selectable_value(&mut self.redact_mode, mode, mode.label());
+                                    }
+                                })
+                                .response
+                                .on_hover_text("The pixels under the rectangle are replaced in the saved image");
+                        }
+                        if self.selected_shape == DrawingShape::Step {
+                            ui.add(DragValue::new(&mut self.step_radius).clamp_range(6..=100).prefix("Radius: ").suffix(" px"))
+                                .on_hover_text("Click to place the next step, drag to point it at something with an arrow");
                         }
-                        DrawingShape::StraightLine => {
-                            ui.label("StraightLine");
-                            let (_id, stroke_rect) = ui.allocate_space(ui.spacing().interact_size);
-                            ui.painter().rect_stroke(
-                                stroke_rect,
-                                egui::Rounding::none(),
-                                *&mut self.stroke,
-                            );
+                        if self.selected_shape == DrawingShape::Spotlight {
+                            egui::ComboBox::from_id_source("spotlight_shape")
+                                .selected_text(if self.spotlight_ellipse { "Ellipse" } else { "Rectangle" })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut self.spotlight_ellipse, false, "Rectangle");
+                                    ui.selectable_value(&mut self.spotlight_ellipse, true, "Ellipse");
+                                })
+                                .response
+                                .on_hover_text("Everything outside the spotlights is dimmed");
+                        }
+                        if self.selected_shape.can_fill() {
+                            let mut filled = self.fill.is_some();
+                            if ui.checkbox(&mut filled, "Fill").changed() {
+                                self.fill = if filled { Some(self.stroke.color.linear_multiply(0.3)) } else { None };
+                            }
+                            if let Some(fill) = self.fill.as_mut() {
+                                ui.color_edit_button_srgba(fill);
+                            }
+                        }
+
+                        // Anteprima dello strumento, disegnata come sul canvas
+                        let (_id, preview_rect) = ui.allocate_space(ui.spacing().interact_size);
+                        let preview_rect = preview_rect.shrink(2.);
+                        let start = preview_rect.left_bottom();
+                        let stroke = self.current_stroke();
+                        let mut preview = self.new_shape(start, egui::Stroke::new(stroke.width.min(4.), stroke.color));
+                        preview.drag_to(start, preview_rect.center_top());
+                        preview.drag_to(start, preview_rect.right_top());
+                        if let Shape::Text { pos, text, size, .. } = &mut preview.shape {
+                            *pos = preview_rect.left_top();
+                            *text = "Aa".to_string();
+                            *size = preview_rect.height();
+                        }
+                        if let Shape::Step { center, radius, target, .. } = &mut preview.shape {
+                            *center = preview_rect.center();
+                            *radius = preview_rect.height() / 2.;
+                            *target = None;
+                        }
+                        let identity = emath::RectTransform::identity(preview_rect);
+                        ui.painter().extend(preview.primitives().iter().map(|p| p.to_egui(ui.ctx(), &identity)));
+                        if self.selected_shape == DrawingShape::Spotlight {
+                            ui.painter().rect_filled(preview_rect, 0., egui::Color32::from_gray(60));
+                            let spot = preview_rect.shrink2(preview_rect.size() / 4.);
+                            if self.spotlight_ellipse {
+                                ui.painter().circle_filled(spot.center(), spot.height() / 2., egui::Color32::from_gray(220));
+                            } else {
+                                ui.painter().rect_filled(spot, 0., egui::Color32::from_gray(220));
+                            }
+                        }
+                        if self.selected_shape == DrawingShape::Redact {
+                            // Le redazioni non hanno primitivi: una scacchiera di grigi, o il colore pieno
+                            let cell = preview_rect.size() / egui::vec2(4., 2.);
+                            for i in 0..8 {
+                                let min = preview_rect.min + cell * egui::vec2((i % 4) as f32, (i / 4) as f32);
+                                let color = match self.redact_mode {
+                                    RedactMode::Solid => self.stroke.color.to_opaque(),
+                                    _ => egui::Color32::from_gray(if (i + i / 4) % 2 == 0 { 90 } else { 170 }),
+                                };
+                                ui.painter().rect_filled(Rect::from_min_size(min, cell), 0., color);
+                            }
                         }
                     }
+
                     if ui.button("✂").clicked() {
+                        self.commit_text();
                         self.active_shape = false;
+                        self.picking = false;
+                        self.pick_source = None;
+                        self.selecting = false;
+                        self.selection.clear();
                         self.crop = Some(crop_utils::Crop::new());
                     }
+
+                    if ui
+                        .selectable_label(self.selecting, "⬚")
+                        .on_hover_text("Select: move, resize, restyle or delete the shapes")
+                        .clicked()
+                    {
+                        self.commit_text();
+                        self.selecting = !self.selecting;
+                        self.active_shape = !self.selecting;
+                        self.picking = false;
+                        self.pick_source = None;
+                        self.selection.clear();
+                    }
+
+                    if ui
+                        .selectable_label(self.picking, "💧")
+                        .on_hover_text("Eyedropper: click the image to copy a color")
+                        .clicked()
+                    {
+                        self.commit_text();
+                        self.picking = !self.picking;
+                        self.active_shape = !self.picking;
+                        self.selecting = false;
+                        self.selection.clear();
+                        // Si legge dall'immagine esportata, così si possono prendere anche i colori delle annotazioni
+                        self.pick_source = if self.picking { Some(self.generate_rgba_image()) } else { None };
+                    }
+                    if self.picking {
+                        color_utils::ui_picker_options(ui, "painting_color_format", &mut self.color_format, &mut self.pick_sets_stroke);
+                    }
                 });
 
                 ui.separator();
 
-                if ui.button("Clear Painting").clicked() {
-                    self.last_actions = self.shapes.clone();
-                    self.shapes.clear();
+                if ui.button("⟲").on_hover_text("Rotate counterclockwise").clicked() {
+                    self.rotate(ui.ctx(), false);
+                }
+                if ui.button("⟳").on_hover_text("Rotate clockwise").clicked() {
+                    self.rotate(ui.ctx(), true);
                 }
 
                 ui.separator();
 
-                if self.shapes.is_empty() {
-                    self.shapes.push(DrawObj::new(vec![], self.stroke.clone()));
-                }
-
-                // UNDO BUTTON
-                if self.shapes.is_empty() {
-                    if ui
-                        .add_enabled(false, egui::Button::new("Undo"))
-                        .on_hover_text("Can't go back anymore!")
-                        .clicked()
-                    {
-                        unreachable!();
+                if ui.button("Clear Painting").clicked() {
+                    self.editing_text = None;
+                    self.selection.clear();
+                    if !self.shapes.is_empty() {
+                        self.history.record(Command::clear(std::mem::take(&mut self.shapes)));
                     }
-                } else if self.shapes.len() > 1 {
-                    // shapes will ALWAYS contain an empty vector, which is placed at the end of the array.
-                    if ui.button("Undo").clicked() {
-                        // Quick and dirty :clown:
-                        self.shapes.pop();
-                        self.last_actions.pop();
-                        
-
-                        self.last_actions.push(self.shapes.pop().unwrap());
+                }
 
-                        self.shapes.push(DrawObj::new( vec![], self.stroke));
-                        self.last_actions.push(DrawObj::new( vec![], self.stroke));
+                ui.separator();
 
-                        // println!("(after) shapes: {:?}", self.shapes);
-                        // println!( "(after) Last actions: {:?}", self.last_actions);
-                    }
+                // UNDO BUTTON
+                if ui
+                    .add_enabled(self.history.can_undo(), egui::Button::new("Undo"))
+                    .on_hover_text("Ctrl+Z")
+                    .on_disabled_hover_text("Can't go back anymore!")
+                    .clicked()
+                {
+                    self.undo();
                 }
 
                 ui.separator();
 
                 // REDO BUTTON
-                if self.last_actions.is_empty() {
-                    if ui
-                        .add_enabled(false, egui::Button::new("Redo"))
-                        .on_hover_text("Can't go forward")
-                        .clicked()
-                    {
-                        unreachable!();
+                if ui
+                    .add_enabled(self.history.can_redo(), egui::Button::new("Redo"))
+                    .on_hover_text("Ctrl+Shift+Z")
+                    .on_disabled_hover_text("Can't go forward")
+                    .clicked()
+                {
+                    self.redo();
+                }
+
+                ui.menu_button("🕘 History", |ui| {
+                    if let Some(position) = self.history.ui(ui) {
+                        self.commit_text();
+                        self.selection.clear();
+                        let image = self.screenshot_image_buffer.as_mut().unwrap();
+                        self.image_changed |= self.history.jump_to(position, &mut self.shapes, image);
                     }
-                } else if self.last_actions.len() > 1 {
-                    // Last_actions will ALWAYS contain an empty vector, which is placed at the end of the array.
-                    if ui.button("Redo").clicked() {
-                        // Quick and dirty :clown:
-                        self.shapes.pop();
-                        self.last_actions.pop();
+                });
 
-                        self.shapes.push(self.last_actions.pop().unwrap());
+                ui.separator();
 
-                        self.shapes.push(DrawObj::new( vec![], self.stroke));
-                        self.last_actions.push(DrawObj::new( vec![], self.stroke));
+                // Le scorciatoie non valgono mentre si scrive un testo, che ha il suo annulla
+                if !ui.ctx().wants_keyboard_input() {
+                    let (undo, redo) = ui.input_mut(|i| {
+                        (
+                            i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z),
+                            i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z),
+                        )
+                    });
+                    if undo {
+                        self.undo();
+                    } else if redo {
+                        self.redo();
                     }
                 }
-
-                ui.separator();
             } else if self.crop.is_some() {
                 //Analizzare qua
                 if ui.button("✔").clicked() {
@@ -244,14 +456,15 @@ impl Painting {
                         self.screenshot_image_buffer.clone().unwrap().width() as f32,
                         self.screenshot_image_buffer.clone().unwrap().height() as f32,
                     ));
-                    let result = imageops::crop(
-                        &mut self.screenshot_image_buffer.clone().unwrap(),
-                        cutrect.min.x.round() as u32,
-                        cutrect.min.y.round() as u32,
-                        cutrect.size().x.round() as u32,
-                        cutrect.size().y.round() as u32,
-                    )
-                    .to_image();
+                    let before = self.shapes.clone();
+                    let change = ImageChange::Crop {
+                        x: cutrect.min.x.round() as u32,
+                        y: cutrect.min.y.round() as u32,
+                        width: cutrect.size().x.round() as u32,
+                        height: cutrect.size().y.round() as u32,
+                    };
+                    let original = self.screenshot_image_buffer.take().unwrap();
+                    let result = change.apply(&original);
                     println!(
                         "Image after cut: {:?} - {:?}",
                         result.width(),
@@ -259,10 +472,12 @@ impl Painting {
                     );
                     self.screenshot_image_buffer = Some(result);
                     self.painting_size(ui.available_size());
-                    if self.shapes.len() > 0 {
-                        self.shapes_remap(&self.crop.clone().unwrap());
+                    if !self.shapes.is_empty() {
+                        self.shapes_remap(Vec2::new(cutrect.min.x.round(), cutrect.min.y.round()));
                         self.original_size = (self.screenshot_image_buffer.clone().unwrap().width(), self.screenshot_image_buffer.clone().unwrap().height());
                     }
+                    self.history.record(Command::crop(change, original, before, self.shapes.clone()));
+                    self.image_changed = true;
                     self.active_shape = true;
                     self.crop = None;
                 } else if ui.button("✖").clicked() {
@@ -283,10 +498,6 @@ impl Painting {
         // Bisogna ancora copiare i disegni sopra lo screenshot, covnertirlo in un formato opportuno
         // e salvarlo
 
-        if self.shapes.is_empty() {
-            self.shapes.push(DrawObj::new( vec![], self.stroke));
-        }
-
         let painting_size = self.painting_size(ui.available_size());
 
         // Alloca un oggetto Painter che disegna soltanto in un rettangolo di dimensione painting_size
@@ -294,105 +505,92 @@ impl Painting {
         // Biggest size possible for the painting by keeping the ar intact
 
         let (mut response, painter) =
-            ui.allocate_painter(painting_size.clone(), egui::Sense::drag());
+            ui.allocate_painter(painting_size, egui::Sense::drag());
         self.ui_size = response.rect;
         self.ui_position = response.rect.min;
 
         // Shows the image we're drawing on
         painter.add(egui::Shape::image(
             self.texture.as_ref().unwrap().id(),
-            egui::Rect::from_min_size(response.rect.min, painting_size.clone()), // StraightLine containing the image
+            egui::Rect::from_min_size(response.rect.min, painting_size), // StraightLine containing the image
             egui::Rect::from_min_max(egui::Pos2::ZERO, egui::Pos2::new(1., 1.)), // uv should normally be Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)) unless you want to crop or flip the image. --> no clue
             egui::Color32::WHITE,
         ));
 
+        // Le forme sono in pixel dell'immagine: to_screen le porta sul canvas
+        let image_size = self.image_size();
         self.to_screen = egui::emath::RectTransform::from_to(
-            egui::Rect::from_min_size(egui::Pos2::ZERO, response.rect.square_proportions()),
+            egui::Rect::from_min_size(egui::Pos2::ZERO, image_size),
             response.rect,
         );
 
+        // Le redazioni cambiano lo screenshot e lo spotlight lo scurisce, quindi vanno sotto tutte le altre forme.
+        // L'evidenziatore ci va subito sopra, sotto le altre forme anche se è stato disegnato dopo (come nell'export).
+        self.paint_redactions(ui.ctx(), &painter);
+        self.paint_spotlight(ui.ctx(), &painter, response.rect);
+        self.paint_highlights(ui.ctx(), &painter, response.rect);
+
         let from_screen = self.to_screen.inverse();
 
-        if self.shapes.is_empty() {
-            self.shapes.push(DrawObj::new( vec![], self.stroke));
-        }
-        if self.active_shape == true {
-            match self.selected_shape {
-                DrawingShape::Line => {
-                    let current_line = self.shapes.last_mut().unwrap();
-                    if let Some(pointer_pos) = response.interact_pointer_pos() {
-                        let canvas_pos = from_screen * pointer_pos;
-
-                        // println!("Canvas pos: {:?}", canvas_pos);
-                        if current_line.points.last() != Some(&canvas_pos) {
-                            if current_line.stroke != self.stroke {
-                                current_line.stroke = self.stroke;
-                            }
-                            current_line.points.push(canvas_pos);
-                            response.mark_changed();
-                        }
-                    } else if !current_line.points.is_empty() {
-                        self.shapes.push(DrawObj::new( vec![], self.stroke));
-                        response.mark_changed();
+        if self.active_shape {
+            // La forma cresce mentre la si trascina e viene aggiunta quando si rilascia il mouse
+            if let Some(pointer_pos) = response.interact_pointer_pos() {
+                let canvas_pos = shape_utils::clamp_to_image(from_screen * pointer_pos, image_size);
+                if response.drag_started() {
+                    // Un clic fuori dal testo che si sta scrivendo lo conferma
+                    self.commit_text();
+                    self.drawing = Some((canvas_pos, self.new_shape(canvas_pos, self.current_stroke())));
+                }
+                if let Some((start, drawing)) = self.drawing.as_mut() {
+                    drawing.drag_to(*start, canvas_pos);
+                    response.mark_changed();
+                }
+            }
+            if response.drag_released() {
+                if let Some((_, drawing)) = self.drawing.take() {
+                    if let Shape::Text { .. } = drawing.shape {
+                        // Il testo si scrive dopo averlo piazzato
+                        self.editing_text = Some(drawing);
+                        self.focus_text = true;
+                    } else if !drawing.is_empty() {
+                        self.add_shape(drawing);
                     }
                 }
-                DrawingShape::StraightLine => {
-                    let current_line = self.shapes.last_mut().unwrap();
-                    let init_canvas_pos: Pos2;
-                    let mut next_canvas_pos = egui::Pos2::new(-1., -1.); // This is the rectange bottom right point as we're dragging it
-
-                    if response.clicked() {
-                        if let Some(pointer_pos) = response.interact_pointer_pos() {
-                            // La prima volta che viene premuto cattura la posizione iniziale
-                            init_canvas_pos = from_screen * pointer_pos;
-                            println!("Init canvas pos: {:?}", init_canvas_pos);
-                        }
-
-                        if let Some(pointer_pos) = response.hover_pos() {
-                            // L'utente sta spostando il mouse nell'immagine -> disegna anteprima
-                            // current_line.pop(); // Elimina ultima linea disegnata
+            }
+        }
 
-                            println!("Hover pos: {:?}", pointer_pos);
+        if self.selecting {
+            if let Some(command) = self.selection.ui(ui.ctx(), &response, &self.to_screen, &mut self.shapes, image_size) {
+                self.history.record(command);
+                response.mark_changed();
+            }
+        }
 
-                            if current_line.points.last() != Some(&next_canvas_pos) {
-                                if current_line.stroke != self.stroke {
-                                    current_line.stroke = self.stroke;
-                                }
-                                next_canvas_pos = from_screen * pointer_pos;
-                                current_line.points.push(next_canvas_pos);
-                                response.mark_changed();
-                            }
+        if self.editing_text.is_some() {
+            self.text_editor_ui(ui);
+        }
 
-                            // current_line.pop();
-                        }
+        // Aggiunte, eliminazioni, annulla e spostamenti nella pila possono aver cambiato l'ordine dei passi
+        shape_utils::renumber_steps(&mut self.shapes);
 
-                        if response.clicked() {
-                            // L'utente ha rilasciato il mouse -> disegna la linea
-                            if let Some(pointer_pos) = response.interact_pointer_pos() {
-                                if current_line.points.last() != Some(&next_canvas_pos) {
-                                    if current_line.stroke != self.stroke {
-                                        current_line.stroke = self.stroke;
-                                    }
-                                    next_canvas_pos = from_screen * pointer_pos;
-                                    current_line.points.push(next_canvas_pos);
-                                    response.mark_changed();
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        // Ridisegna le linee
-        let shapes = self
+        // Ridisegna le forme, più quella che si sta trascinando
+        let primitives = self
             .shapes
             .iter()
-            .filter(|line| line.points.len() >= 2)
-            .map(|line| {
-                let points: Vec<egui::Pos2> = line.points.iter().map(|p| self.to_screen * *p).collect();
-                egui::Shape::line(points, line.stroke)
-            });
-        painter.extend(shapes);
+            .chain(self.editing_text.as_ref())
+            .chain(self.drawing.as_ref().map(|(_, drawing)| drawing))
+            .flat_map(|shape| shape.primitives())
+            .filter(|primitive| !matches!(primitive, Primitive::Highlight { .. }))
+            .collect::<Vec<_>>();
+        self.paint_primitives(ui.ctx(), &painter, primitives, response.rect);
+
+        if self.selecting {
+            self.selection.paint(ui.ctx(), &painter, &self.to_screen, &self.shapes);
+        }
+
+        if self.picking {
+            self.picker_ui(ui, &response, &painter);
+        }
 
         if self.crop.is_some() {
             self.crop.as_mut().unwrap().crop_img(
@@ -404,115 +602,351 @@ impl Painting {
         response
     }
 
-    pub fn generate_rgba_image(&mut self) -> RgbaImage {
-        //  Prende tutte le shapes fatte, che sono composte da coordinate (egui::Pos2)
-        let mut output_image = self.screenshot_image_buffer.clone();
-
-        println!("{:?}", self.stroke.width);
-
-        // let from_screen = to_screen.inverse();
-
-        // Ho dovuto clonare perché altrimenti dava problemi il borrow checker
-        for line in self.shapes.clone().iter() {
-            for couple_points in line.points.windows(2) {
-                for offset in 0..= line.stroke.width as u8 {
-                    // Domanda: Perché è stato fatto così?
-                    // Risposta: Egui può disegnare le linee con un certo spessore perché per ogni coppia di punti disegna un rettangolo con uno spessore.
-                    // Ho cercato di fare lo stesso con imageproc, ma non si può fare perché la linea non ha un argomento per lo spessore. Ho cercato di farlo
-                    // manualmente traslando di vari offset, ma uscivano sempre dei buchi bianchi. Ho cercato di riempirli, e attualmente questo è stato il risultato migliore
-                    // Non posso perderci altro tempo. La libreria è troppo acerba.
-
-                    let mut start = self.segment_coordinates(&couple_points[0], (offset, offset));
-                    let mut end = self.segment_coordinates(&couple_points[1], (offset, offset));
-
-                    imageproc::drawing::draw_line_segment_mut(
-                        output_image.as_mut().unwrap(),
-                        start,
-                        end,
-                        image::Rgba(line.stroke.color.to_array()),
-                    );
-                    // let rect = imageproc::rect::RectPosition::
-
-                    start = self.segment_coordinates(&couple_points[0], (0, offset));
-                    end = self.segment_coordinates(&couple_points[1], (0, offset));
-                    imageproc::drawing::draw_line_segment_mut(
-                        output_image.as_mut().unwrap(),
-                        start,
-                        end,
-                        image::Rgba(line.stroke.color.to_array()),
-                    );
+    fn picker_ui(&mut self, ui: &egui::Ui, response: &egui::Response, painter: &egui::Painter) {
+        // Mostra il colore sotto il puntatore; premendo lo si copia
+        let Some(image) = self.pick_source.as_ref() else {
+            return;
+        };
+        let Some(pointer) = response.hover_pos() else {
+            return;
+        };
+        ui.ctx().set_cursor_icon(egui::CursorIcon::Crosshair);
+        let rect = response.rect;
+        let x = ((pointer.x - rect.min.x) / rect.width() * image.width() as f32).floor();
+        let y = ((pointer.y - rect.min.y) / rect.height() * image.height() as f32).floor();
+        let x = (x.max(0.) as u32).min(image.width() - 1);
+        let y = (y.max(0.) as u32).min(image.height() - 1);
+        let [r, g, b, _] = image.get_pixel(x, y).0;
+        let color = egui::Color32::from_rgb(r, g, b);
+        let text = color_utils::format_color(color, self.color_format);
+
+        let swatch = egui::Rect::from_min_size(pointer + egui::vec2(16., 16.), egui::vec2(24., 24.));
+        painter.rect_filled(swatch, 2.0, color);
+        painter.rect_stroke(swatch, 2.0, egui::Stroke::new(1.0, egui::Color32::WHITE));
+        let galley = painter.layout_no_wrap(text, egui::FontId::monospace(13.0), egui::Color32::WHITE);
+        let label = egui::Rect::from_min_size(
+            swatch.right_center() + egui::vec2(8., -galley.size().y / 2.),
+            galley.size(),
+        );
+        painter.rect_filled(label.expand(3.0), 2.0, egui::Color32::from_black_alpha(200));
+        painter.galley(label.min, galley);
 
-                    start = self.segment_coordinates(&couple_points[0], (offset, 0));
-                    end = self.segment_coordinates(&couple_points[1], (offset, 0));
-                    imageproc::drawing::draw_line_segment_mut(
-                        output_image.as_mut().unwrap(),
-                        start,
-                        end,
-                        image::Rgba(line.stroke.color.to_array()),
-                    );
-                    // imageproc::drawing::draw_filled_rect_mut(output_image.as_mut().unwrap(), start, end);
-                }
+        if response.drag_started() {
+            if self.pick_sets_stroke {
+                // Mantiene la trasparenza scelta per il tratto
+                self.stroke.color = egui::Color32::from_rgba_unmultiplied(r, g, b, self.stroke.color.a());
             }
+            self.picked = Some(PickedColor::new(color, self.color_format, self.pick_sets_stroke));
         }
+    }
 
-        return output_image.unwrap().clone();
+    pub fn generate_rgba_image(&self) -> RgbaImage {
+        // Disegna le forme sopra lo screenshot, con la stessa geometria del canvas
+        let mut output_image = export_base(self.screenshot_image_buffer.as_ref().unwrap(), self.shapes.iter());
+        let primitives: Vec<_> = self
+            .shapes
+            .iter()
+            .chain(self.editing_text.as_ref())
+            .flat_map(|shape| shape.primitives())
+            .collect();
+        render_utils::render(&mut output_image, &primitives);
+        output_image
     }
 
-    /*     pub fn ui_size(&mut self) -> egui::Rect{
-        // Ritorna la grandezza della UI, usata per il cropping
-        return self.ui_size
+    // Una nuova forma dello strumento scelto, che parte da pos
+    fn new_shape(&self, pos: Pos2, stroke: egui::Stroke) -> DrawObj {
+        let shape = match self.selected_shape {
+            DrawingShape::Freehand => Shape::Freehand(vec![pos]),
+            DrawingShape::Line => Shape::Line { from: pos, to: pos },
+            DrawingShape::Arrow => Shape::Arrow { from: pos, to: pos, head: self.arrow_head },
+            DrawingShape::Rectangle => Shape::Rectangle(Rect::from_min_max(pos, pos)),
+            DrawingShape::Ellipse => Shape::Ellipse(Rect::from_min_max(pos, pos)),
+            DrawingShape::Text => Shape::Text {
+                pos,
+                text: String::new(),
+                size: self.text_size,
+                align: self.text_align,
+            },
+            DrawingShape::Redact => Shape::Redact { rect: Rect::from_min_max(pos, pos), mode: self.redact_mode },
+            DrawingShape::Step => Shape::Step {
+                center: pos,
+                radius: self.step_radius,
+                number: self.shapes.iter().filter(|s| matches!(s.shape, Shape::Step { .. })).count() as u32 + 1,
+                target: None,
+            },
+            DrawingShape::Highlighter => Shape::Highlight(vec![pos]),
+            DrawingShape::Spotlight => Shape::Spotlight { rect: Rect::from_min_max(pos, pos), ellipse: self.spotlight_ellipse },
+        };
+        let fill = match self.selected_shape {
+            DrawingShape::Rectangle | DrawingShape::Ellipse => self.fill,
+            DrawingShape::Text => self.text_background,
+            _ => None,
+        };
+        DrawObj::new(shape, stroke, fill)
     }
 
-    pub fn ui_size_vec2(&mut self) -> egui::Vec2{
-        // Ritorna la grandezza della UI, usata per il cropping
-        return egui::Vec2::new(self.ui_size.width(), self.ui_size.height())
+    // Il campo in cui si scrive il testo, appena sotto il testo nel canvas. I controlli del testo
+    // (dimensione, colore, sfondo, allineamento) valgono anche per quello che si sta scrivendo.
+    fn text_editor_ui(&mut self, ui: &mut egui::Ui) {
+        let (stroke, background) = (self.stroke, self.text_background);
+        let (text_size, text_align) = (self.text_size, self.text_align);
+        let to_screen = self.to_screen;
+        let Some(editing) = self.editing_text.as_mut() else {
+            return;
+        };
+        editing.stroke = stroke;
+        editing.fill = background;
+        let Shape::Text { pos, text, size, align } = &mut editing.shape else {
+            return;
+        };
+        *size = text_size;
+        *align = text_align;
+
+        let mut confirm = false;
+        let mut discard = false;
+        // Sotto l'ultima riga del testo
+        let lines = text.lines().count().max(1) as f32;
+        let anchor = to_screen.transform_pos(*pos) + egui::vec2(0., text_size * lines * to_screen.scale().y + 8.);
+        egui::Area::new("text_annotation_editor")
+            .fixed_pos(anchor)
+            .order(egui::Order::Foreground)
+            .show(ui.ctx(), |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        let edit = ui.add(
+                            egui::TextEdit::multiline(text)
+                                .desired_rows(1)
+                                .desired_width(200.)
+                                .hint_text("Type the note"),
+                        );
+                        if self.focus_text {
+                            edit.request_focus();
+                            self.focus_text = false;
+                        }
+                        if ui.button("✔").on_hover_text("Confirm (Ctrl+Enter)").clicked() {
+                            confirm = true;
+                        }
+                        if ui.button("🗑").on_hover_text("Discard (Esc)").clicked() {
+                            discard = true;
+                        }
+                    });
+                });
+            });
+        ui.input(|i| {
+            confirm |= i.modifiers.command && i.key_pressed(egui::Key::Enter);
+            discard |= i.key_pressed(egui::Key::Escape);
+        });
+
+        if discard {
+            self.editing_text = None;
+        } else if confirm {
+            self.commit_text();
+        }
     }
 
-    pub fn ui_position(&mut self) -> egui::Pos2{
-        // Ritorna la posizione della UI, usata per il cropping
-        return self.ui_position
-    }*/
+    // Aggiunge alle forme il testo che si sta scrivendo, se non è vuoto
+    fn commit_text(&mut self) {
+        if let Some(mut editing) = self.editing_text.take() {
+            if let Shape::Text { text, .. } = &mut editing.shape {
+                // Ctrl+Invio può aver lasciato un a capo in fondo
+                *text = text.trim_end().to_string();
+            }
+            if !editing.is_empty() {
+                self.add_shape(editing);
+            }
+        }
+    }
 
-    fn segment_coordinates(&mut self, point: &egui::Pos2, offset: (u8, u8)) -> (f32, f32) {
-        // Trasforma le coordinate di un punto p che è stato normalizzato con proporzioni quadrate, in coordinate per l'immagine
+    fn add_shape(&mut self, shape: DrawObj) {
+        self.history.record(Command::add(self.shapes.len(), shape.clone()));
+        self.shapes.push(shape);
+    }
 
-        let w = self.screenshot_image_buffer.as_ref().unwrap().width();
-        let h = self.screenshot_image_buffer.as_ref().unwrap().height();
+    fn undo(&mut self) {
+        // Un testo a metà diventa prima una forma, così l'annulla parte da lì
+        self.commit_text();
+        self.selection.clear();
+        let image = self.screenshot_image_buffer.as_mut().unwrap();
+        self.image_changed |= self.history.undo(&mut self.shapes, image);
+    }
 
-        let output_size = egui::Vec2::new(w as f32, h as f32);
-        let rect_output_size = egui::Rect::from_min_size(egui::Pos2::ZERO, output_size);
+    fn redo(&mut self) {
+        self.commit_text();
+        self.selection.clear();
+        let image = self.screenshot_image_buffer.as_mut().unwrap();
+        self.image_changed |= self.history.redo(&mut self.shapes, image);
+    }
 
-        // Serve per trasformare i punti p di shapes, in coordinate per lo schermo
-        self.to_screen = egui::emath::RectTransform::from_to(
-            egui::Rect::from_min_size(egui::Pos2::ZERO, rect_output_size.square_proportions()),
-            rect_output_size,
-        );
+    // Ruota lo screenshot di un quarto di giro, con le forme
+    fn rotate(&mut self, ctx: &egui::Context, clockwise: bool) {
+        self.commit_text();
+        self.selection.clear();
+        let size = self.image_size();
+        let before = self.shapes.clone();
+        let change = ImageChange::Rotate { clockwise };
+        let image = self.screenshot_image_buffer.as_mut().unwrap();
+        *image = change.apply(image);
+        for shape in self.shapes.iter_mut() {
+            shape.rotate(ctx, clockwise, size);
+        }
+        self.history.record(Command::rotate(clockwise, before, self.shapes.clone()));
+        self.image_changed = true;
+    }
 
-        // .add(egui::Vec2::new(self.stroke.width/2. + offset as f32, self.stroke.width/2. + offset as f32))
+    // Sul canvas ogni redazione è una texture con i pixel già redatti, ricalcolata solo quando cambia
+    fn paint_redactions(&mut self, ctx: &egui::Context, painter: &egui::Painter) {
+        let image = self.screenshot_image_buffer.as_ref().unwrap();
+        let mut used = HashMap::new();
+        for shape in self.shapes.iter().chain(self.drawing.as_ref().map(|(_, drawing)| drawing)) {
+            let Shape::Redact { rect, mode } = shape.shape else {
+                continue;
+            };
+            let Some(bounds) = redact_utils::pixel_bounds(rect, image.width(), image.height()) else {
+                continue;
+            };
+            let (x, y, width, height) = bounds;
+            let screen = Rect::from_two_pos(
+                self.to_screen.transform_pos(Pos2::new(x as f32, y as f32)),
+                self.to_screen.transform_pos(Pos2::new((x + width) as f32, (y + height) as f32)),
+            );
+            if mode == RedactMode::Solid {
+                painter.rect_filled(screen, 0., shape.stroke.color.to_opaque());
+                continue;
+            }
+            let key = (bounds, mode);
+            let texture = match self.redaction_textures.remove(&key).or_else(|| used.remove(&key)) {
+                Some(texture) => texture,
+                None => {
+                    let patch = redact_utils::redacted_patch(image, rect, mode, shape.stroke.color).unwrap();
+                    let patch = egui::ColorImage::from_rgba_unmultiplied([width as usize, height as usize], patch.as_raw());
+                    ctx.load_texture(format!("redaction_{:?}", key), patch, egui::TextureOptions::LINEAR)
+                }
+            };
+            painter.image(texture.id(), screen, Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.)), egui::Color32::WHITE);
+            used.insert(key, texture);
+        }
+        // Quelle non più usate (forme spostate o eliminate) si liberano
+        self.redaction_textures = used;
+    }
 
-        let mut new_coordinates = self.to_screen * *point;
+    // Il velo è una texture grande quanto il canvas, rifatta solo quando cambiano le aree o la dimensione
+    fn paint_spotlight(&mut self, ctx: &egui::Context, painter: &egui::Painter, rect: Rect) {
+        let drawing = self.drawing.as_ref().map(|(_, drawing)| drawing);
+        let holes = shape_utils::spotlight_holes(self.shapes.iter().chain(drawing));
+        if holes.is_empty() {
+            self.spotlight_texture = None;
+            return;
+        }
+        let pixels_per_point = ctx.pixels_per_point();
+        let size = [(rect.width() * pixels_per_point).round() as u32, (rect.height() * pixels_per_point).round() as u32];
+        let key = (size, holes);
+        if self.spotlight_texture.as_ref().map(|(cached, _)| cached) != Some(&key) {
+            let scale = size[0] as f32 / self.image_size().x;
+            let transform = tiny_skia::Transform::from_scale(scale, size[1] as f32 / self.image_size().y);
+            let Some(overlay) = render_utils::spotlight_overlay(size[0], size[1], &key.1, transform) else {
+                return;
+            };
+            let texture = ctx.load_texture("spotlight", overlay, egui::TextureOptions::LINEAR);
+            self.spotlight_texture = Some((key, texture));
+        }
+        let (_, texture) = self.spotlight_texture.as_ref().unwrap();
+        painter.image(texture.id(), rect, Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.)), egui::Color32::WHITE);
+    }
 
-        // Add an offset to simulate the width
-        new_coordinates = new_coordinates.add(egui::Vec2::new(
-            self.stroke.width / 2. + offset.0 as f32,
-            self.stroke.width / 2. + offset.1 as f32,
-        ));
+    // I tratti passano da tiny-skia, come nell'export, ognuno con una texture grande quanto lui, rifatta solo
+    // quando cambia; gli altri primitivi li disegna egui. Si rispetta l'ordine in cui sono stati disegnati.
+    fn paint_primitives(&mut self, ctx: &egui::Context, painter: &egui::Painter, primitives: Vec<Primitive>, rect: Rect) {
+        let pixels_per_point = ctx.pixels_per_point();
+        let scale = [
+            rect.width() * pixels_per_point / self.image_size().x,
+            rect.height() * pixels_per_point / self.image_size().y,
+        ];
+        let mut used = Vec::new();
+        for primitive in primitives {
+            let Primitive::Polyline { points, closed, stroke } = &primitive else {
+                painter.add(primitive.to_egui(ctx, &self.to_screen));
+                continue;
+            };
+            let key = (primitive.clone(), scale, rect.min);
+            let cached = self.stroke_textures.iter().position(|(cached, ..)| *cached == key);
+            let (texture, screen) = match cached {
+                Some(index) => {
+                    let (_, texture, screen) = self.stroke_textures.swap_remove(index);
+                    (texture, screen)
+                }
+                None => {
+                    let transform = tiny_skia::Transform::from_scale(scale[0], scale[1]);
+                    let Some((overlay, [x, y])) = render_utils::stroke_overlay(points, *closed, *stroke, transform) else {
+                        continue;
+                    };
+                    let min = rect.min + Vec2::new(x as f32, y as f32) / pixels_per_point;
+                    let size = Vec2::new(overlay.size[0] as f32, overlay.size[1] as f32) / pixels_per_point;
+                    (ctx.load_texture("stroke", overlay, egui::TextureOptions::LINEAR), Rect::from_min_size(min, size))
+                }
+            };
+            painter.image(texture.id(), screen, Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.)), egui::Color32::WHITE);
+            used.push((key, texture, screen));
+        }
+        // Quelli non più usati (forme spostate, eliminate o ridimensionate) si liberano
+        self.stroke_textures = used;
+    }
 
-        if new_coordinates.x > output_size.x {
-            // If it's out the screen, then something went wrong
-            new_coordinates.x = output_size.x - 1.;
-        } else if new_coordinates.x < 0. {
-            new_coordinates.x = 0.;
+    // L'evidenziatore moltiplica i colori come nell'export: una texture grande quanto il canvas con i pixel
+    // sotto i tratti già moltiplicati, rifatta solo quando cambiano i tratti, quello che c'è sotto o la dimensione
+    fn paint_highlights(&mut self, ctx: &egui::Context, painter: &egui::Painter, rect: Rect) {
+        let drawing = self.drawing.as_ref().map(|(_, drawing)| drawing);
+        let shapes = || self.shapes.iter().chain(drawing);
+        let highlights: Vec<DrawObj> = shapes().filter(|shape| matches!(shape.shape, Shape::Highlight(_))).cloned().collect();
+        if highlights.is_empty() {
+            self.highlight_texture = None;
+            return;
+        }
+        let pixels_per_point = ctx.pixels_per_point();
+        let size = [(rect.width() * pixels_per_point).round() as u32, (rect.height() * pixels_per_point).round() as u32];
+        let underneath = shapes()
+            .filter(|shape| matches!(shape.shape, Shape::Redact { .. } | Shape::Spotlight { .. }))
+            .cloned()
+            .collect();
+        let key = ((size, underneath), highlights);
+        if self.highlight_texture.as_ref().map(|(cached, _)| cached) != Some(&key) {
+            if self.highlight_base.as_ref().map(|(cached, _)| cached) != Some(&key.0) {
+                let image = self.screenshot_image_buffer.as_ref().unwrap();
+                let base = export_base(image, key.0 .1.iter());
+                let base = imageops::resize(&base, size[0].max(1), size[1].max(1), imageops::FilterType::Triangle);
+                self.highlight_base = Some((key.0.clone(), base));
+            }
+            let (_, base) = self.highlight_base.as_ref().unwrap();
+            let transform = tiny_skia::Transform::from_scale(size[0] as f32 / self.image_size().x, size[1] as f32 / self.image_size().y);
+            let strokes: Vec<_> = key
+                .1
+                .iter()
+                .filter_map(|shape| match &shape.shape {
+                    Shape::Highlight(points) => Some((points.clone(), shape.stroke)),
+                    _ => None,
+                })
+                .collect();
+            let Some(overlay) = render_utils::highlight_overlay(base, &strokes, transform) else {
+                return;
+            };
+            let texture = ctx.load_texture("highlights", overlay, egui::TextureOptions::LINEAR);
+            self.highlight_texture = Some((key, texture));
         }
+        let (_, texture) = self.highlight_texture.as_ref().unwrap();
+        painter.image(texture.id(), rect, Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.)), egui::Color32::WHITE);
+    }
 
-        if new_coordinates.y > output_size.y {
-            // If it's out the screen, then something went wrong
-            new_coordinates.y = output_size.y - 1.;
-        } else if new_coordinates.y < 0. {
-            new_coordinates.y = 0.;
+    // Il tratto delle forme nuove: l'evidenziatore ha il suo
+    fn current_stroke(&self) -> egui::Stroke {
+        if self.selected_shape == DrawingShape::Highlighter {
+            self.highlight_stroke
+        } else {
+            self.stroke
         }
+    }
 
-        return [new_coordinates.x, new_coordinates.y].into();
+    fn image_size(&self) -> Vec2 {
+        let image = self.screenshot_image_buffer.as_ref().unwrap();
+        Vec2::new(image.width() as f32, image.height() as f32)
     }
 
     fn painting_size(&mut self, ui_available_size: egui::Vec2) -> egui::Vec2 {
@@ -532,23 +966,76 @@ impl Painting {
                 egui::Vec2::from([ui_available_size.y * self.aspect_ratio, ui_available_size.y]);
         };
 
-        return painting_size;
+        painting_size
     }
 
-    pub fn shapes_remap(&mut self, crop: &crop_utils::Crop) { 
-        // Remaps the shapes coordinates to the new image size
+    pub fn shapes_remap(&mut self, cut_offset: Vec2) {
+        // Remaps the shapes coordinates to the new image size: the cut image starts at cut_offset
         for shape in &mut self.shapes {
-            let points: Vec<Pos2> = shape.points.iter_mut().map(|p| {
-                let mut point =  *p;
-                point.x -= crop.offset_x_left;
-                point.y -= crop.offset_y_up;
+            shape.translate(-cut_offset);
+        }
+    }
+}
 
-                return point;
-            }).collect();
+// Lo screenshot sotto le altre forme nell'export: le redazioni sovrascrivono i pixel, che nell'immagine salvata
+// non restano, e lo spotlight scurisce quello che sta fuori dalle sue aree
+fn export_base<'a>(screenshot: &RgbaImage, shapes: impl Iterator<Item = &'a DrawObj> + Clone) -> RgbaImage {
+    let mut image = screenshot.clone();
+    for shape in shapes.clone() {
+        if let Shape::Redact { rect, mode } = shape.shape {
+            redact_utils::redact(&mut image, rect, mode, shape.stroke.color);
+        }
+    }
+    render_utils::spotlight(&mut image, &shape_utils::spotlight_holes(shapes));
+    image
+}
 
-            //println!("--------------------------------");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    // Rumore: ogni canale di ogni pixel è 0 o 255 a caso (ma sempre lo stesso caso). Un blocco di almeno
+    // 16 pixel ha quasi sicuramente una media che non c'era; un pixel da solo, o una media di pochi, no.
+    fn noise() -> RgbaImage {
+        RgbaImage::from_fn(64, 48, |x, y| {
+            let hash = (x.wrapping_mul(374_761_393) ^ y.wrapping_mul(668_265_263)).wrapping_mul(1_274_126_177);
+            let bit = |shift: u32| if (hash >> shift) & 1 == 1 { 255 } else { 0 };
+            Rgba([bit(7), bit(13), bit(21), 255])
+        })
+    }
 
-            shape.points = points;
+    #[test]
+    fn redactions_leave_no_original_pixel() {
+        let original = noise();
+        let rects = [
+            Rect::from_min_max(Pos2::new(5.5, 7.2), Pos2::new(41.3, 30.)),
+            Rect::from_min_max(Pos2::new(3., 3.), Pos2::new(4., 4.)),     // Un pixel
+            Rect::from_min_max(Pos2::new(50., 2.), Pos2::new(53., 5.)),   // Più piccolo di un blocco
+            Rect::from_min_max(Pos2::new(40., 33.), Pos2::new(53., 46.)), // 13 × 13: un blocco e un pixel
+            Rect::from_min_max(Pos2::new(60., 0.), Pos2::new(62., 48.)),  // Una striscia larga due pixel
+        ];
+        // Un colore che nel rumore non c'è, così anche Solid cambia ogni pixel
+        let color = egui::Color32::from_rgb(10, 200, 90);
+        for rect in rects {
+            let (x0, y0, width, height) = redact_utils::pixel_bounds(rect, 64, 48).unwrap();
+            for mode in RedactMode::ALL {
+                let redaction = DrawObj::new(Shape::Redact { rect, mode }, egui::Stroke::new(1., color), None);
+                let painting = Painting {
+                    screenshot_image_buffer: Some(original.clone()),
+                    shapes: vec![redaction],
+                    ..Painting::default()
+                };
+                let output = painting.generate_rgba_image();
+                for (x, y, pixel) in output.enumerate_pixels() {
+                    let inside = (x0..x0 + width).contains(&x) && (y0..y0 + height).contains(&y);
+                    if inside {
+                        assert_ne!(pixel, original.get_pixel(x, y), "{:?} in {:?} kept the pixel at {}, {}", mode, rect, x, y);
+                    } else {
+                        assert_eq!(pixel, original.get_pixel(x, y), "{:?} in {:?} changed the pixel at {}, {}", mode, rect, x, y);
+                    }
+                }
+            }
         }
     }
 }
@@ -25,9 +25,9 @@ pub fn ui(ui: &mut Ui, path: &mut SavePath) {
         .show_ui(ui, |ui| {
             ui.style_mut().wrap = Some(false);
             ui.set_min_width(60.0);
-            ui.selectable_value(&mut path.format, ImgFormats::PNG, "PNG");
-            ui.selectable_value(&mut path.format, ImgFormats::JPEG, "JPEG");
-            ui.selectable_value(&mut path.format, ImgFormats::GIF, "GIF");
+            ui.selectable_value(&mut path.format, ImgFormats::Png, "PNG");
+            ui.selectable_value(&mut path.format, ImgFormats::Jpeg, "JPEG");
+            ui.selectable_value(&mut path.format, ImgFormats::Gif, "GIF");
         });
     ui.end_row();
     let start_tree = path.path.clone();
@@ -61,21 +61,18 @@ pub fn ui(ui: &mut Ui, path: &mut SavePath) {
                     ))
                     .default_open(true)
                     .show(ui, |ui| {
-                        if let Ok(entries) = fs::read_dir(&start_tree.clone()) {
-                            for entry in entries {
-                                if let Ok(entry) = entry {
-                                    if entry.path().is_dir() {
-                                        if ui
-                                            .button(format!(
-                                                "🗁 {}",
-                                                entry.file_name().to_string_lossy()
-                                            ))
-                                            .clicked()
-                                        {
-                                            println!("Path: {}", entry.path().display());
-                                            path.path = entry.path().to_path_buf();
-                                        }
-                                    }
+                        if let Ok(entries) = fs::read_dir(start_tree.clone()) {
+                            for entry in entries.flatten() {
+                                if entry.path().is_dir()
+                                    && ui
+                                        .button(format!(
+                                            "🗁 {}",
+                                            entry.file_name().to_string_lossy()
+                                        ))
+                                        .clicked()
+                                {
+                                    println!("Path: {}", entry.path().display());
+                                    path.path = entry.path().to_path_buf();
                                 }
                             }
                         }
@@ -91,9 +88,9 @@ pub fn ui_settings(ui: &mut Ui, path: &mut SavePath) {
         .show_ui(ui, |ui| {
             ui.style_mut().wrap = Some(false);
             ui.set_min_width(60.0);
-            ui.selectable_value(&mut path.format, ImgFormats::PNG, "PNG");
-            ui.selectable_value(&mut path.format, ImgFormats::JPEG, "JPEG");
-            ui.selectable_value(&mut path.format, ImgFormats::GIF, "GIF");
+            ui.selectable_value(&mut path.format, ImgFormats::Png, "PNG");
+            ui.selectable_value(&mut path.format, ImgFormats::Jpeg, "JPEG");
+            ui.selectable_value(&mut path.format, ImgFormats::Gif, "GIF");
         });
     ui.end_row();
     let start_tree = path.path.clone();
@@ -127,21 +124,18 @@ pub fn ui_settings(ui: &mut Ui, path: &mut SavePath) {
                     ))
                     .default_open(true)
                     .show(ui, |ui| {
-                        if let Ok(entries) = fs::read_dir(&start_tree.clone()) {
-                            for entry in entries {
-                                if let Ok(entry) = entry {
-                                    if entry.path().is_dir() {
-                                        if ui
-                                            .button(format!(
-                                                "🗁 {}",
-                                                entry.file_name().to_string_lossy()
-                                            ))
-                                            .clicked()
-                                        {
-                                            println!("Path: {}", entry.path().display());
-                                            path.path = entry.path().to_path_buf();
-                                        }
-                                    }
+                        if let Ok(entries) = fs::read_dir(start_tree.clone()) {
+                            for entry in entries.flatten() {
+                                if entry.path().is_dir()
+                                    && ui
+                                        .button(format!(
+                                            "🗁 {}",
+                                            entry.file_name().to_string_lossy()
+                                        ))
+                                        .clicked()
+                                {
+                                    println!("Path: {}", entry.path().display());
+                                    path.path = entry.path().to_path_buf();
                                 }
                             }
                         }
@@ -0,0 +1,249 @@
+use super::selection_utils::Constraint;
+use egui::{vec2, ComboBox, DragValue, Ui};
+use std::fs;
+use std::path::PathBuf;
+
+const PRESETS_FILE: &str = "region_presets.txt";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresetKind {
+    FixedSize,   // width × height pixel fisici esatti
+    AspectRatio, // width : height
+}
+
+// Un preset per la selezione della regione, definito dall'utente nelle impostazioni
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegionPreset {
+    pub name: String,
+    pub kind: PresetKind,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl RegionPreset {
+    pub fn fixed(name: &str, width: u32, height: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: PresetKind::FixedSize,
+            width,
+            height,
+        }
+    }
+
+    pub fn ratio(name: &str, width: u32, height: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: PresetKind::AspectRatio,
+            width,
+            height,
+        }
+    }
+
+    // Il vincolo per la selezione. Le dimensioni fisse sono in pixel fisici, la selezione lavora in punti:
+    // scale_factor è quello del monitor su cui si trova la selezione.
+    pub fn constraint(&self, scale_factor: f32) -> Constraint {
+        let (width, height) = (self.width.max(1) as f32, self.height.max(1) as f32);
+        match self.kind {
+            PresetKind::FixedSize => Constraint::FixedSize(vec2(width, height) / scale_factor),
+            PresetKind::AspectRatio => Constraint::AspectRatio(width / height),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self.kind {
+            PresetKind::FixedSize => format!("{} ({}×{})", self.name, self.width, self.height),
+            PresetKind::AspectRatio => format!("{} ({}:{})", self.name, self.width, self.height),
+        }
+    }
+
+    // Una riga del file dei preset: nome, tipo, larghezza e altezza separati da tab
+    fn to_line(&self) -> String {
+        let kind = match self.kind {
+            PresetKind::FixedSize => "fixed",
+            PresetKind::AspectRatio => "ratio",
+        };
+        format!("{}\t{}\t{}\t{}", self.name.replace('\t', " "), kind, self.width, self.height)
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let name = fields.next()?.to_string();
+        let kind = match fields.next()? {
+            "fixed" => PresetKind::FixedSize,
+            "ratio" => PresetKind::AspectRatio,
+            _ => return None,
+        };
+        let width = fields.next()?.trim().parse().ok()?;
+        let height = fields.next()?.trim().parse().ok()?;
+        Some(Self {
+            name,
+            kind,
+            width,
+            height,
+        })
+    }
+}
+
+pub fn default_presets() -> Vec<RegionPreset> {
+    vec![
+        RegionPreset::fixed("HD", 1280, 720),
+        RegionPreset::fixed("Social card", 1200, 630),
+        RegionPreset::ratio("Widescreen", 16, 9),
+        RegionPreset::ratio("Square", 1, 1),
+    ]
+}
+
+// La cartella delle impostazioni di QuickCapture: $XDG_CONFIG_HOME o ~/.config su Linux e macOS, %APPDATA% su Windows
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map(|dir| dir.join("quickcapture"))
+}
+
+// I preset salvati, o quelli di default se non ne sono mai stati salvati
+pub fn load_presets() -> Vec<RegionPreset> {
+    let Some(path) = config_dir().map(|dir| dir.join(PRESETS_FILE)) else {
+        return default_presets();
+    };
+    match fs::read_to_string(&path) {
+        Ok(content) => parse_presets(&content, |line| println!("Ignoring invalid preset in {}: {}", path.display(), line)),
+        Err(_) => default_presets(),
+    }
+}
+
+// Il contenuto del file dei preset: righe vuote e commenti (#) si saltano, le righe non valide si segnalano a invalid
+fn parse_presets(content: &str, mut invalid: impl FnMut(&str)) -> Vec<RegionPreset> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let preset = RegionPreset::from_line(line);
+            if preset.is_none() {
+                invalid(line);
+            }
+            preset
+        })
+        .collect()
+}
+
+pub fn save_presets(presets: &[RegionPreset]) -> std::io::Result<()> {
+    let dir = config_dir().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no config directory"))?;
+    fs::create_dir_all(&dir)?;
+    let mut content = String::from("# name\tfixed|ratio\twidth\theight\n");
+    for preset in presets {
+        content.push_str(&preset.to_line());
+        content.push('\n');
+    }
+    fs::write(dir.join(PRESETS_FILE), content)
+}
+
+// Cosa è successo nell'editor dei preset in questo frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PresetsEdit {
+    pub changed: bool,   // I preset sono cambiati
+    pub committed: bool, // L'utente ha finito una modifica (invio, focus perso, trascinamento rilasciato): si salva
+}
+
+// Editor dei preset per la settings view. Scrivendo un nome o un numero i preset cambiano a ogni tasto,
+// ma si salvano solo quando la modifica è confermata.
+pub fn ui_settings(ui: &mut Ui, presets: &mut Vec<RegionPreset>) -> PresetsEdit {
+    let mut edit = PresetsEdit::default();
+    let track = |edit: &mut PresetsEdit, response: egui::Response| {
+        edit.changed |= response.changed();
+        edit.committed |= response.lost_focus() || response.drag_released();
+    };
+    let mut to_remove = None;
+    ui.label("Region presets");
+    for (i, preset) in presets.iter_mut().enumerate() {
+        ui.push_id(i, |ui| {
+            ui.horizontal(|ui| {
+                track(&mut edit, ui.add(egui::TextEdit::singleline(&mut preset.name).desired_width(120.0)));
+                ComboBox::from_id_source("preset_kind")
+                    .selected_text(match preset.kind {
+                        PresetKind::FixedSize => "Fixed size",
+                        PresetKind::AspectRatio => "Aspect ratio",
+                    })
+                    .show_ui(ui, |ui| {
+                        for (kind, label) in [(PresetKind::FixedSize, "Fixed size"), (PresetKind::AspectRatio, "Aspect ratio")] {
+                            if ui.selectable_value(&mut preset.kind, kind, label).changed() {
+                                edit = PresetsEdit { changed: true, committed: true };
+                            }
+                        }
+                    });
+                track(&mut edit, ui.add(DragValue::new(&mut preset.width).clamp_range(1..=20000)));
+                ui.label(if preset.kind == PresetKind::FixedSize { "×" } else { ":" });
+                track(&mut edit, ui.add(DragValue::new(&mut preset.height).clamp_range(1..=20000)));
+                if ui.small_button("🗑").clicked() {
+                    to_remove = Some(i);
+                }
+            });
+        });
+    }
+    if let Some(i) = to_remove {
+        presets.remove(i);
+        edit = PresetsEdit { changed: true, committed: true };
+    }
+    if ui.small_button("➕ Add preset").clicked() {
+        presets.push(RegionPreset::ratio("New preset", 4, 3));
+        edit = PresetsEdit { changed: true, committed: true };
+    }
+    edit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> (Vec<RegionPreset>, Vec<String>) {
+        let mut invalid = vec![];
+        let presets = parse_presets(content, |line| invalid.push(line.to_string()));
+        (presets, invalid)
+    }
+
+    #[test]
+    fn presets_survive_a_round_trip() {
+        let presets = vec![
+            RegionPreset::fixed("HD", 1280, 720),
+            RegionPreset::ratio("Widescreen 16:9", 16, 9),
+            RegionPreset::fixed("Città ✔", 1, 20000),
+            RegionPreset::ratio("", 4, 3),
+        ];
+        let content: String = presets.iter().map(|preset| preset.to_line() + "\n").collect();
+        assert_eq!(parse(&content), (presets, vec![]));
+    }
+
+    #[test]
+    fn tabs_in_the_name_do_not_break_the_line() {
+        let line = RegionPreset::fixed("a\tb", 10, 20).to_line();
+        assert_eq!(RegionPreset::from_line(&line), Some(RegionPreset::fixed("a b", 10, 20)));
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_and_skipped() {
+        let content = "# name\tfixed|ratio\twidth\theight\n\
+                       \n\
+                       HD\tfixed\t1280\t720\n\
+                       Nothing else\n\
+                       Kind\tsquare\t1\t1\n\
+                       Short\tratio\t16\n\
+                       Negative\tfixed\t-1\t10\n\
+                       Words\tfixed\twide\t10\n\
+                       Spaces\tratio\t 4 \t3 \n";
+        let (presets, invalid) = parse(content);
+        assert_eq!(presets, vec![RegionPreset::fixed("HD", 1280, 720), RegionPreset::ratio("Spaces", 4, 3)]);
+        assert_eq!(invalid.len(), 5, "{:?}", invalid);
+    }
+
+    #[test]
+    fn constraint_uses_physical_pixels_for_fixed_sizes() {
+        assert_eq!(RegionPreset::fixed("HD", 1280, 720).constraint(2.0), Constraint::FixedSize(vec2(640., 360.)));
+        assert_eq!(RegionPreset::ratio("Wide", 16, 9).constraint(2.0), Constraint::AspectRatio(16. / 9.));
+        // Un preset con lato zero (da un file scritto a mano) non divide per zero
+        assert_eq!(RegionPreset::ratio("Zero", 4, 0).constraint(1.0), Constraint::AspectRatio(4.));
+    }
+}
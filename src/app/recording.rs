@@ -0,0 +1,443 @@
+use super::animation_utils::{AnimationFormat, RecordedFrame};
+use super::capture_utils::{CaptureBackend, CaptureError};
+use super::coords_utils::PhysicalRect;
+use super::cursor_utils::{CursorMode, CursorReader};
+use super::image_utils;
+use super::save_utils::{self, SavePath};
+use super::screenshot_utils::{self, CaptureTarget};
+use egui::*;
+use image::{imageops, Rgba, RgbaImage};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Prima di iniziare si aspetta che la maschera sparisca dallo schermo
+const MIN_START_DELAY: Duration = Duration::from_millis(300);
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+pub const CONTROL_WINDOW_SIZE: Vec2 = vec2(420.0, 48.0);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordingOptions {
+    pub fps: u32,
+    pub max_frames: usize, // Oltre ci si ferma: ogni fotogramma resta in memoria fino al salvataggio
+    pub dedup: bool,       // I fotogrammi uguali al precedente allungano quello, invece di aggiungerne uno
+    pub cursor: CursorMode,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self {
+            fps: 10,
+            max_frames: 300,
+            dedup: true,
+            cursor: CursorMode::Hidden,
+        }
+    }
+}
+
+pub enum RecordingEvent {
+    Progress { frames: usize, elapsed: Duration },
+    Finished(Result<Recorded, CaptureError>),
+}
+
+// Una registrazione finita. Se una cattura è fallita ci si è fermati lì, ma i fotogrammi presi fino ad allora restano.
+pub struct Recorded {
+    pub frames: Vec<RecordedFrame>,
+    pub stopped_by: Option<CaptureError>,
+}
+
+pub enum RecordingAction {
+    None,
+    Stop,
+    Cancel,
+}
+
+// Una registrazione della regione: un thread la cattura fps volte al secondo finché non viene fermato.
+// In pausa non si cattura, e il tempo passato in pausa non finisce nella durata dei fotogrammi.
+pub struct Recording {
+    pub region: PhysicalRect,
+    receiver: mpsc::Receiver<RecordingEvent>,
+    stop: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    pub frames: usize,
+    pub elapsed: Duration,
+    max_frames: usize,
+}
+
+impl Recording {
+    pub fn start(
+        ctx: &Context,
+        backend: &Arc<dyn CaptureBackend>,
+        region: PhysicalRect,
+        delay: Duration,
+        options: RecordingOptions,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let paused = Arc::new(AtomicBool::new(false));
+        let backend = backend.clone();
+        let ctx = ctx.clone();
+        let (stop1, paused1) = (stop.clone(), paused.clone());
+        thread::spawn(move || {
+            thread::sleep(delay.max(MIN_START_DELAY));
+            let result = record_loop(backend.as_ref(), region, options, &stop1, &paused1, |frames, elapsed| {
+                let _ = sender.send(RecordingEvent::Progress { frames, elapsed });
+                ctx.request_repaint();
+            });
+            let _ = sender.send(RecordingEvent::Finished(result));
+            ctx.request_repaint(); // Sveglia la UI, che sta aspettando il risultato
+        });
+        Self {
+            region,
+            receiver,
+            stop,
+            paused,
+            frames: 0,
+            elapsed: Duration::ZERO,
+            max_frames: options.max_frames,
+        }
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle_pause(&self) {
+        self.paused.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    // Va chiamata ad ogni update. Restituisce i fotogrammi quando il thread ha finito.
+    pub fn poll(&mut self) -> Option<Result<Recorded, CaptureError>> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(RecordingEvent::Progress { frames, elapsed }) => {
+                    self.frames = frames;
+                    self.elapsed = elapsed;
+                }
+                Ok(RecordingEvent::Finished(result)) => return Some(result),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(Err(CaptureError::Interrupted)),
+            }
+        }
+    }
+
+    pub fn ui(&self, ctx: &Context) -> RecordingAction {
+        let mut action = RecordingAction::None;
+        Window::new("Recording")
+            .title_bar(false)
+            .resizable(false)
+            .fixed_pos(Pos2::ZERO)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let icon = if self.is_paused() { "⏸" } else { "⏺" };
+                    ui.colored_label(Color32::LIGHT_RED, icon);
+                    ui.label(format!(
+                        "{:.1} s, {}/{} frames",
+                        self.elapsed.as_secs_f32(),
+                        self.frames,
+                        self.max_frames
+                    ));
+                    ui.separator();
+                    let pause_label = if self.is_paused() { "▶ Resume" } else { "⏸ Pause" };
+                    if ui.button(pause_label).clicked() {
+                        self.toggle_pause();
+                    }
+                    if ui.button("⏹ Stop").clicked() {
+                        action = RecordingAction::Stop;
+                    }
+                    if ui.button("✖ Cancel").clicked() {
+                        action = RecordingAction::Cancel;
+                    }
+                });
+            });
+        action
+    }
+}
+
+fn record_loop(
+    backend: &dyn CaptureBackend,
+    region: PhysicalRect,
+    options: RecordingOptions,
+    stop: &AtomicBool,
+    paused: &AtomicBool,
+    progress: impl Fn(usize, Duration),
+) -> Result<Recorded, CaptureError> {
+    let target = CaptureTarget::PhysicalRegion(region);
+    let interval = Duration::from_secs_f64(1.0 / options.fps.max(1) as f64);
+    let mut frames: Vec<RecordedFrame> = vec![];
+    let mut elapsed = Duration::ZERO;
+    let mut last_at = Instant::now();
+    let mut next_tick = Instant::now();
+    let mut cursor_reader = CursorReader::default(); // Una sola connessione per tutta la registrazione
+    let mut stopped_by = None;
+
+    while !stop.load(Ordering::Relaxed) {
+        if paused.load(Ordering::Relaxed) {
+            last_at = Instant::now();
+            next_tick = last_at;
+            thread::sleep(PAUSE_POLL_INTERVAL);
+            continue;
+        }
+
+        let mut capture = match screenshot_utils::take_screenshot(backend, &target, true, &options.cursor, &mut cursor_reader) {
+            Ok(capture) => capture,
+            Err(e) => {
+                println!("Recording: capture failed, stopping: {}", e);
+                stopped_by = Some(e);
+                break;
+            }
+        };
+        if capture.region != region {
+            // Un monitor saltato (o staccato) ha tolto una parte della regione: la si riempie di nero,
+            // così tutti i fotogrammi restano grandi come la regione
+            capture.image = pad_to_region(&capture.image, &capture.region, &region);
+        }
+        let now = Instant::now();
+        // L'ultimo fotogramma è rimasto sullo schermo fino a adesso
+        if let Some(last) = frames.last_mut() {
+            last.delay += now - last_at;
+            elapsed += now - last_at;
+        }
+        last_at = now;
+        let duplicate = options.dedup && frames.last().map_or(false, |last| last.image == capture.image);
+        if !duplicate {
+            frames.push(RecordedFrame {
+                image: capture.image,
+                delay: Duration::ZERO,
+            });
+        }
+        progress(frames.len(), elapsed);
+        if frames.len() >= options.max_frames {
+            println!("Recording: reached {} frames, stopping", options.max_frames);
+            break;
+        }
+
+        // Se la cattura è più lenta dell'intervallo si va avanti subito, senza recuperare i fotogrammi persi
+        next_tick += interval;
+        match next_tick.checked_duration_since(Instant::now()) {
+            Some(wait) => thread::sleep(wait),
+            None => next_tick = Instant::now(),
+        }
+    }
+
+    if let Some(last) = frames.last_mut() {
+        if last.delay.is_zero() {
+            last.delay = interval;
+        }
+    }
+    if frames.is_empty() {
+        return Err(stopped_by.unwrap_or(CaptureError::Interrupted));
+    }
+    Ok(Recorded { frames, stopped_by })
+}
+
+// L'immagine di captured, messa al suo posto in un'immagine nera grande come region
+fn pad_to_region(image: &RgbaImage, captured: &PhysicalRect, region: &PhysicalRect) -> RgbaImage {
+    let mut padded = RgbaImage::from_pixel(region.width, region.height, Rgba([0, 0, 0, 255]));
+    let offset = captured.relative_to(region);
+    imageops::replace(&mut padded, image, offset.x as i64, offset.y as i64);
+    padded
+}
+
+pub enum PreviewAction {
+    None,
+    Saved(PathBuf),
+    Failed(String),
+    Discard,
+}
+
+// Anteprima della registrazione prima di salvarla: la riproduce in loop e permette di scegliere il formato
+pub struct RecordingPreview {
+    pub frames: Arc<Vec<RecordedFrame>>, // Condivisi con il thread che salva, senza copiarli
+    texture: Option<TextureHandle>,
+    shown: Option<usize>, // Il fotogramma caricato nella texture
+    started: Instant,
+    pub format: AnimationFormat,
+    pub gif_speed: i32,
+    saving: Option<mpsc::Receiver<Result<PathBuf, String>>>,
+}
+
+impl RecordingPreview {
+    pub fn new(frames: Vec<RecordedFrame>) -> Self {
+        Self {
+            frames: Arc::new(frames),
+            texture: None,
+            shown: None,
+            started: Instant::now(),
+            format: AnimationFormat::Gif,
+            gif_speed: 10,
+            saving: None,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|f| f.delay).sum()
+    }
+
+    // Il fotogramma da mostrare adesso, riproducendo in loop
+    fn current_frame(&self) -> usize {
+        let total = self.duration().as_millis().max(1);
+        let mut t = self.started.elapsed().as_millis() % total;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let delay = frame.delay.as_millis();
+            if t < delay {
+                return i;
+            }
+            t -= delay;
+        }
+        self.frames.len() - 1
+    }
+
+    pub fn ui(&mut self, ctx: &Context, save_path: &SavePath) -> PreviewAction {
+        let mut action = PreviewAction::None;
+
+        if let Some(receiver) = self.saving.as_ref() {
+            match receiver.try_recv() {
+                Ok(Ok(path)) => action = PreviewAction::Saved(path),
+                Ok(Err(e)) => action = PreviewAction::Failed(e),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => action = PreviewAction::Failed("the encoder stopped".to_string()),
+            }
+            if !matches!(action, PreviewAction::None) {
+                self.saving = None;
+            }
+        }
+
+        let index = self.current_frame();
+        if self.shown != Some(index) {
+            let image = image_utils::load_image_from_memory(self.frames[index].image.clone());
+            match self.texture.as_mut() {
+                Some(texture) => texture.set(image, TextureOptions::default()),
+                None => self.texture = Some(ctx.load_texture("recording_preview", image, TextureOptions::default())),
+            }
+            self.shown = Some(index);
+        }
+        // Il prossimo fotogramma va mostrato anche se l'utente non muove il mouse
+        ctx.request_repaint_after(Duration::from_millis(10));
+
+        CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let saving = self.saving.is_some();
+                ui.label(format!(
+                    "{} frames, {:.1} s",
+                    self.frames.len(),
+                    self.duration().as_secs_f32()
+                ));
+                ui.separator();
+                ComboBox::from_id_source("animation_format")
+                    .selected_text(match self.format {
+                        AnimationFormat::Gif => "GIF",
+                        AnimationFormat::Apng => "APNG",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.format, AnimationFormat::Gif, "GIF");
+                        ui.selectable_value(&mut self.format, AnimationFormat::Apng, "APNG");
+                    });
+                if self.format == AnimationFormat::Gif {
+                    ui.add(DragValue::new(&mut self.gif_speed).clamp_range(1..=30).prefix("Palette speed: "))
+                        .on_hover_text("1 gives the best colors but is very slow, 30 is fast but with worse colors");
+                }
+                ui.separator();
+                if saving {
+                    ui.spinner();
+                    ui.label("Encoding...");
+                } else {
+                    if ui
+                        .add_enabled(save_utils::check_filename(&save_path.name), Button::new("💾 Save"))
+                        .on_hover_text(format!("Save in {}", save_path.path.display()))
+                        .clicked()
+                    {
+                        self.start_saving(save_path.clone());
+                    }
+                    if ui.button("🗑 Discard").clicked() {
+                        action = PreviewAction::Discard;
+                    }
+                }
+            });
+            ui.separator();
+            if let Some(texture) = self.texture.as_ref() {
+                let size = texture.size_vec2();
+                let scale = (ui.available_width() / size.x).min(ui.available_height() / size.y).min(1.0);
+                ui.centered_and_justified(|ui| ui.image(texture, size * scale));
+            }
+        });
+        action
+    }
+
+    // La codifica può richiedere parecchio: si fa in un altro thread, intanto l'anteprima continua
+    fn start_saving(&mut self, save_path: SavePath) {
+        let (sender, receiver) = mpsc::channel();
+        let frames = Arc::clone(&self.frames);
+        let (format, speed) = (self.format, self.gif_speed);
+        thread::spawn(move || {
+            let _ = sender.send(save_utils::save_animation(&save_path, &frames, format, speed));
+        });
+        self.saving = Some(receiver);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::capture_utils::{FakeBackend, Monitor};
+    use std::sync::atomic::AtomicUsize;
+
+    // Un FakeBackend che dopo un certo numero di catture smette di trovare i monitor, come uno schermo staccato
+    struct FailingAfter {
+        backend: FakeBackend,
+        captures_left: AtomicUsize,
+    }
+
+    impl CaptureBackend for FailingAfter {
+        fn monitors(&self) -> Result<Vec<Monitor>, CaptureError> {
+            match self.captures_left.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1)) {
+                Ok(_) => self.backend.monitors(),
+                Err(_) => Err(CaptureError::NoDisplays {
+                    reason: "unplugged".to_string(),
+                }),
+            }
+        }
+
+        fn capture(&self, monitor: &Monitor) -> Result<RgbaImage, CaptureError> {
+            self.backend.capture(monitor)
+        }
+    }
+
+    fn record(captures: usize) -> Result<Recorded, CaptureError> {
+        let backend = FailingAfter {
+            backend: FakeBackend::dual_monitor(),
+            captures_left: AtomicUsize::new(captures),
+        };
+        let region = PhysicalRect { x: 10, y: 10, width: 64, height: 48 };
+        let options = RecordingOptions {
+            fps: 200,
+            dedup: false,
+            ..Default::default()
+        };
+        let (stop, paused) = (AtomicBool::new(false), AtomicBool::new(false));
+        record_loop(&backend, region, options, &stop, &paused, |_, _| {})
+    }
+
+    #[test]
+    fn a_failed_capture_keeps_the_frames_recorded_so_far() {
+        let Ok(recorded) = record(3) else {
+            panic!("the frames before the failure were lost");
+        };
+        assert_eq!(recorded.frames.len(), 3);
+        assert!(recorded.frames.iter().all(|frame| !frame.delay.is_zero()));
+        assert!(matches!(recorded.stopped_by, Some(CaptureError::NoDisplays { .. })));
+    }
+
+    #[test]
+    fn a_failed_first_capture_is_an_error() {
+        assert!(matches!(record(0), Err(CaptureError::NoDisplays { .. })));
+    }
+}
@@ -0,0 +1,138 @@
+use egui::{Color32, Rect};
+use image::{imageops, Rgba, RgbaImage};
+
+// Le redazioni nascondono dati sensibili sostituendo i pixel dello screenshot, non coprendoli: nell'immagine
+// esportata quei pixel non esistono più. Pixelate e blur partono dalla media di blocchi di pixel, che non si
+// può invertire: da un blocco non si ricavano i pixel che lo componevano.
+
+const PIXEL_BLOCK: u32 = 12; // Lato dei quadrati di pixelate, in pixel dell'immagine
+const BLUR_BLOCK: u32 = 6; // Il blur parte da blocchi più piccoli, poi sfumati
+const BLUR_SIGMA: f32 = 1.5; // In blocchi
+// Meno pixel di così in un blocco e la media dice troppo di ognuno (con uno solo, è il pixel stesso):
+// le redazioni così piccole diventano Solid
+const MIN_BLOCK_PIXELS: u32 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RedactMode {
+    Pixelate,
+    Blur,
+    Solid, // Riempie con il colore del tratto, senza trasparenza
+}
+
+impl RedactMode {
+    pub const ALL: [RedactMode; 3] = [RedactMode::Pixelate, RedactMode::Blur, RedactMode::Solid];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RedactMode::Pixelate => "Pixelate",
+            RedactMode::Blur => "Blur",
+            RedactMode::Solid => "Solid",
+        }
+    }
+}
+
+// I pixel coperti da rect, dentro l'immagine: (x, y, larghezza, altezza)
+pub fn pixel_bounds(rect: Rect, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+    let x0 = (rect.min.x.floor().max(0.0) as u32).min(width);
+    let y0 = (rect.min.y.floor().max(0.0) as u32).min(height);
+    let x1 = (rect.max.x.ceil().max(0.0) as u32).min(width);
+    let y1 = (rect.max.y.ceil().max(0.0) as u32).min(height);
+    if x1 > x0 && y1 > y0 {
+        Some((x0, y0, x1 - x0, y1 - y0))
+    } else {
+        None
+    }
+}
+
+// Il contenuto redatto dei pixel coperti da rect, da mettere al posto dell'originale (anche sul canvas)
+pub fn redacted_patch(image: &RgbaImage, rect: Rect, mode: RedactMode, color: Color32) -> Option<RgbaImage> {
+    let (x, y, width, height) = pixel_bounds(rect, image.width(), image.height())?;
+    let region = imageops::crop_imm(image, x, y, width, height).to_image();
+    let block = match mode {
+        RedactMode::Pixelate => PIXEL_BLOCK,
+        RedactMode::Blur => BLUR_BLOCK,
+        RedactMode::Solid => 0,
+    };
+    let mode = if block > 0 && width.min(block) * height.min(block) < MIN_BLOCK_PIXELS {
+        RedactMode::Solid
+    } else {
+        mode
+    };
+    Some(match mode {
+        RedactMode::Pixelate => {
+            let blocks = block_average(&region, PIXEL_BLOCK);
+            imageops::resize(&blocks, width, height, imageops::FilterType::Nearest)
+        }
+        RedactMode::Blur => {
+            let blocks = imageops::blur(&block_average(&region, BLUR_BLOCK), BLUR_SIGMA);
+            imageops::resize(&blocks, width, height, imageops::FilterType::Triangle)
+        }
+        RedactMode::Solid => {
+            let [r, g, b, _] = color.to_srgba_unmultiplied();
+            RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]))
+        }
+    })
+}
+
+// Sovrascrive i pixel dell'immagine coperti da rect
+pub fn redact(image: &mut RgbaImage, rect: Rect, mode: RedactMode, color: Color32) {
+    let Some((x, y, _, _)) = pixel_bounds(rect, image.width(), image.height()) else {
+        return;
+    };
+    if let Some(patch) = redacted_patch(image, rect, mode, color) {
+        imageops::replace(image, &patch, x as i64, y as i64);
+    }
+}
+
+// Un pixel per ogni blocco di circa block × block, con la media dei suoi pixel. I lati si dividono in parti
+// uguali, così nessun blocco sul bordo è più piccolo di block (a meno che non lo sia tutta la regione).
+fn block_average(region: &RgbaImage, block: u32) -> RgbaImage {
+    let columns = (region.width() / block).max(1);
+    let rows = (region.height() / block).max(1);
+    RgbaImage::from_fn(columns, rows, |column, row| {
+        let (x0, x1) = (column * region.width() / columns, (column + 1) * region.width() / columns);
+        let (y0, y1) = (row * region.height() / rows, (row + 1) * region.height() / rows);
+        let mut sum = [0u32; 4];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                for (total, channel) in sum.iter_mut().zip(region.get_pixel(x, y).0) {
+                    *total += channel as u32;
+                }
+            }
+        }
+        let count = (x1 - x0) * (y1 - y0);
+        Rgba(sum.map(|total| ((total + count / 2) / count) as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_block_is_smaller_than_the_block_side() {
+        // 13 colonne: prima restava una colonna di blocchi larga un pixel
+        let region = RgbaImage::from_fn(13, 30, |x, y| Rgba([(x * 19) as u8, (y * 7) as u8, 0, 255]));
+        let blocks = block_average(&region, 12);
+        assert_eq!(blocks.dimensions(), (1, 2));
+        let blocks = block_average(&region, 6);
+        assert_eq!(blocks.dimensions(), (2, 5));
+    }
+
+    #[test]
+    fn tiny_redactions_become_solid() {
+        let image = RgbaImage::from_fn(40, 40, |x, y| Rgba([(x * 6) as u8, (y * 6) as u8, 128, 255]));
+        let color = Color32::from_rgb(10, 200, 90);
+        for (rect, solid) in [
+            (Rect::from_min_max(egui::pos2(5., 5.), egui::pos2(6., 6.)), true),   // Un pixel
+            (Rect::from_min_max(egui::pos2(5., 5.), egui::pos2(8., 8.)), true),   // 9 pixel
+            (Rect::from_min_max(egui::pos2(5., 5.), egui::pos2(6., 35.)), true),  // Una colonna: blocchi da 12
+            (Rect::from_min_max(egui::pos2(5., 5.), egui::pos2(9., 9.)), false),  // 16 pixel
+            (Rect::from_min_max(egui::pos2(5., 5.), egui::pos2(7., 35.)), false), // Blocchi da 2 × 12
+        ] {
+            let patch = redacted_patch(&image, rect, RedactMode::Pixelate, color).unwrap();
+            let is_solid = patch.pixels().all(|pixel| pixel.0 == [10, 200, 90, 255]);
+            assert_eq!(is_solid, solid, "{:?}", rect);
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use super::shape_utils::Primitive;
+use super::text_utils::TextRasterizer;
+use egui::{Color32, Pos2, Stroke};
+use image::RgbaImage;
+use tiny_skia::{BlendMode, FillRule, LineCap, LineJoin, Mask, Paint, PathBuilder, Pixmap, Transform};
+
+// Quanto lo spotlight scurisce quello che sta fuori dalle sue aree
+pub const SPOTLIGHT_DIM: Color32 = Color32::from_rgba_premultiplied(0, 0, 0, 150);
+
+// Disegna i primitivi delle annotazioni sull'immagine da esportare, con la stessa geometria del canvas.
+// I tratti e i poligoni passano da tiny-skia: antialiasing, giunzioni ed estremità arrotondate, spessore
+// esatto e colori semitrasparenti fusi con quello che c'è sotto.
+pub fn render(image: &mut RgbaImage, primitives: &[Primitive]) {
+    let Some(mut pixmap) = to_pixmap(image) else {
+        return; // Immagine vuota
+    };
+    let mut text_rasterizer: Option<TextRasterizer> = None; // Carica i font solo se c'è del testo
+    // L'evidenziatore va sotto tutte le altre forme, come sul canvas, anche se è stato disegnato dopo
+    let (highlights, others): (Vec<_>, Vec<_>) =
+        primitives.iter().partition(|primitive| matches!(primitive, Primitive::Highlight { .. }));
+    for primitive in highlights.into_iter().chain(others) {
+        match primitive {
+            Primitive::Polyline { points, closed, stroke } => {
+                stroke_polyline(&mut pixmap, points, *closed, *stroke, BlendMode::SourceOver, Transform::identity())
+            }
+            // L'evidenziatore moltiplica i colori: il testo sotto resta scuro e leggibile
+            Primitive::Highlight { points, stroke } => {
+                stroke_polyline(&mut pixmap, points, false, *stroke, BlendMode::Multiply, Transform::identity())
+            }
+            Primitive::Polygon { points, fill } => fill_polygon(&mut pixmap, points, *fill),
+            Primitive::Text { pos, text, style } => text_rasterizer
+                .get_or_insert_with(TextRasterizer::new)
+                .draw(&mut pixmap, *pos, text, style),
+        }
+    }
+    from_pixmap(&pixmap, image);
+}
+
+// Scurisce l'immagine fuori dalle aree dello spotlight (poligoni chiusi, tutti nello stesso verso)
+pub fn spotlight(image: &mut RgbaImage, holes: &[Vec<Pos2>]) {
+    if holes.is_empty() {
+        return;
+    }
+    let Some(mut pixmap) = to_pixmap(image) else {
+        return;
+    };
+    dim_outside(&mut pixmap, holes, Transform::identity());
+    from_pixmap(&pixmap, image);
+}
+
+// Lo stesso velo dello spotlight, da mettere sopra al canvas: transform porta dai pixel dell'immagine
+// a quelli dell'overlay
+pub fn spotlight_overlay(width: u32, height: u32, holes: &[Vec<Pos2>], transform: Transform) -> Option<egui::ColorImage> {
+    let mut pixmap = Pixmap::new(width, height)?;
+    dim_outside(&mut pixmap, holes, transform);
+    Some(egui::ColorImage::from_rgba_premultiplied([width as usize, height as usize], pixmap.data()))
+}
+
+// Un tratto sul canvas. egui non ha estremità e giunzioni arrotondate, quindi passa da tiny-skia come nell'export:
+// transform porta dai pixel dell'immagine a quelli dello schermo. Ritorna la texture, grande quanto il tratto,
+// e dove va il suo angolo in alto a sinistra.
+pub fn stroke_overlay(points: &[Pos2], closed: bool, stroke: Stroke, transform: Transform) -> Option<(egui::ColorImage, [i32; 2])> {
+    let mut corners = points.iter().map(|p| {
+        let mut point = tiny_skia::Point::from_xy(p.x, p.y);
+        transform.map_point(&mut point);
+        point
+    });
+    let first = corners.next()?;
+    let (mut min, mut max) = (first, first);
+    for point in corners {
+        (min.x, min.y) = (min.x.min(point.x), min.y.min(point.y));
+        (max.x, max.y) = (max.x.max(point.x), max.y.max(point.y));
+    }
+    // Mezzo tratto attorno ai punti, più un pixel per l'antialiasing
+    let reach = stroke.width * transform.sx.abs().max(transform.sy.abs()) / 2.0 + 1.0;
+    let (x0, y0) = ((min.x - reach).floor() as i32, (min.y - reach).floor() as i32);
+    let (x1, y1) = ((max.x + reach).ceil() as i32, (max.y + reach).ceil() as i32);
+    let mut pixmap = Pixmap::new((x1 - x0) as u32, (y1 - y0) as u32)?;
+    let transform = transform.post_translate(-x0 as f32, -y0 as f32);
+    stroke_polyline(&mut pixmap, points, closed, stroke, BlendMode::SourceOver, transform);
+    let size = [pixmap.width() as usize, pixmap.height() as usize];
+    Some((egui::ColorImage::from_rgba_premultiplied(size, pixmap.data()), [x0, y0]))
+}
+
+// L'evidenziatore sul canvas: egui non sa moltiplicare i colori, quindi i tratti si moltiplicano qui con
+// base, quello che c'è sotto già grande quanto l'overlay. L'overlay è opaco quanto i tratti (trasparente fuori)
+// e ha i colori che, messi sopra a base, danno i pixel moltiplicati.
+pub fn highlight_overlay(base: &RgbaImage, highlights: &[(Vec<Pos2>, Stroke)], transform: Transform) -> Option<egui::ColorImage> {
+    let below = to_pixmap(base)?;
+    let mut multiplied = below.clone();
+    let mut coverage = Pixmap::new(base.width(), base.height())?;
+    for (points, stroke) in highlights {
+        stroke_polyline(&mut multiplied, points, false, *stroke, BlendMode::Multiply, transform);
+        let opaque = Stroke::new(stroke.width, Color32::WHITE);
+        stroke_polyline(&mut coverage, points, false, opaque, BlendMode::SourceOver, transform);
+    }
+    let pixels = (below.pixels().iter().zip(multiplied.pixels()).zip(coverage.pixels()))
+        .map(|((below, multiplied), coverage)| {
+            // overlay + below × (1 - alpha) = multiplied
+            let alpha = coverage.alpha();
+            let channel = |below: u8, multiplied: u8| {
+                let over = multiplied as f32 - below as f32 * (255 - alpha) as f32 / 255.0;
+                over.round().clamp(0.0, alpha as f32) as u8
+            };
+            Color32::from_rgba_premultiplied(
+                channel(below.red(), multiplied.red()),
+                channel(below.green(), multiplied.green()),
+                channel(below.blue(), multiplied.blue()),
+                alpha,
+            )
+        })
+        .collect();
+    Some(egui::ColorImage { size: [base.width() as usize, base.height() as usize], pixels })
+}
+
+fn dim_outside(pixmap: &mut Pixmap, holes: &[Vec<Pos2>], transform: Transform) {
+    // Un solo percorso con tutte le aree: dove si sovrappongono restano illuminate
+    let mut builder = PathBuilder::new();
+    for hole in holes.iter().filter(|hole| hole.len() >= 3) {
+        builder.move_to(hole[0].x, hole[0].y);
+        for p in hole[1..].iter() {
+            builder.line_to(p.x, p.y);
+        }
+        builder.close();
+    }
+    let Some(mut mask) = Mask::new(pixmap.width(), pixmap.height()) else {
+        return;
+    };
+    if let Some(path) = builder.finish() {
+        mask.fill_path(&path, FillRule::Winding, true, transform);
+    }
+    mask.invert();
+    let rect = tiny_skia::Rect::from_xywh(0.0, 0.0, pixmap.width() as f32, pixmap.height() as f32).unwrap();
+    pixmap.fill_rect(rect, &paint(SPOTLIGHT_DIM), Transform::identity(), Some(&mask));
+}
+
+fn stroke_polyline(pixmap: &mut Pixmap, points: &[Pos2], closed: bool, stroke: Stroke, blend_mode: BlendMode, transform: Transform) {
+    if stroke.width <= 0.0 || stroke.color == Color32::TRANSPARENT {
+        return;
+    }
+    let mut builder = PathBuilder::new();
+    for (i, p) in points.iter().enumerate() {
+        if i == 0 {
+            builder.move_to(p.x, p.y);
+        } else {
+            builder.line_to(p.x, p.y);
+        }
+    }
+    if closed {
+        builder.close();
+    }
+    let Some(path) = builder.finish() else {
+        return; // Meno di due punti
+    };
+    let style = tiny_skia::Stroke {
+        width: stroke.width,
+        line_cap: LineCap::Round,
+        line_join: LineJoin::Round,
+        ..Default::default()
+    };
+    let mut paint = paint(stroke.color);
+    paint.blend_mode = blend_mode;
+    pixmap.stroke_path(&path, &paint, &style, transform, None);
+}
+
+fn fill_polygon(pixmap: &mut Pixmap, points: &[Pos2], fill: Color32) {
+    if points.len() < 3 || fill == Color32::TRANSPARENT {
+        return;
+    }
+    let mut builder = PathBuilder::new();
+    builder.move_to(points[0].x, points[0].y);
+    for p in points[1..].iter() {
+        builder.line_to(p.x, p.y);
+    }
+    builder.close();
+    if let Some(path) = builder.finish() {
+        pixmap.fill_path(&path, &paint(fill), FillRule::Winding, Transform::identity(), None);
+    }
+}
+
+fn paint(color: Color32) -> Paint<'static> {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(r, g, b, a);
+    paint.anti_alias = true;
+    paint
+}
+
+// tiny-skia lavora con colori premoltiplicati, l'immagine no
+fn to_pixmap(image: &RgbaImage) -> Option<Pixmap> {
+    let mut pixmap = Pixmap::new(image.width(), image.height())?;
+    for (pixel, source) in pixmap.pixels_mut().iter_mut().zip(image.pixels()) {
+        let [r, g, b, a] = source.0;
+        *pixel = tiny_skia::ColorU8::from_rgba(r, g, b, a).premultiply();
+    }
+    Some(pixmap)
+}
+
+fn from_pixmap(pixmap: &Pixmap, image: &mut RgbaImage) {
+    for (pixel, target) in pixmap.pixels().iter().zip(image.pixels_mut()) {
+        let color = pixel.demultiply();
+        target.0 = [color.red(), color.green(), color.blue(), color.alpha()];
+    }
+}
+
+// Confronta l'export con le immagini di riferimento in tests/golden. Se l'export cambia di proposito,
+// le immagini si rigenerano con: UPDATE_GOLDEN=1 cargo test golden
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::shape_utils::{ArrowHead, DrawObj, Shape};
+    use egui::{pos2, Align, Rect};
+    use image::Rgba;
+    use std::path::PathBuf;
+
+    // Quanto può scostarsi un canale, per gli arrotondamenti che cambiano da una piattaforma all'altra
+    const TOLERANCE: u8 = 2;
+
+    // Uno sfondo a scacchi con una sfumatura, così si vedono sia le fusioni che le trasparenze
+    fn background() -> RgbaImage {
+        RgbaImage::from_fn(320, 240, |x, y| {
+            let light = ((x / 20) + (y / 20)) % 2 == 0;
+            let base = if light { 230 } else { 170 };
+            Rgba([base, base - (y / 4) as u8, base - (x / 8) as u8, 255])
+        })
+    }
+
+    fn stroke(width: f32, color: Color32) -> Stroke {
+        Stroke::new(width, color)
+    }
+
+    fn shapes() -> Vec<DrawObj> {
+        let red = Color32::from_rgb(220, 30, 30);
+        let blue = Color32::from_rgb(30, 60, 220);
+        vec![
+            DrawObj::new(
+                Shape::Freehand(vec![pos2(10., 200.), pos2(40., 170.), pos2(70., 210.), pos2(100., 180.)]),
+                stroke(4., blue),
+                None,
+            ),
+            DrawObj::new(Shape::Line { from: pos2(10., 10.), to: pos2(120., 60.) }, stroke(3., red), None),
+            DrawObj::new(
+                Shape::Arrow { from: pos2(140., 20.), to: pos2(220., 20.), head: ArrowHead::Open },
+                stroke(3., red),
+                None,
+            ),
+            DrawObj::new(
+                Shape::Arrow { from: pos2(140., 50.), to: pos2(220., 50.), head: ArrowHead::Filled },
+                stroke(3., red),
+                None,
+            ),
+            DrawObj::new(
+                Shape::Arrow { from: pos2(140., 80.), to: pos2(220., 80.), head: ArrowHead::Double },
+                stroke(3., red),
+                None,
+            ),
+            DrawObj::new(
+                Shape::Rectangle(Rect::from_min_max(pos2(20., 80.), pos2(110., 140.))),
+                stroke(5., blue),
+                Some(Color32::from_rgba_unmultiplied(255, 255, 0, 120)),
+            ),
+            DrawObj::new(
+                Shape::Ellipse(Rect::from_min_max(pos2(230., 100.), pos2(310., 160.))),
+                stroke(2., Color32::from_rgba_unmultiplied(0, 160, 0, 180)),
+                Some(Color32::from_rgba_unmultiplied(0, 200, 255, 90)),
+            ),
+            DrawObj::new(
+                Shape::Step { center: pos2(160., 150.), radius: 14., number: 7, target: Some(pos2(200., 215.)) },
+                stroke(2., red),
+                None,
+            ),
+            DrawObj::new(
+                Shape::Highlight(vec![pos2(120., 225.), pos2(300., 225.)]),
+                stroke(16., Color32::from_rgb(255, 230, 0)),
+                None,
+            ),
+        ]
+    }
+
+    fn texts() -> Vec<DrawObj> {
+        let text = |pos, text: &str, size, align, color, fill| {
+            DrawObj::new(Shape::Text { pos, text: text.to_string(), size, align }, stroke(1., color), fill)
+        };
+        vec![
+            text(pos2(10., 10.), "Hello, QuickCapture!", 24., Align::Min, Color32::BLACK, None),
+            text(pos2(160., 60.), "Centered\ntwo lines", 18., Align::Center, Color32::from_rgb(30, 60, 220), None),
+            text(
+                pos2(310., 130.),
+                "Right, on a box",
+                16.,
+                Align::Max,
+                Color32::WHITE,
+                Some(Color32::from_rgba_unmultiplied(200, 0, 0, 200)),
+            ),
+            text(pos2(10., 180.), "àèìòù ✔ 123", 28., Align::Min, Color32::from_rgba_unmultiplied(0, 0, 0, 128), None),
+        ]
+    }
+
+    fn export(shapes: &[DrawObj]) -> RgbaImage {
+        let mut image = background();
+        spotlight(&mut image, &crate::app::shape_utils::spotlight_holes(shapes.iter()));
+        let primitives: Vec<_> = shapes.iter().flat_map(|shape| shape.primitives()).collect();
+        render(&mut image, &primitives);
+        image
+    }
+
+    fn check_golden(name: &str, image: &RgbaImage) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name].iter().collect();
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            image.save(&path).unwrap();
+            return;
+        }
+        let reference = image::open(&path)
+            .unwrap_or_else(|e| panic!("{}: {} (UPDATE_GOLDEN=1 creates it)", path.display(), e))
+            .to_rgba8();
+        assert_eq!(image.dimensions(), reference.dimensions(), "{}", name);
+        let different = image
+            .pixels()
+            .zip(reference.pixels())
+            .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > TOLERANCE))
+            .count();
+        if different > 0 {
+            let actual = path.with_extension("actual.png");
+            image.save(&actual).unwrap();
+            panic!("{} pixels differ from {}, the export is in {}", different, path.display(), actual.display());
+        }
+    }
+
+    #[test]
+    fn golden_shapes() {
+        check_golden("shapes.png", &export(&shapes()));
+    }
+
+    #[test]
+    fn golden_text() {
+        check_golden("text.png", &export(&texts()));
+    }
+
+    #[test]
+    fn golden_spotlight() {
+        let mut shapes = vec![
+            DrawObj::new(
+                Shape::Spotlight { rect: Rect::from_min_max(pos2(20., 20.), pos2(140., 120.)), ellipse: false },
+                stroke(1., Color32::BLACK),
+                None,
+            ),
+            DrawObj::new(
+                Shape::Spotlight { rect: Rect::from_min_max(pos2(100., 90.), pos2(300., 220.)), ellipse: true },
+                stroke(1., Color32::BLACK),
+                None,
+            ),
+        ];
+        shapes.extend(shapes_on_top());
+        check_golden("spotlight.png", &export(&shapes));
+    }
+
+    // Sul canvas l'overlay dell'evidenziatore, messo sopra quello che c'è sotto, dà gli stessi pixel dell'export
+    #[test]
+    fn highlight_overlay_matches_export() {
+        let points = vec![pos2(20., 40.), pos2(150., 60.), pos2(300., 200.)];
+        let highlight = stroke(18., Color32::from_rgba_unmultiplied(255, 225, 0, 200));
+        let base = background();
+        let mut exported = base.clone();
+        render(&mut exported, &[Primitive::Highlight { points: points.clone(), stroke: highlight }]);
+
+        let overlay = highlight_overlay(&base, &[(points, highlight)], Transform::identity()).unwrap();
+        for ((x, y, below), over) in base.enumerate_pixels().zip(overlay.pixels.iter()) {
+            let over = over.to_array();
+            let canvas: Vec<u8> =
+                (0..3).map(|i| (over[i] as f32 + below.0[i] as f32 * (255 - over[3]) as f32 / 255.).round() as u8).collect();
+            let export = &exported.get_pixel(x, y).0[..3];
+            assert!(
+                canvas.iter().zip(export).all(|(a, b)| a.abs_diff(*b) <= TOLERANCE),
+                "{:?} on the canvas, {:?} in the export at {}, {}",
+                canvas,
+                export,
+                x,
+                y
+            );
+        }
+    }
+
+    // Un evidenziatore disegnato dopo una linea non la moltiplica: resta sotto, come sul canvas
+    #[test]
+    fn highlight_goes_under_the_other_shapes() {
+        let line = Primitive::Polyline {
+            points: vec![pos2(10., 50.), pos2(300., 50.)],
+            closed: false,
+            stroke: stroke(6., Color32::from_rgb(30, 60, 220)),
+        };
+        let highlight = Primitive::Highlight {
+            points: vec![pos2(160., 10.), pos2(160., 100.)],
+            stroke: stroke(20., Color32::from_rgb(255, 225, 0)),
+        };
+        let mut image = background();
+        render(&mut image, &[line, highlight]);
+        assert_eq!(image.get_pixel(160, 50).0, [30, 60, 220, 255]);
+        assert_ne!(image.get_pixel(160, 80), background().get_pixel(160, 80));
+    }
+
+    // Sul canvas un tratto (con estremità e giunzioni arrotondate) dà gli stessi pixel dell'export
+    #[test]
+    fn stroke_overlay_matches_export() {
+        let cases = [
+            (vec![pos2(20., 30.), pos2(150., 60.), pos2(60., 200.)], false, stroke(15., Color32::from_rgb(220, 30, 30))),
+            (
+                vec![pos2(180., 40.), pos2(300., 40.), pos2(300., 150.), pos2(180., 150.)],
+                true,
+                stroke(9., Color32::from_rgba_unmultiplied(30, 60, 220, 160)),
+            ),
+        ];
+        for (points, closed, stroke) in cases {
+            let base = background();
+            let mut exported = base.clone();
+            render(&mut exported, &[Primitive::Polyline { points: points.clone(), closed, stroke }]);
+
+            let (overlay, [x0, y0]) = stroke_overlay(&points, closed, stroke, Transform::identity()).unwrap();
+            let mut canvas = base.clone();
+            for (i, over) in overlay.pixels.iter().enumerate() {
+                let (x, y) = (x0 + (i % overlay.size[0]) as i32, y0 + (i / overlay.size[0]) as i32);
+                if x < 0 || y < 0 || x >= base.width() as i32 || y >= base.height() as i32 {
+                    continue;
+                }
+                let below = canvas.get_pixel_mut(x as u32, y as u32);
+                let over = over.to_array();
+                for c in 0..3 {
+                    below.0[c] = (over[c] as f32 + below.0[c] as f32 * (255 - over[3]) as f32 / 255.).round() as u8;
+                }
+            }
+            let different = canvas
+                .pixels()
+                .zip(exported.pixels())
+                .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > TOLERANCE))
+                .count();
+            assert_eq!(different, 0, "{} pixels differ for {:?}", different, points);
+        }
+    }
+
+    // Le forme sopra lo spotlight non vengono scurite
+    fn shapes_on_top() -> Vec<DrawObj> {
+        vec![DrawObj::new(
+            Shape::Arrow { from: pos2(250., 30.), to: pos2(160., 100.), head: ArrowHead::Filled },
+            stroke(4., Color32::from_rgb(220, 30, 30)),
+            None,
+        )]
+    }
+}
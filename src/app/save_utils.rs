@@ -1,5 +1,8 @@
 use image::ImageBuffer;
 use crate::app::ImgFormats;
+use crate::app::animation_utils::{self, AnimationFormat, RecordedFrame};
+use std::fs::File;
+use std::io::BufWriter;
 use std::path::PathBuf;
 use chrono::{DateTime, Local};
 
@@ -26,26 +29,38 @@ impl SavePath {
 
 }
 
-pub fn save_image(save_path: &SavePath, picture: ImageBuffer<image::Rgba<u8>, Vec<u8>>){
+pub fn save_image(save_path: &SavePath, picture: ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<PathBuf, String> {
     // Questa funzione in base al path selezionato e al nome file, salva l'immagine
     // L'immagine salvata è gestita correttamente da image::save_buffer che salva correttamente nel formato desiderato
-    // Ovviamente si presume che il path sia valido e che l'estensione del file sia valida
-    let mut pathname = save_path.path.to_string_lossy().to_string().to_owned();
-    pathname.push_str("/");
-    let filename = save_path.name.to_owned();
-    pathname.push_str(&filename);
-    if save_path.format == ImgFormats::PNG{
-        pathname.push_str(".png");
-    }
-    else if save_path.format == ImgFormats::JPEG{
-        pathname.push_str(".jpeg");
-    }
-    else if save_path.format == ImgFormats::GIF{
-        pathname.push_str(".gif");
-    }
-    println!("Saving image to {}", pathname);
+    // Il path e il nome possono non essere validi (cartella cancellata, disco pieno...): l'errore torna al chiamante
+    let extension = match save_path.format {
+        ImgFormats::Png => "png",
+        ImgFormats::Jpeg => "jpeg",
+        ImgFormats::Gif => "gif",
+    };
+    let pathname = save_path.path.join(format!("{}.{}", save_path.name, extension));
+    println!("Saving image to {}", pathname.display());
+
+    image::save_buffer(&pathname, picture.as_raw(), picture.width(), picture.height(), image::ColorType::Rgba8)
+        .map_err(|e| e.to_string())?;
+    Ok(pathname)
+}
 
-    image::save_buffer(pathname,  &picture.as_raw().as_slice(), picture.width(), picture.height(), image::ColorType::Rgba8).unwrap();
+// Salva una registrazione nella stessa cartella e con lo stesso nome degli screenshot, ma con l'estensione del formato animato
+pub fn save_animation(
+    save_path: &SavePath,
+    frames: &[RecordedFrame],
+    format: AnimationFormat,
+    gif_speed: i32,
+) -> Result<PathBuf, String> {
+    let pathname = save_path.path.join(format!("{}.{}", save_path.name, format.extension()));
+    println!("Saving animation to {}", pathname.display());
+    let file = BufWriter::new(File::create(&pathname).map_err(|e| e.to_string())?);
+    match format {
+        AnimationFormat::Gif => animation_utils::encode_gif(file, frames, gif_speed)?,
+        AnimationFormat::Apng => animation_utils::encode_apng(file, frames)?,
+    }
+    Ok(pathname)
 }
 
 pub fn generate_filename() -> String {
@@ -56,10 +71,5 @@ pub fn generate_filename() -> String {
 
 pub fn check_filename(name: &str) -> bool {
     //TODO controllare che il filename sia valido (per windows)
-    if name.contains("\\") || name.contains("/") || name.contains(":") || name.contains("*") || name.contains("?") || name.contains("\"") || name.contains("<") || name.contains(">") || name.contains("|") {
-        return false;
-    }
-    else {
-        return true;
-    }
+    !name.contains(['\\', '/', ':', '*', '?', '"', '<', '>', '|'])
 }
\ No newline at end of file
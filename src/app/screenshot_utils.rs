@@ -1,123 +1,411 @@
-use super::screenshot_view::ScreenshotView;
-use crate::app::ScreenshotType;
-use image::{GenericImage, RgbaImage, GenericImageView};
-use screenshots::Screen;
-use std::io::Cursor;
+use super::capture_utils::{CaptureBackend, CaptureError, Monitor};
+use super::coords_utils::{DesktopLayout, PhysicalRect};
+use super::cursor_utils::{self, CursorImage, CursorMode, CursorReader};
+use image::RgbaImage;
+use std::thread;
+use std::time::Instant;
 
 struct ScreenImage {
-    screen: Screen,
-    image: screenshots::Image,
+    monitor: Monitor,
+    image: RgbaImage,
 }
 
-// Fa lo screenshot di ogni schermo e ritorna un buffer che descrive l'immagine. Ma non è l'immagine stessa.
+// Cosa catturare. Le regioni logiche sono in punti del desktop (quelli della maschera di selezione),
+// quelle fisiche in pixel del desktop virtuale (vedi coords_utils).
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureTarget {
+    Desktop,
+    LogicalRegion(egui::Rect),
+    PhysicalRegion(PhysicalRect),
+    Monitor(u32), // Soltanto il monitor con questo id, alla sua risoluzione nativa
+}
+
+// Il risultato di take_screenshot: l'immagine, la regione del desktop virtuale che contiene,
+// gli eventuali monitor che sono stati saltati e il cursore letto al momento della cattura
+pub struct Capture {
+    pub image: RgbaImage,
+    pub region: PhysicalRect,
+    pub skipped: Vec<CaptureError>,
+    pub cursor: Option<CursorImage>,
+}
+
+// Fa lo screenshot di ogni schermo, li compone in un'unica immagine e ritaglia quello che serve.
+// Se skip_failed_monitors è true, i monitor che non si riescono a catturare vengono saltati (e finiscono in
+// Capture::skipped) invece di far fallire tutta la cattura. Il cursore viene disegnato sopra come chiede cursor.
 pub fn take_screenshot(
-    _screenshot_type: Option<ScreenshotType>,
-    _grabbed_area: Option<ScreenshotView>,
-    _ctx: &egui::Context,
-) -> Option<image::RgbaImage> {
-    let mut img: RgbaImage;
-    let screen_images = Screen::all()
-        .unwrap()
-        .into_iter()
-        .map(|screen| {
-            let image = screen.capture().unwrap();
-            ScreenImage { screen, image }
-        })
-        .collect::<Vec<ScreenImage>>();
-    let x_min = screen_images
-        .iter()
-        .map(|s| s.screen.display_info.x * s.screen.display_info.scale_factor as i32)
-        .min()
-        .unwrap();
-    let y_min = screen_images
-        .iter()
-        .map(|s| s.screen.display_info.y * s.screen.display_info.scale_factor as i32)
-        .min()
-        .unwrap();
-    let x_max = screen_images
-        .iter()
-        .map(|s| (s.screen.display_info.x + s.screen.display_info.width as i32) * s.screen.display_info.scale_factor as i32)
-        .max()
-        .unwrap();
-    let y_max = screen_images
+    backend: &dyn CaptureBackend,
+    target: &CaptureTarget,
+    skip_failed_monitors: bool,
+    cursor: &CursorMode,
+    reader: &mut CursorReader,
+) -> Result<Capture, CaptureError> {
+    let mut capture = capture_with_cursor(backend, target, skip_failed_monitors, *cursor != CursorMode::Hidden, reader)?;
+    if let Some(pointer) = capture.cursor.as_ref() {
+        cursor_utils::composite_cursor(&mut capture.image, &capture.region, pointer, cursor);
+    }
+    Ok(capture)
+}
+
+// Come take_screenshot, ma il cursore non viene disegnato: se read_cursor è true resta in Capture::cursor,
+// da disegnare più tardi (es. sul ritaglio del fotogramma congelato)
+pub fn capture_with_cursor(
+    backend: &dyn CaptureBackend,
+    target: &CaptureTarget,
+    skip_failed_monitors: bool,
+    read_cursor: bool,
+    reader: &mut CursorReader,
+) -> Result<Capture, CaptureError> {
+    let mut capture = capture_target(backend, target, skip_failed_monitors)?;
+    if read_cursor {
+        capture.cursor = reader.read();
+    }
+    Ok(capture)
+}
+
+fn capture_target(
+    backend: &dyn CaptureBackend,
+    target: &CaptureTarget,
+    skip_failed_monitors: bool,
+) -> Result<Capture, CaptureError> {
+    // La posizione fisica di ogni monitor dipende dai vicini: il layout si calcola su tutti i monitor,
+    // anche quelli che poi vengono saltati
+    let monitors = backend.monitors()?;
+    let mut layout = DesktopLayout::new(&monitors);
+
+    if let CaptureTarget::Monitor(id) = target {
+        // Un solo monitor: niente composito, l'immagine è già quella giusta
+        let space = layout
+            .monitors
+            .iter()
+            .find(|m| m.monitor.id == *id)
+            .ok_or(CaptureError::MonitorCapture {
+                monitor: *id,
+                reason: "the display has been disconnected".to_string(),
+            })?;
+        let image = backend.capture(&space.monitor)?;
+        println!("Screenshot taken");
+        return Ok(Capture {
+            image,
+            region: space.physical,
+            skipped: vec![],
+            cursor: None,
+        });
+    }
+
+    let mut skipped = vec![];
+    let mut screen_images = vec![];
+    let started = Instant::now();
+    for (monitor, result) in capture_all(backend, monitors) {
+        match result {
+            Ok(image) => screen_images.push(ScreenImage { monitor, image }),
+            Err(e) if skip_failed_monitors => {
+                println!("Skipping display {}: {}", monitor.id, e);
+                skipped.push(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    println!("Captured {} displays in {:?}", screen_images.len(), started.elapsed());
+    if screen_images.is_empty() {
+        // Sono falliti tutti: non c'è niente da comporre
+        return Err(skipped.into_iter().next().unwrap_or(CaptureError::NoDisplays {
+            reason: "no display could be captured".to_string(),
+        }));
+    }
+
+    // Restano i monitor catturati, nello stesso ordine di screen_images
+    layout
+        .monitors
+        .retain(|space| screen_images.iter().any(|s| s.monitor.id == space.monitor.id));
+    let bounds = layout.bounds();
+    println!("Desktop: {:?}", bounds);
+
+    let placements: Vec<Placement<'_>> = layout
+        .monitors
         .iter()
-        .map(|s| (s.screen.display_info.y + s.screen.display_info.height as i32) * s.screen.display_info.scale_factor as i32)
-        .max()
-        .unwrap();
-
-    let offset = (x_min, y_min);
-    println!("Offset: {:?}", offset);
-    let size: (u32, u32);
-    size = ((x_max - x_min) as u32, (y_max - y_min) as u32);
-    println!("Size: {:?}", size);
-    img = RgbaImage::new(size.0, size.1);
-    for pixels in img.enumerate_pixels_mut() {
-        *pixels.2 = image::Rgba([0, 0, 0, 255]);
-    }
-    for screen_image in screen_images {
-        println!("Screen: {:?}", screen_image.screen.display_info);
-            let screenshot = image::io::Reader::new(Cursor::new(screen_image.image.to_png().unwrap()))
-                .with_guessed_format()
+        .zip(screen_images.iter())
+        .map(|(space, screen_image)| {
+            println!("Screen: {:?}", space);
+            let position = space.physical.relative_to(&bounds);
+            Placement {
+                x: position.x as u32,
+                y: position.y as u32,
+                image: &screen_image.image,
+            }
+        })
+        .collect();
+    let started = Instant::now();
+    let img = composite(bounds.width, bounds.height, &placements);
+    println!("Composited {} displays in {:?}", placements.len(), started.elapsed());
+
+    let (image, region) = crop_to_target(img, bounds, &layout, target)?;
+    println!("Screenshot taken");
+    Ok(Capture {
+        image,
+        region,
+        skipped,
+        cursor: None,
+    })
+}
+
+// Ritaglia da un'immagine del desktop (che copre bounds) la parte richiesta da target.
+// Serve sia a take_screenshot sia alla selezione sul fotogramma congelato.
+pub fn crop_to_target(
+    image: RgbaImage,
+    bounds: PhysicalRect,
+    layout: &DesktopLayout,
+    target: &CaptureTarget,
+) -> Result<(RgbaImage, PhysicalRect), CaptureError> {
+    let region = match target {
+        CaptureTarget::Desktop => bounds,
+        CaptureTarget::LogicalRegion(rect) => layout
+            .logical_rect_to_physical(*rect)
+            .ok_or(CaptureError::OutsideDisplays)?,
+        CaptureTarget::PhysicalRegion(rect) => *rect,
+        CaptureTarget::Monitor(id) => layout
+            .monitors
+            .iter()
+            .find(|m| m.monitor.id == *id)
+            .map(|m| m.physical)
+            .ok_or(CaptureError::MonitorCapture {
+                monitor: *id,
+                reason: "the display has been disconnected".to_string(),
+            })?,
+    };
+    if region == bounds {
+        return Ok((image, region));
+    }
+
+    // Solo la parte della regione che cade dentro il desktop
+    let region = region.intersect(&bounds).ok_or(CaptureError::OutsideDisplays)?;
+    let local = region.relative_to(&bounds);
+    println!("Cropping {:?}", region);
+    let cropped = image::imageops::crop_imm(
+        &image,
+        local.x as u32,
+        local.y as u32,
+        local.width,
+        local.height,
+    )
+    .to_image();
+    Ok((cropped, region))
+}
+
+// Cattura tutti i monitor, ognuno con il suo risultato
+fn capture_all(
+    backend: &dyn CaptureBackend,
+    monitors: Vec<Monitor>,
+) -> Vec<(Monitor, Result<RgbaImage, CaptureError>)> {
+    let results = backend.capture_all(&monitors);
+    monitors.into_iter().zip(results).collect()
+}
+
+// Un'immagine da copiare nel composito, con la posizione in pixel del suo angolo in alto a sinistra
+pub struct Placement<'a> {
+    pub x: u32,
+    pub y: u32,
+    pub image: &'a RgbaImage,
+}
+
+// Compone le immagini dei monitor copiandole riga per riga nel buffer finale, senza passare da PNG.
+// Il composito viene diviso in fasce orizzontali, e ogni fascia viene riempita da un thread diverso.
+// Quello che esce dal bordo viene tagliato, quello che non è coperto da nessun monitor resta nero.
+pub fn composite(width: u32, height: u32, placements: &[Placement<'_>]) -> RgbaImage {
+    let row_len = width as usize * 4;
+    if row_len == 0 || height == 0 {
+        return RgbaImage::new(width, height);
+    }
+    let mut buffer = [0u8, 0, 0, 255].repeat(width as usize * height as usize);
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let band_rows = (height as usize + threads - 1) / threads;
+
+    thread::scope(|scope| {
+        for (index, band) in buffer.chunks_mut(row_len * band_rows).enumerate() {
+            let band_y = (index * band_rows) as u32;
+            scope.spawn(move || blit_band(band, band_y, width, placements));
+        }
+    });
+
+    // Il buffer ha esattamente width * height pixel, from_raw non può fallire
+    RgbaImage::from_raw(width, height, buffer).unwrap()
+}
+
+fn blit_band(band: &mut [u8], band_y: u32, width: u32, placements: &[Placement<'_>]) {
+    let row_len = width as usize * 4;
+    let band_height = (band.len() / row_len) as u32;
+    for placement in placements {
+        if placement.x >= width {
+            continue;
+        }
+        let copy_len = placement.image.width().min(width - placement.x) as usize * 4;
+        let source_row_len = placement.image.width() as usize * 4;
+        let source = placement.image.as_raw();
+        let top = placement.y.max(band_y);
+        let bottom = (placement.y + placement.image.height()).min(band_y + band_height);
+        for y in top..bottom {
+            let source_start = (y - placement.y) as usize * source_row_len;
+            let target_start = (y - band_y) as usize * row_len + placement.x as usize * 4;
+            band[target_start..target_start + copy_len]
+                .copy_from_slice(&source[source_start..source_start + copy_len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::capture_utils::FakeBackend;
+
+    fn monitor(backend: &FakeBackend, id: u32) -> Monitor {
+        backend.monitors().unwrap().into_iter().find(|m| m.id == id).unwrap()
+    }
+
+    fn shoot(backend: &FakeBackend, target: CaptureTarget, skip_failed_monitors: bool) -> Result<Capture, CaptureError> {
+        take_screenshot(backend, &target, skip_failed_monitors, &CursorMode::Hidden, &mut CursorReader::default())
+    }
+
+    #[test]
+    fn desktop_places_each_monitor_at_its_origin() {
+        let backend = FakeBackend::dual_monitor();
+        let (left, right) = (monitor(&backend, 1), monitor(&backend, 2));
+        let capture = shoot(&backend, CaptureTarget::Desktop, false).unwrap();
+
+        assert_eq!(capture.image.dimensions(), (3840, 1080));
+        assert_eq!(capture.region, PhysicalRect { x: 0, y: 0, width: 3840, height: 1080 });
+        assert!(capture.skipped.is_empty());
+        assert_eq!(*capture.image.get_pixel(0, 0), FakeBackend::pattern_pixel(&left, 0, 0));
+        assert_eq!(*capture.image.get_pixel(1919, 1079), FakeBackend::pattern_pixel(&left, 1919, 1079));
+        assert_eq!(*capture.image.get_pixel(1920, 0), FakeBackend::pattern_pixel(&right, 0, 0));
+        assert_eq!(*capture.image.get_pixel(1925, 7), FakeBackend::pattern_pixel(&right, 5, 7));
+    }
+
+    #[test]
+    fn broken_monitor_is_skipped_when_allowed() {
+        let backend = FakeBackend::dual_monitor().failing_monitor(2);
+        let capture = shoot(&backend, CaptureTarget::Desktop, true).unwrap();
+
+        // Resta solo il primo monitor, e il secondo finisce tra quelli saltati
+        assert_eq!(capture.image.dimensions(), (1920, 1080));
+        assert_eq!(capture.region, PhysicalRect { x: 0, y: 0, width: 1920, height: 1080 });
+        assert!(matches!(capture.skipped.as_slice(), [CaptureError::MonitorCapture { monitor: 2, .. }]));
+        let left = monitor(&backend, 1);
+        assert_eq!(*capture.image.get_pixel(100, 200), FakeBackend::pattern_pixel(&left, 100, 200));
+    }
+
+    #[test]
+    fn broken_monitor_fails_the_capture_when_not_skipped() {
+        let backend = FakeBackend::dual_monitor().failing_monitor(2);
+        let result = shoot(&backend, CaptureTarget::Desktop, false);
+        assert!(matches!(result, Err(CaptureError::MonitorCapture { monitor: 2, .. })));
+    }
+
+    #[test]
+    fn every_monitor_broken_is_an_error_even_when_skipping() {
+        let backend = FakeBackend::dual_monitor().failing_monitor(1).failing_monitor(2);
+        let result = shoot(&backend, CaptureTarget::Desktop, true);
+        assert!(matches!(result, Err(CaptureError::MonitorCapture { monitor: 1, .. })));
+    }
+
+    #[test]
+    fn region_across_monitors_is_cropped() {
+        let backend = FakeBackend::dual_monitor();
+        let (left, right) = (monitor(&backend, 1), monitor(&backend, 2));
+        let rect = egui::Rect::from_min_max(egui::pos2(1900., 10.), egui::pos2(1940., 30.));
+        let capture = shoot(&backend, CaptureTarget::LogicalRegion(rect), false).unwrap();
+
+        assert_eq!(capture.image.dimensions(), (40, 20));
+        assert_eq!(capture.region, PhysicalRect { x: 1900, y: 10, width: 40, height: 20 });
+        assert_eq!(*capture.image.get_pixel(0, 0), FakeBackend::pattern_pixel(&left, 1900, 10));
+        assert_eq!(*capture.image.get_pixel(19, 19), FakeBackend::pattern_pixel(&left, 1919, 29));
+        assert_eq!(*capture.image.get_pixel(20, 0), FakeBackend::pattern_pixel(&right, 0, 10));
+    }
+
+    #[test]
+    fn region_on_a_skipped_monitor_is_outside_the_displays() {
+        let backend = FakeBackend::dual_monitor().failing_monitor(2);
+        let region = PhysicalRect { x: 2000, y: 0, width: 100, height: 100 };
+        let result = shoot(&backend, CaptureTarget::PhysicalRegion(region), true);
+        assert!(matches!(result, Err(CaptureError::OutsideDisplays)));
+    }
+
+    #[test]
+    fn single_monitor_keeps_its_native_image() {
+        let backend = FakeBackend::dual_monitor();
+        let right = monitor(&backend, 2);
+        let capture = shoot(&backend, CaptureTarget::Monitor(2), false).unwrap();
+
+        assert_eq!(capture.image.dimensions(), (1920, 1080));
+        assert_eq!(capture.region, PhysicalRect { x: 1920, y: 0, width: 1920, height: 1080 });
+        assert_eq!(*capture.image.get_pixel(3, 4), FakeBackend::pattern_pixel(&right, 3, 4));
+    }
+
+    #[test]
+    fn composite_clips_and_leaves_gaps_black() {
+        let source = RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 255]));
+        let placements = [Placement { x: 8, y: 2, image: &source }];
+        let image = composite(10, 5, &placements);
+
+        assert_eq!(image.dimensions(), (10, 5));
+        assert_eq!(*image.get_pixel(0, 0), image::Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(9, 4), image::Rgba([10, 20, 30, 255]));
+        assert_eq!(*image.get_pixel(7, 3), image::Rgba([0, 0, 0, 255]));
+    }
+
+    // Il vecchio percorso: ogni monitor codificato in PNG, decodificato e copiato nel composito
+    fn png_round_trip(backend: &FakeBackend) -> RgbaImage {
+        let monitors = backend.monitors().unwrap();
+        let layout = DesktopLayout::new(&monitors);
+        let bounds = layout.bounds();
+        let mut composite = RgbaImage::new(bounds.width, bounds.height);
+        for space in layout.monitors.iter() {
+            let mut png = vec![];
+            backend
+                .capture(&space.monitor)
                 .unwrap()
-                .decode()
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
                 .unwrap();
-            /*if screen_image.screen.display_info.scale_factor != 1.0 {
-                println!(
-                    "Scale factor: {}",
-                    screen_image.screen.display_info.scale_factor
-                );
-                let scaled_screenshot = screenshot.resize(
-                    (screenshot.width() as f32 / screen_image.screen.display_info.scale_factor)
-                        as u32,
-                    (screenshot.height() as f32 / screen_image.screen.display_info.scale_factor)
-                        as u32,
-                    image::imageops::FilterType::Nearest,
-                );
-                screenshot = scaled_screenshot;
-            }*/
-
-            let x = (screen_image.screen.display_info.x * screen_image.screen.display_info.scale_factor as i32 - offset.0) as u32;
-            let y = (screen_image.screen.display_info.y * screen_image.screen.display_info.scale_factor as i32 - offset.1) as u32;
-            if x + screenshot.width() <= img.width() && y + screenshot.height() <= img.height() {
-                    match img.copy_from(&screenshot, x, y) {
-                        Ok(_) => (),
-                        Err(e) => println!("Failed to copy screen image: {}", e),
-                    }
-            }   
-    }
-    if _screenshot_type.clone().unwrap() == ScreenshotType::PartialScreen{
-        let grab = _grabbed_area.clone().unwrap();
-        let x_start: i32;
-        let y_start: i32;
-        println!("Pixels per point: {}", _ctx.pixels_per_point());
-        if grab.starting_point.x > grab.ending_point.x {
-            x_start = (grab.ending_point.x * _ctx.pixels_per_point()) as i32 as i32 - offset.0 as i32;
-        } else {
-            x_start = (grab.starting_point.x * _ctx.pixels_per_point()) as i32 as i32 - offset.0 as i32;
+            let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+            let position = space.physical.relative_to(&bounds);
+            image::imageops::replace(&mut composite, &decoded, position.x as i64, position.y as i64);
         }
-        if grab.starting_point.y < grab.ending_point.y {
-            y_start = (grab.starting_point.y * _ctx.pixels_per_point()) as i32 as i32 - offset.1 as i32;
-        } else {
-            y_start = (grab.ending_point.y * _ctx.pixels_per_point()) as i32 as i32 - offset.1 as i32;
+        composite
+    }
+
+    // Tre monitor 4K affiancati. Da lanciare con: cargo test --release -- --ignored --nocapture composite_benchmark
+    #[test]
+    #[ignore]
+    fn composite_benchmark() {
+        let monitors = (0..3)
+            .map(|i| Monitor {
+                id: i + 1,
+                output: None,
+                x: 3840 * i as i32,
+                y: 0,
+                width: 3840,
+                height: 2160,
+                scale_factor: 1.0,
+                is_primary: i == 0,
+            })
+            .collect();
+        let backend = FakeBackend::new(monitors);
+        let runs = 5;
+
+        let started = Instant::now();
+        for _ in 0..runs {
+            png_round_trip(&backend);
         }
-        let clone = img.clone();
-        println!("grab dimensions: {:?}", grab.dimension_selected);
-        let (real_x, real_y) = (grab.dimension_selected.x * _ctx.pixels_per_point(), grab.dimension_selected.y * _ctx.pixels_per_point());
-        let cropped = image::imageops::crop_imm(&clone, x_start as u32, y_start as u32, real_x as u32, real_y as u32);
-        println!("cropped dimensions: {:?}", cropped.dimensions());
-        let cropped_to_img = cropped.to_image();
-        img = RgbaImage::new(real_x as u32, real_y as u32);
-        match img.copy_from(&cropped_to_img, 0, 0) {
-            Ok(_) => (),
-            Err(e) => println!("Failed to copy screen image: {}", e),
+        let round_trip = started.elapsed() / runs;
+
+        let started = Instant::now();
+        for _ in 0..runs {
+            shoot(&backend, CaptureTarget::Desktop, false).unwrap();
         }
-    }
-    let return_img: Option<RgbaImage> = Some(img);
-    if return_img.is_some() {
-        println!("Screenshot taken");
-        return return_img;
-    } else {
-        println!("Screenshot failed");
-        return None;
+        let direct = started.elapsed() / runs;
+
+        println!("PNG round-trip: {:?} per capture, direct blit: {:?} per capture", round_trip, direct);
+        assert_eq!(png_round_trip(&backend), shoot(&backend, CaptureTarget::Desktop, false).unwrap().image);
+        assert!(direct < round_trip);
     }
 }
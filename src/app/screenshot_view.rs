@@ -1,7 +1,39 @@
+use super::capture_utils::{CaptureBackend, Monitor};
+use super::capture_utils::CaptureError;
+use super::color_utils::{self, ColorFormat, PickedColor};
+use super::coords_utils::{self, DesktopLayout, PhysicalRect};
+use super::cursor_utils::{self, CursorImage, CursorMode};
+use super::image_utils;
+use super::interval::IntervalOptions;
+use super::loupe_utils;
+use super::screenshot_utils::{self, Capture, CaptureTarget};
+use super::preset_utils::{self, RegionPreset};
+use super::recording::RecordingOptions;
+use super::selection_utils::{Constraint, Handle, Selection};
+use super::window_utils::{self, WindowInfo};
 use super::ScreenshotType;
 use crate::app;
 use display_info::DisplayInfo;
 use egui::*;
+use image::RgbaImage;
+
+// Cosa diventa la regione selezionata quando la si conferma
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionMode {
+    Screenshot,
+    Scrolling, // Cattura a scorrimento, vedi scroll_capture
+    Recording, // Registrazione animata, vedi recording
+}
+
+// Il desktop catturato prima della selezione. Se c'è, la maschera lo mostra a tutto schermo
+// e la selezione viene ritagliata da qui, invece di fare una nuova cattura.
+#[derive(Clone)]
+pub struct FrozenFrame {
+    pub image: RgbaImage,
+    pub region: PhysicalRect, // La parte del desktop virtuale coperta da image
+    pub cursor: Option<CursorImage>, // Il cursore al momento della cattura, non disegnato in image
+    texture: TextureHandle,
+}
 
 #[derive(Clone)]
 pub struct ScreenshotView {
@@ -14,6 +46,31 @@ pub struct ScreenshotView {
     pub finished_selection: bool,
     pub screen_selected: u32,
     pub timer_delay: i32,
+    pub skip_failed_monitors: bool, // Se un monitor non si riesce a catturare, fai lo screenshot degli altri
+    pub window_origin: Pos2, // Dove si trova la maschera sul desktop, in punti logici
+    pub monitors: Vec<Monitor>, // I monitor tra cui scegliere con ScreenshotType::Monitor
+    pub window_mode: bool, // Invece di trascinare una selezione, si sceglie una finestra
+    pub windows: Vec<WindowInfo>,
+    pub include_decorations: bool,
+    pub include_shadow: bool,
+    pub frozen_frame: bool, // Prima cattura, poi fai scegliere la regione sul fotogramma congelato
+    pub frozen: Option<FrozenFrame>,
+    pub freeze_requested: bool, // L'utente ha appena attivato frozen_frame: l'app deve catturare il fotogramma
+    pub show_loupe: bool,
+    pub eyedropper: bool, // Un clic prende il colore del pixel invece di tracciare una selezione
+    pub color_format: ColorFormat,
+    pub pick_sets_stroke: bool,
+    pub picked: Option<PickedColor>, // L'ultimo colore preso: l'app lo copia negli appunti
+    pub selection: Option<Selection>, // La regione tracciata, che si può ancora modificare prima di confermarla
+    pub min_selection_size: f32, // Le selezioni più piccole di così (in pixel fisici) vengono scartate
+    pub presets: Vec<RegionPreset>,
+    pub preset: Option<usize>, // Il preset scelto in presets, None per una selezione libera
+    pub last_region: Option<PhysicalRect>, // L'ultima regione catturata, in pixel fisici del desktop
+    pub region_mode: RegionMode,
+    pub auto_scroll: bool,
+    pub recording_options: RecordingOptions,
+    pub interval_mode: bool, // Lo screenshot scelto viene ripetuto a intervalli, vedi interval
+    pub interval_options: IntervalOptions,
 }
 
 impl Default for ScreenshotView {
@@ -28,6 +85,31 @@ impl Default for ScreenshotView {
             finished_selection: false,
             screen_selected: 0,
             timer_delay: 0,
+            skip_failed_monitors: true,
+            window_origin: Pos2::ZERO,
+            monitors: vec![],
+            window_mode: false,
+            windows: vec![],
+            include_decorations: true,
+            include_shadow: false,
+            frozen_frame: false,
+            frozen: None,
+            freeze_requested: false,
+            show_loupe: true,
+            eyedropper: false,
+            color_format: ColorFormat::Hex,
+            pick_sets_stroke: false,
+            picked: None,
+            selection: None,
+            min_selection_size: 1.0,
+            presets: preset_utils::load_presets(),
+            preset: None,
+            last_region: None,
+            region_mode: RegionMode::Screenshot,
+            auto_scroll: false,
+            recording_options: RecordingOptions::default(),
+            interval_mode: false,
+            interval_options: IntervalOptions::default(),
         }
     }
 }
@@ -46,6 +128,8 @@ impl ScreenshotView {
         ctx.set_cursor_icon(CursorIcon::Crosshair);
         let width = _frame.info().window_info.monitor_size.unwrap().x;
         let height = _frame.info().window_info.monitor_size.unwrap().y;
+        // Le posizioni del puntatore sono relative alla finestra: servono in coordinate del desktop
+        self.window_origin = _frame.info().window_info.position.unwrap_or(Pos2::ZERO);
 
         if _type.is_some() {
             _frame.set_visible(false);
@@ -62,69 +146,35 @@ impl ScreenshotView {
             // ui.label("Screenshot");
             // let rect = ui.max_rect();
             let rect = egui::Rect::from_min_size(egui::Pos2::ZERO, egui::Vec2::new(width, height));
+            if let Some(frozen) = self.frozen.as_ref() {
+                // Disegna la parte del fotogramma congelato che sta sotto la finestra
+                let layout = DesktopLayout::new(&self.monitors);
+                let window_rect = rect.translate(self.window_origin.to_vec2());
+                if let Some(visible) = layout.logical_rect_to_physical(window_rect) {
+                    let local = visible.relative_to(&frozen.region);
+                    let size = vec2(frozen.region.width as f32, frozen.region.height as f32);
+                    let uv = Rect::from_min_size(
+                        pos2(local.x as f32 / size.x, local.y as f32 / size.y),
+                        vec2(local.width as f32 / size.x, local.height as f32 / size.y),
+                    );
+                    ui.painter().image(frozen.texture.id(), rect, uv, Color32::WHITE);
+                }
+            }
             ui.painter()
                 .rect_filled(rect, 0.0, Color32::from_rgba_unmultiplied(0, 0, 0, 30));
-            let response = ui.allocate_response(rect.size(), Sense::drag());
-            let bound = response.rect.size();
-            if response.drag_started() {
-                self.starting_point = ctx.pointer_interact_pos().unwrap();
-                self.started_selection = true;
+            let response = ui.allocate_response(rect.size(), Sense::click_and_drag());
+            if self.show_loupe || self.eyedropper {
+                self.loupe_ui(ctx, &response, rect);
             }
-            if response.dragged() {
-                self.middle_point = ctx.pointer_interact_pos().unwrap();
-                if self.middle_point != self.starting_point && self.started_selection {
-                    let selected_area = Rect::from_two_pos(self.starting_point, self.middle_point);
-                    let selected = ui.painter().add(Shape::Noop);
-                    /*let where_is_selected = */
-                    ui.painter().set(
-                        selected,
-                        epaint::RectShape {
-                            rounding: Rounding::none(),
-                            fill: Color32::from_rgba_unmultiplied(255, 255, 255, 0),
-                            stroke: Stroke::new(1.0, Color32::WHITE),
-                            rect: selected_area,
-                        },
-                    );
-                }
+            if self.eyedropper {
+                self.eyedropper_ui(ctx, &response);
+                return;
             }
-            if response.drag_released() {
-                self.ending_point = ctx.pointer_interact_pos().unwrap();
-
-                self.dimension_selected.x = self.ending_point.x - self.starting_point.x;
-                self.dimension_selected.y = self.ending_point.y - self.starting_point.y;
-
-                if self.dimension_selected.x.is_sign_negative()
-                    || self.dimension_selected.y.is_sign_negative()
-                {
-                    self.dimension_selected.x = self.dimension_selected.x.abs();
-                    self.dimension_selected.y = self.dimension_selected.y.abs();
-                    let tmp = self.starting_point;
-                    self.starting_point = self.ending_point;
-                    self.ending_point = tmp;
-                }
-
-                if self.dimension_selected.x > 50.0 && self.dimension_selected.y > 50.0 {
-                    if self.dimension_selected.x > bound.x {
-                        self.dimension_selected.x = bound.x;
-                    }
-                    if self.dimension_selected.y > bound.y {
-                        self.dimension_selected.y = bound.y;
-                    }
-                    self.finished_selection = true;
-                    let disp = DisplayInfo::from_point(
-                        (self.starting_point.x as u32).try_into().unwrap(),
-                        (self.starting_point.y as u32).try_into().unwrap(),
-                    )
-                    .unwrap();
-                    self.screen_selected = disp.id;
-                    *_type = Some(ScreenshotType::PartialScreen);
-
-                } else {
-                    println!("ups!");
-                    self.started_selection = false;
-                }
-
+            if self.window_mode {
+                self.window_picker_ui(ui, &response, _type);
+                return;
             }
+            self.selection_ui(ctx, ui, &response, rect, _type);
         });
 
         Window::new("Screenshot")
@@ -136,6 +186,8 @@ impl ScreenshotView {
                 ui.horizontal(|ui| {
                     ui.horizontal(|ui| {
                         if ui.button("◀ Go back").clicked() {
+                            self.frozen = None;
+                            self.selection = None;
                             _frame.set_window_size(vec2(640.0, 400.0));
                             _frame.set_centered();
                             *_view = app::Views::Home;
@@ -145,31 +197,501 @@ impl ScreenshotView {
                             _frame.set_window_size(vec2(600., 420.));
                         }
 
-                        if ui.add_enabled(false, Button::new("⛶")).clicked() {
-                            *_type = Some(ScreenshotType::PartialScreen);
+                        if ui
+                            .add_enabled(self.selection.is_some(), Button::new("⛶"))
+                            .on_hover_text("Capture the selection (Enter)")
+                            .clicked()
+                        {
+                            self.confirm_selection(_type);
                             // println!("PartialScreen button pressed");
                         }
                         ui.separator();
 
+                        // I preset vincolano la selezione a una dimensione fissa o a un rapporto
+                        let previous_preset = self.preset;
+                        let preset_label = self
+                            .preset
+                            .and_then(|i| self.presets.get(i))
+                            .map(|p| p.label())
+                            .unwrap_or_else(|| "Free".to_string());
+                        ComboBox::from_id_source("region_preset")
+                            .selected_text(preset_label)
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut self.preset, None, "Free");
+                                for (i, preset) in self.presets.iter().enumerate() {
+                                    ui.selectable_value(&mut self.preset, Some(i), preset.label());
+                                }
+                            });
+                        if self.preset != previous_preset {
+                            self.apply_preset(ctx, Rect::from_min_size(Pos2::ZERO, vec2(width, height)));
+                        }
+                        ui.separator();
+
                         if ui.button("🖵 Fullscreen").clicked() {
                             *_type = Some(ScreenshotType::FullScreen);
                             // println!("FullScreen button pressed");
                         }
                         ui.separator();
 
+                        // Cattura soltanto il monitor scelto, alla sua risoluzione nativa
+                        let selected_label = self
+                            .monitors
+                            .iter()
+                            .find(|m| m.id == self.screen_selected)
+                            .map(monitor_label)
+                            .unwrap_or_else(|| "No monitor".to_string());
+                        ComboBox::from_id_source("monitor_picker")
+                            .selected_text(selected_label)
+                            .show_ui(ui, |ui| {
+                                for monitor in self.monitors.iter() {
+                                    ui.selectable_value(&mut self.screen_selected, monitor.id, monitor_label(monitor));
+                                }
+                            });
+                        if ui
+                            .add_enabled(!self.monitors.is_empty(), Button::new("🖥 This monitor"))
+                            .clicked()
+                        {
+                            *_type = Some(ScreenshotType::Monitor(self.screen_selected));
+                        }
+                        ui.separator();
+
+                        if ui
+                            .selectable_label(self.window_mode, "🗔 Window")
+                            .on_hover_text("Hover a window and click it to capture it")
+                            .clicked()
+                        {
+                            self.window_mode = !self.window_mode;
+                            if self.window_mode {
+                                self.windows = window_utils::list_windows();
+                            }
+                        }
+                        if self.window_mode {
+                            if self.windows.is_empty() {
+                                ui.colored_label(Color32::LIGHT_RED, "No windows found");
+                            }
+                            ui.checkbox(&mut self.include_decorations, "Decorations");
+                            ui.checkbox(&mut self.include_shadow, "Shadow");
+                        }
+                        ui.separator();
+
                         
+                        if ui
+                            .checkbox(&mut self.frozen_frame, "❄ Frozen frame")
+                            .on_hover_text("Capture the screen first, then select on the frozen image")
+                            .changed()
+                        {
+                            self.frozen = None;
+                            self.freeze_requested = self.frozen_frame;
+                        }
+                        ui.separator();
+
+                        // Cosa fare con la regione: uno screenshot, una cattura a scorrimento o una registrazione
+                        ui.selectable_value(&mut self.region_mode, RegionMode::Screenshot, "📷 Region");
+                        ui.selectable_value(&mut self.region_mode, RegionMode::Scrolling, "📜 Scrolling")
+                            .on_hover_text("Select the region, then scroll down: the frames are stitched into one tall image");
+                        ui.selectable_value(&mut self.region_mode, RegionMode::Recording, "⏺ Record")
+                            .on_hover_text("Record the region as an animated GIF or APNG");
+                        match self.region_mode {
+                            RegionMode::Scrolling if cfg!(target_os = "linux") => {
+                                ui.checkbox(&mut self.auto_scroll, "Auto-scroll")
+                                    .on_hover_text("Scroll automatically until the end of the page (X11 only)");
+                            }
+                            RegionMode::Recording => {
+                                let options = &mut self.recording_options;
+                                ui.add(DragValue::new(&mut options.fps).clamp_range(1..=30).suffix(" FPS"));
+                                ui.add(DragValue::new(&mut options.max_frames).clamp_range(1..=3000).prefix("Max frames: "))
+                                    .on_hover_text("The recording stops by itself after this many frames");
+                                ui.checkbox(&mut options.dedup, "Skip repeated frames")
+                                    .on_hover_text("Frames identical to the previous one make it last longer instead of being added");
+                            }
+                            _ => {}
+                        }
+                        ui.separator();
+
+                        ui.checkbox(&mut self.interval_mode, "⏱ Interval")
+                            .on_hover_text("Repeat the screenshot at regular intervals, saving every shot");
+                        if self.interval_mode {
+                            let options = &mut self.interval_options;
+                            ui.add(DragValue::new(&mut options.every_secs).clamp_range(1..=86400).prefix("Every ").suffix(" s"));
+                            ui.add(DragValue::new(&mut options.max_shots).clamp_range(0..=100000).prefix("Shots: "))
+                                .on_hover_text("0 keeps going until you stop it");
+                            ui.checkbox(&mut options.skip_identical, "Skip identical shots");
+                        }
+                        ui.separator();
+
+                        ui.checkbox(&mut self.show_loupe, "🔍 Loupe");
+                        if ui
+                            .selectable_label(self.eyedropper, "💧 Eyedropper")
+                            .on_hover_text("Click a pixel to copy its color")
+                            .clicked()
+                        {
+                            self.eyedropper = !self.eyedropper;
+                            // I colori si leggono dal fotogramma congelato
+                            if self.eyedropper && !self.frozen_frame {
+                                self.frozen_frame = true;
+                                self.freeze_requested = true;
+                            }
+                        }
+                        if self.eyedropper {
+                            color_utils::ui_picker_options(ui, "overlay_color_format", &mut self.color_format, &mut self.pick_sets_stroke);
+                        }
+                        ui.separator();
+
                         let mut _timer_delay = self.timer_delay;
                         ui.add(egui::DragValue::new(&mut _timer_delay).speed(50).max_decimals(2).clamp_range(0..=10000).prefix("Delay Timer (ms): "));
                         // println!("timer_delay: {}", _timer_delay);
                         self.timer_delay = _timer_delay;
+                        ui.separator();
+
+                        ui.checkbox(&mut self.skip_failed_monitors, "Skip failing monitors")
+                            .on_hover_text("If a monitor can't be captured, take the screenshot of the others instead of failing");
                     });
                 });
             });
             
     }
 
+    pub fn freeze(&mut self, ctx: &Context, capture: Capture) {
+        let texture = ctx.load_texture(
+            "frozen_frame",
+            image_utils::load_image_from_memory(capture.image.clone()),
+            TextureOptions::NEAREST,
+        );
+        self.frozen = Some(FrozenFrame {
+            image: capture.image,
+            region: capture.region,
+            cursor: capture.cursor,
+            texture,
+        });
+    }
+
+    // Ritaglia target dal fotogramma congelato, come farebbe take_screenshot con una nuova cattura:
+    // il cursore di quel momento viene disegnato sul ritaglio come chiede cursor
+    pub fn crop_frozen(&self, target: &CaptureTarget, cursor: &CursorMode) -> Option<Result<Capture, CaptureError>> {
+        let frozen = self.frozen.as_ref()?;
+        let layout = DesktopLayout::new(&self.monitors);
+        Some(
+            screenshot_utils::crop_to_target(frozen.image.clone(), frozen.region, &layout, target).map(
+                |(mut image, region)| {
+                    if let Some(pointer) = frozen.cursor.as_ref() {
+                        cursor_utils::composite_cursor(&mut image, &region, pointer, cursor);
+                    }
+                    Capture {
+                        image,
+                        region,
+                        skipped: vec![],
+                        cursor: frozen.cursor.clone(),
+                    }
+                },
+            ),
+        )
+    }
+
+    // La lente di ingrandimento accanto al puntatore, disegnata sopra tutto il resto
+    fn loupe_ui(&self, ctx: &Context, response: &Response, screen: Rect) {
+        if let Some(pointer) = response.hover_pos() {
+            let layout = DesktopLayout::new(&self.monitors);
+            let origin = self.window_origin.to_vec2();
+            if let Some((x, y)) = layout.logical_to_physical(pointer + origin) {
+                // La dimensione della selezione, in pixel fisici
+                let selection = self
+                    .selection
+                    .as_ref()
+                    .and_then(|s| layout.logical_rect_to_physical(s.rect.translate(origin)))
+                    .map(|r| (r.width, r.height));
+                let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("loupe")));
+                loupe_utils::paint_loupe(
+                    &painter,
+                    pointer,
+                    screen,
+                    (x.floor() as i32, y.floor() as i32),
+                    selection,
+                    self.frozen.as_ref(),
+                );
+            }
+        }
+    }
+
+    // Con il contagocce un clic prende il colore del pixel sotto il puntatore, dal fotogramma congelato
+    fn eyedropper_ui(&mut self, ctx: &Context, response: &Response) {
+        if !response.clicked() {
+            return;
+        }
+        let (Some(pointer), Some(frozen)) = (response.interact_pointer_pos(), self.frozen.as_ref()) else {
+            return;
+        };
+        let layout = DesktopLayout::new(&self.monitors);
+        if let Some((x, y)) = layout.logical_to_physical(pointer + self.window_origin.to_vec2()) {
+            if let Some(color) = loupe_utils::sample(frozen, x.floor() as i32, y.floor() as i32) {
+                self.picked = Some(PickedColor::new(color, self.color_format, self.pick_sets_stroke));
+                ctx.request_repaint();
+            }
+        }
+    }
+
+    // Traccia, modifica e conferma la selezione. Trascinando fuori dalla selezione se ne traccia una nuova,
+    // trascinando le maniglie o l'interno la si ridimensiona o sposta. Le frecce la spostano di un pixel
+    // (dieci con Shift, Ctrl per ridimensionarla), Invio o doppio clic la confermano, Esc la annulla.
+    fn selection_ui(
+        &mut self,
+        ctx: &Context,
+        ui: &mut Ui,
+        response: &Response,
+        screen: Rect,
+        _type: &mut Option<ScreenshotType>,
+    ) {
+        if response.drag_started() {
+            if let Some(pointer) = ctx.pointer_interact_pos() {
+                let handle = self.selection.as_ref().and_then(|s| s.handle_at(pointer));
+                match (self.selection.as_mut(), handle) {
+                    (Some(selection), Some(handle)) => selection.begin_drag(handle, pointer),
+                    (Some(selection), None) if selection.is_fixed_size() => {
+                        // Con una dimensione fissa non si traccia: il rettangolo salta sotto il puntatore
+                        selection.rect = Rect::from_center_size(pointer, selection.rect.size());
+                        selection.begin_drag(Handle::Move, pointer);
+                    }
+                    _ => {
+                        // Una nuova selezione è un rettangolo vuoto di cui si trascina l'angolo in basso a destra
+                        let constraint = self.constraint(ctx, pointer);
+                        let mut selection = Selection::with_constraint(Rect::from_min_max(pointer, pointer), constraint);
+                        selection.begin_drag(Handle::BottomRight, pointer);
+                        self.selection = Some(selection);
+                        self.starting_point = pointer;
+                        self.started_selection = true;
+                    }
+                }
+            }
+        }
+        if response.dragged() {
+            if let (Some(selection), Some(pointer)) = (self.selection.as_mut(), ctx.pointer_interact_pos()) {
+                selection.drag_to(pointer);
+            }
+        }
+        if response.drag_released() {
+            // Una dimensione fissa portata su un monitor con un'altra scala cambia dimensione in punti
+            let fixed_size = self.selection.as_ref().filter(|s| s.is_fixed_size()).map(|s| s.rect.min);
+            let constraint = fixed_size.map(|min| self.constraint(ctx, min));
+            if let Some(selection) = self.selection.as_mut() {
+                selection.end_drag();
+                if let Some(constraint) = constraint {
+                    *selection = Selection::with_constraint(selection.rect, constraint);
+                }
+                // Una dimensione fissa non si rimpicciolisce contro i bordi dello schermo
+                if !selection.is_fixed_size() {
+                    selection.rect = selection.rect.intersect(screen);
+                    if selection.is_smaller_than(self.min_selection_size, ctx.pixels_per_point()) {
+                        println!("Selection smaller than {} px, discarded", self.min_selection_size);
+                        self.selection = None;
+                        self.started_selection = false;
+                    }
+                }
+            }
+        }
+
+        let Some(selection) = self.selection.as_mut() else {
+            return;
+        };
+
+        // Un pixel fisico, in punti
+        let pixel = 1.0 / ctx.pixels_per_point();
+        let (delta, resize, confirm, cancel) = ctx.input(|i| {
+            let step = if i.modifiers.shift { 10.0 * pixel } else { pixel };
+            let mut delta = Vec2::ZERO;
+            if i.key_pressed(Key::ArrowLeft) {
+                delta.x -= step;
+            }
+            if i.key_pressed(Key::ArrowRight) {
+                delta.x += step;
+            }
+            if i.key_pressed(Key::ArrowUp) {
+                delta.y -= step;
+            }
+            if i.key_pressed(Key::ArrowDown) {
+                delta.y += step;
+            }
+            (delta, i.modifiers.command, i.key_pressed(Key::Enter), i.key_pressed(Key::Escape))
+        });
+        if delta != Vec2::ZERO && !selection.is_dragging() {
+            selection.nudge(delta, resize);
+        }
+        if let Some(hover) = response.hover_pos() {
+            selection.set_cursor(ctx, hover);
+        }
+        selection.paint(ui.painter());
+
+        let double_clicked = response.double_clicked()
+            && response.hover_pos().map_or(false, |p| selection.rect.contains(p));
+        let bar_pos = selection.rect.right_bottom() + vec2(0.0, 8.0);
+        let mut confirm_clicked = false;
+        let mut cancel_clicked = false;
+        if !selection.is_dragging() {
+            Area::new("selection_buttons")
+                .order(Order::Foreground)
+                .fixed_pos(bar_pos)
+                .pivot(Align2::RIGHT_TOP)
+                .show(ctx, |ui| {
+                    Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            confirm_clicked = ui.button("✔").on_hover_text("Capture (Enter)").clicked();
+                            cancel_clicked = ui.button("✖").on_hover_text("Cancel (Esc)").clicked();
+                        });
+                    });
+                });
+        }
+
+        if confirm || confirm_clicked || double_clicked {
+            self.confirm_selection(_type);
+        } else if cancel || cancel_clicked {
+            self.selection = None;
+            self.started_selection = false;
+        }
+    }
+
+    // Il vincolo del preset scelto per una selezione in at (coordinate della maschera). Le dimensioni fisse
+    // sono in pixel fisici: vanno convertite con la scala del monitor su cui si trova la selezione.
+    fn constraint(&self, ctx: &Context, at: Pos2) -> Constraint {
+        let scale = DesktopLayout::new(&self.monitors)
+            .monitor_at_logical(at + self.window_origin.to_vec2())
+            .map_or(ctx.pixels_per_point(), |m| m.monitor.scale_factor);
+        self.preset
+            .and_then(|i| self.presets.get(i))
+            .map_or(Constraint::Free, |p| p.constraint(scale))
+    }
+
+    // Applica il preset appena scelto: una dimensione fissa compare subito al centro dello schermo,
+    // un rapporto si applica alla prossima selezione tracciata
+    fn apply_preset(&mut self, ctx: &Context, screen: Rect) {
+        self.selection = match self.constraint(ctx, screen.center()) {
+            Constraint::FixedSize(size) => Some(Selection::with_constraint(
+                Rect::from_center_size(screen.center(), size),
+                Constraint::FixedSize(size),
+            )),
+            _ => None,
+        };
+    }
+
+    // Fissa la selezione corrente e chiede lo screenshot della regione
+    fn confirm_selection(&mut self, _type: &mut Option<ScreenshotType>) {
+        let Some(selection) = self.selection.take() else {
+            return;
+        };
+        let layout = DesktopLayout::new(&self.monitors);
+        let origin = self.window_origin.to_vec2();
+        let fixed_size = self
+            .preset
+            .and_then(|i| self.presets.get(i))
+            .filter(|p| p.kind == preset_utils::PresetKind::FixedSize)
+            .map(|p| (p.width, p.height));
+
+        // La regione esatta in pixel fisici, se serve: la dimensione deve essere proprio quella del preset,
+        // e la cattura a scorrimento ripete la stessa regione a ogni fotogramma
+        let physical = match fixed_size {
+            Some((width, height)) => layout.logical_to_physical(selection.rect.min + origin).map(|(x, y)| PhysicalRect {
+                x: x.round() as i32,
+                y: y.round() as i32,
+                width,
+                height,
+            }),
+            None => layout.logical_rect_to_physical(selection.rect.translate(origin)),
+        };
+
+        if fixed_size.is_some() {
+            // Resta dov'è, pronta per la prossima cattura
+            self.selection = Some(selection.clone());
+        }
+        self.starting_point = selection.rect.min;
+        self.ending_point = selection.rect.max;
+        self.dimension_selected = selection.rect.size();
+        self.started_selection = false;
+        self.finished_selection = true;
+        if let Ok(disp) = DisplayInfo::from_point(
+            self.starting_point.x as i32,
+            self.starting_point.y as i32,
+        ) {
+            self.screen_selected = disp.id;
+        }
+
+        *_type = match physical {
+            Some(region) if self.region_mode == RegionMode::Scrolling => Some(ScreenshotType::Scrolling(region)),
+            Some(region) if self.region_mode == RegionMode::Recording => Some(ScreenshotType::Recording(region)),
+            Some(region) if fixed_size.is_some() => Some(ScreenshotType::Region(region)),
+            _ => Some(ScreenshotType::PartialScreen),
+        };
+    }
+
+    // Evidenzia la finestra sotto il puntatore, e la sceglie quando l'utente clicca
+    fn window_picker_ui(&mut self, ui: &mut Ui, response: &Response, _type: &mut Option<ScreenshotType>) {
+        let layout = DesktopLayout::new(&self.monitors);
+        if let Some(pointer) = response.hover_pos() {
+            let desktop_pointer = pointer + self.window_origin.to_vec2();
+            if let Some((x, y)) = layout.logical_to_physical(desktop_pointer) {
+                if let Some(window) = window_utils::window_at(
+                    &self.windows,
+                    x.floor() as i32,
+                    y.floor() as i32,
+                    self.include_decorations,
+                    self.include_shadow,
+                ) {
+                    let bounds = window.bounds(self.include_decorations, self.include_shadow);
+                    if let Some(rect) = layout.physical_rect_to_logical(&bounds) {
+                        let rect = rect.translate(-self.window_origin.to_vec2());
+                        ui.painter().rect(
+                            rect,
+                            0.0,
+                            Color32::from_rgba_unmultiplied(18, 160, 215, 40),
+                            Stroke::new(2.0, Color32::from_rgb(18, 160, 215)),
+                        );
+                        ui.painter().text(
+                            rect.left_top() + vec2(8.0, 8.0),
+                            Align2::LEFT_TOP,
+                            &window.title,
+                            FontId::proportional(14.0),
+                            Color32::WHITE,
+                        );
+                    }
+                    if response.clicked() {
+                        *_type = Some(ScreenshotType::Window(bounds));
+                    }
+                }
+            }
+        }
+    }
+
+    // Aggiorna la lista dei monitor. Se il monitor scelto non c'è più, sceglie quello su cui si trova
+    // la maschera, o altrimenti il principale.
+    pub fn refresh_monitors(&mut self, backend: &dyn CaptureBackend) {
+        self.monitors = backend.monitors().unwrap_or_default();
+        if !self.monitors.iter().any(|m| m.id == self.screen_selected) {
+            let origin = self.window_origin;
+            let under_window = self.monitors.iter().find(|m| coords_utils::logical_rect(m).contains(origin));
+            let primary = self.monitors.iter().find(|m| m.is_primary);
+            if let Some(monitor) = under_window.or(primary).or(self.monitors.first()) {
+                self.screen_selected = monitor.id;
+            }
+        }
+    }
+
+    // La selezione in punti logici del desktop, qualunque sia la direzione in cui è stata trascinata
+    pub fn selected_rect(&self) -> Rect {
+        Rect::from_two_pos(self.starting_point, self.ending_point).translate(self.window_origin.to_vec2())
+    }
+
     pub fn get_timer_delay(&self) -> i32 {
         self.timer_delay
     }
 
 }
+
+// Nome, risoluzione nativa e posizione del monitor, come vengono mostrati nel selettore
+fn monitor_label(monitor: &Monitor) -> String {
+    let (width, height) = coords_utils::physical_size(monitor);
+    format!(
+        "{}{}: {}×{} at ({}, {})",
+        monitor.name(),
+        if monitor.is_primary { " (primary)" } else { "" },
+        width,
+        height,
+        monitor.x,
+        monitor.y,
+    )
+}
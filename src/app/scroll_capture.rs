@@ -0,0 +1,309 @@
+use super::capture_utils::{CaptureBackend, CaptureError};
+use super::coords_utils::PhysicalRect;
+use super::cursor_utils::{CursorMode, CursorReader};
+use super::screenshot_utils::{self, CaptureTarget};
+use super::stitch_utils::{Overlap, Stitcher};
+use egui::*;
+use image::RgbaImage;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Ogni quanto si cattura la regione mentre si scorre
+const FRAME_INTERVAL: Duration = Duration::from_millis(200);
+// Prima di iniziare si aspetta che la maschera sparisca dallo schermo
+const MIN_START_DELAY: Duration = Duration::from_millis(300);
+// Durante le attese si controlla così spesso se l'utente ha premuto Done o Cancel
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(20);
+// Con lo scorrimento automatico, dopo tanti fotogrammi uguali si è arrivati in fondo alla pagina
+const UNCHANGED_FRAMES_AT_END: u32 = 3;
+// Oltre questa altezza ci si ferma comunque: l'immagine starebbe tutta in memoria
+const MAX_HEIGHT: u32 = 30_000;
+
+pub const CONTROL_WINDOW_SIZE: Vec2 = vec2(460.0, 48.0);
+
+pub enum ScrollEvent {
+    Progress { frames: u32, height: u32, missed: u32 },
+    Finished(Result<Option<RgbaImage>, CaptureError>), // None se ci si è fermati prima del primo fotogramma
+}
+
+pub enum ScrollAction {
+    None,
+    Stop,   // Tieni quello che è stato catturato finora
+    Cancel, // Butta via tutto
+}
+
+// Una cattura a scorrimento: un thread cattura la regione a intervalli regolari e unisce i fotogrammi
+// con stitch_utils, mentre l'utente (o lo scorrimento automatico) fa scorrere il contenuto verso il basso.
+pub struct ScrollCapture {
+    pub region: PhysicalRect,
+    receiver: mpsc::Receiver<ScrollEvent>,
+    stop: Arc<AtomicBool>,
+    pub frames: u32,
+    pub height: u32,
+    pub missed: u32, // Fotogrammi scartati perché senza sovrapposizione: si sta scorrendo troppo veloce
+}
+
+impl ScrollCapture {
+    pub fn start(
+        ctx: &Context,
+        backend: &Arc<dyn CaptureBackend>,
+        region: PhysicalRect,
+        delay: Duration,
+        auto_scroll: bool,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let backend = backend.clone();
+        let ctx = ctx.clone();
+        let stop1 = stop.clone();
+        thread::spawn(move || {
+            // Se ci si ferma durante l'attesa scroll_loop non cattura niente
+            sleep_unless_stopped(delay.max(MIN_START_DELAY), &stop1);
+            let result = scroll_loop(backend.as_ref(), region, auto_scroll, &stop1, |frames, height, missed| {
+                let _ = sender.send(ScrollEvent::Progress { frames, height, missed });
+                ctx.request_repaint();
+            });
+            let _ = sender.send(ScrollEvent::Finished(result));
+            ctx.request_repaint(); // Sveglia la UI, che sta aspettando il risultato
+        });
+        Self {
+            region,
+            receiver,
+            stop,
+            frames: 0,
+            height: 0,
+            missed: 0,
+        }
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    // Va chiamata ad ogni update. Restituisce l'immagine unita quando il thread ha finito.
+    pub fn poll(&mut self) -> Option<Result<Option<RgbaImage>, CaptureError>> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(ScrollEvent::Progress { frames, height, missed }) => {
+                    self.frames = frames;
+                    self.height = height;
+                    self.missed = missed;
+                }
+                Ok(ScrollEvent::Finished(result)) => return Some(result),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return Some(Err(CaptureError::Interrupted)),
+            }
+        }
+    }
+
+    pub fn ui(&self, ctx: &Context) -> ScrollAction {
+        let mut action = ScrollAction::None;
+        Window::new("Scrolling capture")
+            .title_bar(false)
+            .resizable(false)
+            .fixed_pos(Pos2::ZERO)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if self.frames == 0 {
+                        ui.label("📜 Get ready to scroll...");
+                    } else {
+                        ui.label(format!("📜 {} frames, {} px", self.frames, self.height));
+                    }
+                    if self.missed > 0 {
+                        ui.colored_label(Color32::LIGHT_RED, "Scroll slower!")
+                            .on_hover_text(format!("{} frames didn't overlap the previous one", self.missed));
+                    }
+                    ui.separator();
+                    if ui.button("✔ Done").clicked() {
+                        action = ScrollAction::Stop;
+                    }
+                    if ui.button("✖ Cancel").clicked() {
+                        action = ScrollAction::Cancel;
+                    }
+                });
+            });
+        action
+    }
+}
+
+fn scroll_loop(
+    backend: &dyn CaptureBackend,
+    region: PhysicalRect,
+    auto_scroll: bool,
+    stop: &AtomicBool,
+    progress: impl Fn(u32, u32, u32),
+) -> Result<Option<RgbaImage>, CaptureError> {
+    let target = CaptureTarget::PhysicalRegion(region);
+    let mut stitcher: Option<Stitcher> = None;
+    let (mut unchanged, mut missed) = (0, 0);
+    let scroller = if auto_scroll {
+        Scroller::connect().map_err(|e| println!("Could not scroll automatically: {}", e)).ok()
+    } else {
+        None
+    };
+    while !stop.load(Ordering::Relaxed) {
+        // Niente cursore: cambierebbe da un fotogramma all'altro e disturberebbe la ricerca della sovrapposizione
+        let capture = screenshot_utils::take_screenshot(backend, &target, true, &CursorMode::Hidden, &mut CursorReader::default())?;
+        match stitcher.as_mut() {
+            None => stitcher = Some(Stitcher::new(capture.image)),
+            Some(stitcher) => match stitcher.push(&capture.image) {
+                Overlap::Identical => unchanged += 1,
+                Overlap::Shifted { .. } => unchanged = 0,
+                Overlap::NotFound => {
+                    println!("Scrolling capture: no overlap with the previous frame, frame skipped");
+                    missed += 1;
+                }
+            },
+        }
+        let stitcher = stitcher.as_ref().expect("the first frame was just added");
+        progress(stitcher.frames, stitcher.height(), missed);
+
+        if stitcher.height() >= MAX_HEIGHT {
+            println!("Scrolling capture: reached {} px, stopping", MAX_HEIGHT);
+            break;
+        }
+        if auto_scroll {
+            if unchanged >= UNCHANGED_FRAMES_AT_END {
+                break; // Non scorre più: siamo in fondo
+            }
+            if let Some(Err(e)) = scroller.as_ref().map(|s| s.scroll_down(&region)) {
+                println!("Could not scroll automatically: {}", e);
+            }
+        }
+        if sleep_unless_stopped(FRAME_INTERVAL, stop) {
+            break;
+        }
+    }
+    // Senza fotogrammi (Done prima che arrivasse il primo) non c'è niente da unire, ma non è un errore
+    Ok(stitcher.map(|s| s.finish()))
+}
+
+// Aspetta duration, ma torna appena viene chiesto di fermarsi. Restituisce true se ci si deve fermare.
+fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep((deadline - now).min(STOP_CHECK_INTERVAL));
+    }
+}
+
+// Lo scorrimento automatico. Su X11 tramite l'estensione XTEST, con una sola connessione per tutta la cattura;
+// sugli altri sistemi non è supportato.
+struct Scroller {
+    #[cfg(target_os = "linux")]
+    x11: x11::Scroller,
+}
+
+impl Scroller {
+    fn connect() -> Result<Self, Box<dyn Error>> {
+        #[cfg(target_os = "linux")]
+        {
+            Ok(Self { x11: x11::Scroller::connect()? })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Err("automatic scrolling is only supported on X11".into())
+        }
+    }
+
+    // Porta il puntatore al centro della regione e manda qualche scatto di rotella verso il basso
+    fn scroll_down(&self, region: &PhysicalRect) -> Result<(), Box<dyn Error>> {
+        #[cfg(target_os = "linux")]
+        {
+            self.x11.scroll_down(
+                region.x + region.width as i32 / 2,
+                region.y + region.height as i32 / 2,
+                3,
+            )
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = region;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use std::error::Error;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{ConnectionExt, Window, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT};
+    use x11rb::protocol::xtest::ConnectionExt as XTestExt;
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::CURRENT_TIME;
+
+    const WHEEL_DOWN: u8 = 5;
+
+    pub struct Scroller {
+        conn: RustConnection,
+        root: Window,
+    }
+
+    impl Scroller {
+        pub fn connect() -> Result<Self, Box<dyn Error>> {
+            let (conn, screen_num) = x11rb::connect(None)?;
+            let root = conn.setup().roots[screen_num].root;
+            Ok(Self { conn, root })
+        }
+
+        pub fn scroll_down(&self, x: i32, y: i32, clicks: u32) -> Result<(), Box<dyn Error>> {
+            let (conn, root) = (&self.conn, self.root);
+            conn.warp_pointer(x11rb::NONE, root, 0, 0, 0, 0, x as i16, y as i16)?;
+            for _ in 0..clicks {
+                conn.xtest_fake_input(BUTTON_PRESS_EVENT, WHEEL_DOWN, CURRENT_TIME, root, 0, 0, 0)?;
+                conn.xtest_fake_input(BUTTON_RELEASE_EVENT, WHEEL_DOWN, CURRENT_TIME, root, 0, 0, 0)?;
+            }
+            // Un round trip: quando torna, il server ha ricevuto tutti gli eventi
+            conn.get_input_focus()?.reply()?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::capture_utils::FakeBackend;
+
+    #[test]
+    fn stopping_before_the_first_frame_gives_an_empty_result() {
+        let region = PhysicalRect { x: 0, y: 0, width: 100, height: 100 };
+        let stop = AtomicBool::new(true);
+        let result = scroll_loop(&FakeBackend::dual_monitor(), region, false, &stop, |_, _, _| {});
+        assert_eq!(result, Ok(None));
+    }
+
+    #[test]
+    fn waiting_ends_as_soon_as_the_capture_is_stopped() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let start = Instant::now();
+        let stopper = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                stop.store(true, Ordering::Relaxed);
+            })
+        };
+        assert!(sleep_unless_stopped(Duration::from_secs(10), &stop));
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn waiting_without_stop_lasts_the_whole_duration() {
+        let start = Instant::now();
+        assert!(!sleep_unless_stopped(Duration::from_millis(60), &AtomicBool::new(false)));
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+}
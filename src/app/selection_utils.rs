@@ -0,0 +1,335 @@
+use egui::*;
+
+// Quanto deve essere vicino il puntatore a un bordo per prenderlo, e quanto sono grandi le maniglie
+const GRAB_DISTANCE: f32 = 8.0;
+const HANDLE_SIZE: f32 = 8.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Handle {
+    Move,
+    Left,
+    Right,
+    Top,
+    Bottom,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Handle {
+    fn cursor_icon(&self) -> CursorIcon {
+        match self {
+            Handle::Move => CursorIcon::Move,
+            Handle::Left | Handle::Right => CursorIcon::ResizeHorizontal,
+            Handle::Top | Handle::Bottom => CursorIcon::ResizeVertical,
+            Handle::TopLeft | Handle::BottomRight => CursorIcon::ResizeNwSe,
+            Handle::TopRight | Handle::BottomLeft => CursorIcon::ResizeNeSw,
+        }
+    }
+}
+
+// Vincoli imposti dai preset alla selezione
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Constraint {
+    Free,
+    FixedSize(Vec2),    // Dimensione in punti: il rettangolo si può solo spostare
+    AspectRatio(f32),   // Larghezza / altezza
+}
+
+// Una selezione rettangolare che resta modificabile dopo essere stata tracciata: si sposta trascinandola,
+// si ridimensiona con le maniglie e si sposta con le frecce. Le coordinate sono quelle della maschera.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection {
+    pub rect: Rect,
+    pub constraint: Constraint,
+    dragging: Option<(Handle, Pos2, Rect)>, // Maniglia, punto e rettangolo all'inizio del trascinamento
+}
+
+impl Selection {
+    pub fn with_constraint(rect: Rect, constraint: Constraint) -> Self {
+        let rect = match constraint {
+            Constraint::FixedSize(size) => Rect::from_min_size(rect.min, size),
+            _ => rect,
+        };
+        Self {
+            rect,
+            constraint,
+            dragging: None,
+        }
+    }
+
+    pub fn is_fixed_size(&self) -> bool {
+        matches!(self.constraint, Constraint::FixedSize(_))
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.dragging.is_some()
+    }
+
+    // La maniglia sotto il punto: prima gli angoli, poi i bordi, poi l'interno
+    pub fn handle_at(&self, pos: Pos2) -> Option<Handle> {
+        let r = self.rect;
+        if self.is_fixed_size() {
+            // Niente maniglie: si può soltanto spostare
+            return r.contains(pos).then_some(Handle::Move);
+        }
+        let near = |a: f32, b: f32| (a - b).abs() <= GRAB_DISTANCE;
+        let in_x = pos.x >= r.min.x - GRAB_DISTANCE && pos.x <= r.max.x + GRAB_DISTANCE;
+        let in_y = pos.y >= r.min.y - GRAB_DISTANCE && pos.y <= r.max.y + GRAB_DISTANCE;
+        if !in_x || !in_y {
+            return None;
+        }
+        let (left, right) = (near(pos.x, r.min.x), near(pos.x, r.max.x));
+        let (top, bottom) = (near(pos.y, r.min.y), near(pos.y, r.max.y));
+        match (left, right, top, bottom) {
+            (true, _, true, _) => Some(Handle::TopLeft),
+            (_, true, true, _) => Some(Handle::TopRight),
+            (true, _, _, true) => Some(Handle::BottomLeft),
+            (_, true, _, true) => Some(Handle::BottomRight),
+            (true, _, _, _) => Some(Handle::Left),
+            (_, true, _, _) => Some(Handle::Right),
+            (_, _, true, _) => Some(Handle::Top),
+            (_, _, _, true) => Some(Handle::Bottom),
+            _ if r.contains(pos) => Some(Handle::Move),
+            _ => None,
+        }
+    }
+
+    // Se uno dei lati, in pixel fisici, è più corto di min_pixels
+    pub fn is_smaller_than(&self, min_pixels: f32, pixels_per_point: f32) -> bool {
+        let size = self.rect.size() * pixels_per_point;
+        size.x < min_pixels || size.y < min_pixels
+    }
+
+    pub fn begin_drag(&mut self, handle: Handle, pointer: Pos2) {
+        self.dragging = Some((handle, pointer, self.rect));
+    }
+
+    pub fn drag_to(&mut self, pointer: Pos2) {
+        if let Some((handle, start, rect)) = self.dragging {
+            self.rect = self.constrained(rect, handle, pointer - start);
+        }
+    }
+
+    pub fn end_drag(&mut self) {
+        self.dragging = None;
+    }
+
+    // Sposta la selezione di delta. Con resize sposta soltanto il bordo destro e quello inferiore.
+    pub fn nudge(&mut self, delta: Vec2, resize: bool) {
+        let handle = if resize { Handle::BottomRight } else { Handle::Move };
+        self.rect = self.constrained(self.rect, handle, delta);
+    }
+
+    fn constrained(&self, rect: Rect, handle: Handle, delta: Vec2) -> Rect {
+        match self.constraint {
+            Constraint::Free => dragged(rect, handle, delta),
+            Constraint::FixedSize(_) => rect.translate(delta),
+            Constraint::AspectRatio(ratio) => dragged_with_ratio(rect, handle, delta, ratio),
+        }
+    }
+
+    pub fn paint(&self, painter: &Painter) {
+        painter.rect_stroke(self.rect, 0.0, Stroke::new(1.0, Color32::WHITE));
+        if self.is_fixed_size() {
+            return;
+        }
+        let r = self.rect;
+        for corner in [
+            r.left_top(),
+            r.center_top(),
+            r.right_top(),
+            r.left_center(),
+            r.right_center(),
+            r.left_bottom(),
+            r.center_bottom(),
+            r.right_bottom(),
+        ] {
+            let handle = Rect::from_center_size(corner, vec2(HANDLE_SIZE, HANDLE_SIZE));
+            painter.rect(handle, 1.0, Color32::WHITE, Stroke::new(1.0, Color32::BLACK));
+        }
+    }
+
+    pub fn set_cursor(&self, ctx: &Context, hover: Pos2) {
+        if let Some(handle) = self.dragging.map(|d| d.0).or_else(|| self.handle_at(hover)) {
+            ctx.set_cursor_icon(handle.cursor_icon());
+        }
+    }
+}
+
+// Il rettangolo dopo aver trascinato la maniglia di delta. Se un bordo supera quello opposto
+// il rettangolo si ribalta invece di diventare negativo.
+pub fn dragged(rect: Rect, handle: Handle, delta: Vec2) -> Rect {
+    let (mut min, mut max) = (rect.min, rect.max);
+    match handle {
+        Handle::Move => return rect.translate(delta),
+        Handle::Left => min.x += delta.x,
+        Handle::Right => max.x += delta.x,
+        Handle::Top => min.y += delta.y,
+        Handle::Bottom => max.y += delta.y,
+        Handle::TopLeft => min += delta,
+        Handle::BottomRight => max += delta,
+        Handle::TopRight => {
+            max.x += delta.x;
+            min.y += delta.y;
+        }
+        Handle::BottomLeft => {
+            min.x += delta.x;
+            max.y += delta.y;
+        }
+    }
+    Rect::from_two_pos(min, max)
+}
+
+// Come dragged, ma mantenendo il rapporto larghezza / altezza. Con gli angoli il punto fisso è l'angolo opposto
+// e il rettangolo cresce fino a raggiungere il puntatore; con i lati resta centrato sull'altro asse.
+pub fn dragged_with_ratio(rect: Rect, handle: Handle, delta: Vec2, ratio: f32) -> Rect {
+    match handle {
+        Handle::Move => rect.translate(delta),
+        Handle::Left | Handle::Right => {
+            let r = dragged(rect, handle, delta);
+            Rect::from_center_size(pos2(r.center().x, rect.center().y), vec2(r.width(), r.width() / ratio))
+        }
+        Handle::Top | Handle::Bottom => {
+            let r = dragged(rect, handle, delta);
+            Rect::from_center_size(pos2(rect.center().x, r.center().y), vec2(r.height() * ratio, r.height()))
+        }
+        _ => {
+            let (anchor, corner) = match handle {
+                Handle::TopLeft => (rect.max, rect.min),
+                Handle::TopRight => (rect.left_bottom(), rect.right_top()),
+                Handle::BottomLeft => (rect.right_top(), rect.left_bottom()),
+                _ => (rect.min, rect.max),
+            };
+            let d = corner + delta - anchor;
+            let (mut width, mut height) = (d.x.abs(), d.y.abs());
+            if width > height * ratio {
+                height = width / ratio;
+            } else {
+                width = height * ratio;
+            }
+            Rect::from_two_pos(anchor, anchor + vec2(width.copysign(d.x), height.copysign(d.y)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Handle; 9] = [
+        Handle::Move,
+        Handle::Left,
+        Handle::Right,
+        Handle::Top,
+        Handle::Bottom,
+        Handle::TopLeft,
+        Handle::TopRight,
+        Handle::BottomLeft,
+        Handle::BottomRight,
+    ];
+
+    fn rect() -> Rect {
+        Rect::from_min_max(pos2(100., 100.), pos2(300., 200.))
+    }
+
+    fn assert_close(a: Rect, b: Rect) {
+        assert!((a.min - b.min).length() < 1e-3 && (a.max - b.max).length() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn every_handle_moves_its_own_sides() {
+        let delta = vec2(10., 20.);
+        for (handle, (min, max)) in ALL.into_iter().zip([
+            ((110., 120.), (310., 220.)),
+            ((110., 100.), (300., 200.)),
+            ((100., 100.), (310., 200.)),
+            ((100., 120.), (300., 200.)),
+            ((100., 100.), (300., 220.)),
+            ((110., 120.), (300., 200.)),
+            ((100., 120.), (310., 200.)),
+            ((110., 100.), (300., 220.)),
+            ((100., 100.), (310., 220.)),
+        ]) {
+            let expected = Rect::from_min_max(pos2(min.0, min.1), pos2(max.0, max.1));
+            assert_close(dragged(rect(), handle, delta), expected);
+        }
+    }
+
+    #[test]
+    fn dragging_past_the_opposite_side_flips_the_rect() {
+        let r = dragged(rect(), Handle::Left, vec2(250., 0.));
+        assert_close(r, Rect::from_min_max(pos2(300., 100.), pos2(350., 200.)));
+        let r = dragged(rect(), Handle::TopLeft, vec2(250., 150.));
+        assert_close(r, Rect::from_min_max(pos2(300., 200.), pos2(350., 250.)));
+    }
+
+    #[test]
+    fn every_handle_keeps_the_ratio() {
+        let ratio = 2.0;
+        for handle in ALL {
+            for delta in [vec2(10., 20.), vec2(-30., 5.), vec2(40., -40.), vec2(-500., -500.)] {
+                let r = dragged_with_ratio(rect(), handle, delta, ratio);
+                assert!((r.width() / r.height() - ratio).abs() < 1e-3, "{:?} {:?}: {:?}", handle, delta, r);
+                assert!(r.min.x <= r.max.x && r.min.y <= r.max.y, "{:?} {:?}: {:?}", handle, delta, r);
+            }
+        }
+    }
+
+    #[test]
+    fn corners_keep_the_opposite_corner_fixed() {
+        let ratio = 2.0;
+        let delta = vec2(40., 10.);
+        let r = dragged_with_ratio(rect(), Handle::BottomRight, delta, ratio);
+        assert_close(r, Rect::from_min_max(pos2(100., 100.), pos2(340., 220.)));
+        let r = dragged_with_ratio(rect(), Handle::TopLeft, -delta, ratio);
+        assert_close(r, Rect::from_min_max(pos2(60., 80.), pos2(300., 200.)));
+        let r = dragged_with_ratio(rect(), Handle::TopRight, vec2(0., -50.), ratio);
+        assert_close(r, Rect::from_min_max(pos2(100., 50.), pos2(400., 200.)));
+        let r = dragged_with_ratio(rect(), Handle::BottomLeft, vec2(-100., 0.), ratio);
+        assert_close(r, Rect::from_min_max(pos2(0., 100.), pos2(300., 250.)));
+    }
+
+    #[test]
+    fn sides_stay_centered_on_the_other_axis() {
+        let ratio = 2.0;
+        let r = dragged_with_ratio(rect(), Handle::Right, vec2(40., 99.), ratio);
+        assert_close(r, Rect::from_center_size(pos2(220., 150.), vec2(240., 120.)));
+        let r = dragged_with_ratio(rect(), Handle::Top, vec2(99., -20.), ratio);
+        assert_close(r, Rect::from_center_size(pos2(200., 140.), vec2(240., 120.)));
+        assert_close(dragged_with_ratio(rect(), Handle::Move, vec2(5., 6.), ratio), rect().translate(vec2(5., 6.)));
+    }
+
+    #[test]
+    fn constraints_apply_while_dragging() {
+        let mut selection = Selection::with_constraint(rect(), Constraint::FixedSize(vec2(50., 40.)));
+        assert_eq!(selection.rect.size(), vec2(50., 40.));
+        assert_eq!(selection.handle_at(pos2(100., 100.)), Some(Handle::Move));
+        selection.begin_drag(Handle::BottomRight, pos2(150., 140.));
+        selection.drag_to(pos2(200., 200.));
+        selection.end_drag();
+        assert_eq!(selection.rect, Rect::from_min_size(pos2(150., 160.), vec2(50., 40.)));
+
+        let mut selection = Selection::with_constraint(rect(), Constraint::AspectRatio(2.0));
+        selection.nudge(vec2(10., 0.), true);
+        assert!((selection.rect.aspect_ratio() - 2.0).abs() < 1e-3);
+        let mut selection = Selection::with_constraint(rect(), Constraint::Free);
+        selection.nudge(vec2(1., 2.), true);
+        assert_eq!(selection.rect, Rect::from_min_max(pos2(100., 100.), pos2(301., 202.)));
+    }
+
+    #[test]
+    fn small_selections_are_measured_in_physical_pixels() {
+        let selection = Selection::with_constraint(Rect::from_min_size(pos2(0., 0.), vec2(5., 20.)), Constraint::Free);
+        assert!(!selection.is_smaller_than(5.0, 1.0));
+        assert!(selection.is_smaller_than(6.0, 1.0));
+        // Con scala 2 i 5 punti sono 10 pixel fisici
+        assert!(!selection.is_smaller_than(10.0, 2.0));
+        assert!(selection.is_smaller_than(10.0, 1.5));
+        // Il lato più corto decide, anche quando è l'altezza
+        let selection = Selection::with_constraint(Rect::from_min_size(pos2(0., 0.), vec2(20., 0.5)), Constraint::Free);
+        assert!(selection.is_smaller_than(1.0, 1.0));
+        assert!(!selection.is_smaller_than(1.0, 2.0));
+    }
+}
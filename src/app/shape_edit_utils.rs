@@ -0,0 +1,413 @@
+use super::history_utils::{Command, History};
+use super::shape_utils::{self, DrawObj};
+use egui::emath::RectTransform;
+use egui::{vec2, Align, Align2, Color32, Context, CursorIcon, DragValue, Key, Painter, Pos2, Rect, Response, Stroke, Ui};
+
+// La selezione delle annotazioni già disegnate: clic per selezionarne una, Shift+clic per aggiungerla o
+// toglierla, trascinando sul vuoto si seleziona con un rettangolo. Le selezionate si spostano trascinandole
+// e si ridimensionano con le maniglie del riquadro; Canc le elimina.
+
+const HANDLE_SIZE: f32 = 8.0; // In punti dello schermo, a qualsiasi zoom
+const HIT_TOLERANCE: f32 = 4.0; // Anche questa in punti dello schermo
+
+// Le otto maniglie del riquadro: l'allineamento dice dove si trovano
+const HANDLES: [Align2; 8] = [
+    Align2::LEFT_TOP,
+    Align2::CENTER_TOP,
+    Align2::RIGHT_TOP,
+    Align2::RIGHT_CENTER,
+    Align2::RIGHT_BOTTOM,
+    Align2::CENTER_BOTTOM,
+    Align2::LEFT_BOTTOM,
+    Align2::LEFT_CENTER,
+];
+
+#[derive(Clone)]
+enum SelectionDrag {
+    // Le forme selezionate com'erano all'inizio del trascinamento, così non si accumulano errori
+    Move { start: Pos2, original: Vec<DrawObj> },
+    Resize { handle: Align2, bounds: Rect, original: Vec<DrawObj> },
+    RubberBand { start: Pos2, current: Pos2, base: Vec<usize> },
+}
+
+#[derive(Clone, Default)]
+pub struct ShapeSelection {
+    pub indices: Vec<usize>, // Indici in Painting::shapes, in ordine crescente
+    drag: Option<SelectionDrag>,
+}
+
+impl ShapeSelection {
+    pub fn clear(&mut self) {
+        self.indices.clear();
+        self.drag = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    // Gestisce il puntatore sul canvas. Le forme cambiano mentre le si trascina, ma il comando per la
+    // cronologia arriva soltanto quando si rilascia il mouse.
+    pub fn ui(&mut self, ctx: &Context, response: &Response, to_screen: &RectTransform, shapes: &mut Vec<DrawObj>, image_size: egui::Vec2) -> Option<Command> {
+        self.indices.retain(|&i| i < shapes.len());
+        let from_screen = to_screen.inverse();
+        let tolerance = HIT_TOLERANCE / to_screen.scale().x;
+        let mut command = None;
+
+        if let Some(pointer) = response.hover_pos() {
+            let pos = from_screen * pointer;
+            let icon = match self.handle_at(ctx, to_screen, shapes, pointer) {
+                Some(handle) => resize_cursor(handle),
+                None if topmost_at(ctx, shapes, pos, tolerance).is_some() => CursorIcon::Move,
+                None => CursorIcon::Default,
+            };
+            ctx.set_cursor_icon(icon);
+        }
+
+        if let Some(pointer) = response.interact_pointer_pos() {
+            let pos = shape_utils::clamp_to_image(from_screen * pointer, image_size);
+            if response.drag_started() {
+                let shift = ctx.input(|i| i.modifiers.shift);
+                self.drag = if let Some(handle) = self.handle_at(ctx, to_screen, shapes, pointer) {
+                    Some(SelectionDrag::Resize {
+                        handle,
+                        bounds: self.bounds(ctx, shapes),
+                        original: self.selected(shapes),
+                    })
+                } else if let Some(index) = topmost_at(ctx, shapes, from_screen * pointer, tolerance) {
+                    if shift {
+                        // Aggiunge o toglie, senza spostare
+                        toggle(&mut self.indices, index);
+                        None
+                    } else {
+                        if !self.indices.contains(&index) {
+                            self.indices = vec![index];
+                        }
+                        Some(SelectionDrag::Move { start: pos, original: self.selected(shapes) })
+                    }
+                } else {
+                    let base = if shift { self.indices.clone() } else { vec![] };
+                    Some(SelectionDrag::RubberBand { start: pos, current: pos, base })
+                };
+            }
+
+            match self.drag.as_mut() {
+                Some(SelectionDrag::Move { start, original }) => {
+                    for (&index, shape) in self.indices.iter().zip(original.iter()) {
+                        let mut moved = shape.clone();
+                        moved.translate(pos - *start);
+                        shapes[index] = moved;
+                    }
+                }
+                Some(SelectionDrag::Resize { handle, bounds, original }) => {
+                    let target = resize_rect(*bounds, *handle, pos);
+                    for (&index, shape) in self.indices.iter().zip(original.iter()) {
+                        let mut resized = shape.clone();
+                        resized.resize(*bounds, target);
+                        shapes[index] = resized;
+                    }
+                }
+                Some(SelectionDrag::RubberBand { start, current, base }) => {
+                    *current = pos;
+                    self.indices = band_selection(ctx, shapes, Rect::from_two_pos(*start, *current), base);
+                }
+                None => {}
+            }
+        }
+        if response.drag_released() {
+            command = match self.drag.take() {
+                Some(SelectionDrag::Move { original, .. }) => self.modified("Move", original, shapes),
+                Some(SelectionDrag::Resize { original, .. }) => self.modified("Resize", original, shapes),
+                _ => None,
+            };
+        }
+
+        // Canc elimina le forme selezionate, Esc toglie la selezione; non mentre si scrive in un campo di testo
+        if !ctx.wants_keyboard_input() {
+            let (delete, escape) = ctx.input(|i| (i.key_pressed(Key::Delete) || i.key_pressed(Key::Backspace), i.key_pressed(Key::Escape)));
+            if delete && !self.is_empty() {
+                command = Some(self.delete(shapes));
+            } else if escape {
+                self.clear();
+            }
+        }
+        command
+    }
+
+    // I controlli della selezione: tratto e riempimento valgono per tutte le forme selezionate
+    pub fn ui_control(&mut self, ui: &mut Ui, shapes: &mut Vec<DrawObj>, history: &mut History) {
+        self.indices.retain(|&i| i < shapes.len());
+        let Some(&first) = self.indices.first() else {
+            ui.label("Click or drag to select");
+            return;
+        };
+        let original = self.selected(shapes);
+        ui.label(format!("{} selected", self.indices.len()));
+
+        // Le risposte dei widget: servono a capire quale ha cambiato le forme e se è ancora in uso
+        let mut responses = vec![];
+
+        let mut stroke = shapes[first].stroke;
+        responses.push(ui.add(DragValue::new(&mut stroke.width).speed(1).clamp_range(1..=40)).on_hover_text("Width"));
+        responses.push(ui.color_edit_button_srgba(&mut stroke.color));
+        if responses.iter().any(|response| response.changed()) {
+            for &index in self.indices.iter() {
+                shapes[index].stroke = stroke;
+            }
+        }
+
+        if let Some(&fillable) = self.indices.iter().find(|&&i| shapes[i].can_fill()) {
+            let mut fill = shapes[fillable].fill;
+            let mut filled = fill.is_some();
+            let checkbox = ui.checkbox(&mut filled, "Fill");
+            let mut fill_changed = checkbox.changed();
+            if fill_changed {
+                fill = if filled { Some(stroke.color.linear_multiply(0.3)) } else { None };
+            }
+            responses.push(checkbox);
+            if let Some(fill) = fill.as_mut() {
+                let response = ui.color_edit_button_srgba(fill);
+                fill_changed |= response.changed();
+                responses.push(response);
+            }
+            if fill_changed {
+                for &index in self.indices.iter() {
+                    if shapes[index].can_fill() {
+                        shapes[index].fill = fill;
+                    }
+                }
+            }
+        }
+
+        if ui.button("🗑").on_hover_text("Delete the selected shapes (Del)").clicked() {
+            history.record(self.delete(shapes));
+            return;
+        }
+        // Finché il widget che ha cambiato le forme resta in uso (trascinato, in modifica, col selettore
+        // del colore aperto nel suo popup) le modifiche successive si uniscono in un solo comando
+        if let Some(changed) = responses.iter().find(|response| response.changed()) {
+            if let Some(command) = self.modified("Restyle", original, shapes) {
+                history.record_merging(command, changed.id);
+            }
+        }
+        let held = responses.iter().any(|response| response.dragged() || response.has_focus())
+            || ui.memory(|memory| memory.any_popup_open());
+        if !held {
+            history.end_interaction();
+        }
+    }
+
+    // Il riquadro di ogni forma selezionata, quello di tutta la selezione con le maniglie e il rettangolo di selezione
+    pub fn paint(&self, ctx: &Context, painter: &Painter, to_screen: &RectTransform, shapes: &[DrawObj]) {
+        let stroke = ctx.style().visuals.selection.stroke;
+        let faint = Stroke::new(1.0, stroke.color.linear_multiply(0.5));
+        for &index in self.indices.iter().filter(|&&i| i < shapes.len()) {
+            painter.rect_stroke(screen_rect(to_screen, shapes[index].bounding_rect(ctx)), 0.0, faint);
+        }
+        if !self.is_empty() {
+            let bounds = screen_rect(to_screen, self.bounds(ctx, shapes));
+            painter.rect_stroke(bounds, 0.0, Stroke::new(1.0, stroke.color));
+            for handle in HANDLES {
+                let handle = Rect::from_center_size(handle.pos_in_rect(&bounds), vec2(HANDLE_SIZE, HANDLE_SIZE));
+                painter.rect_filled(handle, 1.0, Color32::WHITE);
+                painter.rect_stroke(handle, 1.0, Stroke::new(1.0, stroke.color));
+            }
+        }
+        if let Some(SelectionDrag::RubberBand { start, current, .. }) = &self.drag {
+            let band = screen_rect(to_screen, Rect::from_two_pos(*start, *current));
+            painter.rect_filled(band, 0.0, stroke.color.linear_multiply(0.1));
+            painter.rect_stroke(band, 0.0, Stroke::new(1.0, stroke.color));
+        }
+    }
+
+    fn delete(&mut self, shapes: &mut Vec<DrawObj>) -> Command {
+        let deleted = self.indices.iter().map(|&i| (i, shapes[i].clone())).collect();
+        // Dal fondo, così gli indici che restano sono ancora validi
+        for &index in self.indices.iter().rev() {
+            shapes.remove(index);
+        }
+        self.clear();
+        Command::delete(deleted)
+    }
+
+    // Il comando per le forme selezionate, se sono diverse da com'erano
+    fn modified(&self, label: &str, original: Vec<DrawObj>, shapes: &[DrawObj]) -> Option<Command> {
+        let current = self.selected(shapes);
+        if current == original {
+            return None;
+        }
+        let indices = self.indices.iter().copied();
+        Some(Command::modify(label, indices.clone().zip(original).collect(), indices.zip(current).collect()))
+    }
+
+    fn selected(&self, shapes: &[DrawObj]) -> Vec<DrawObj> {
+        self.indices.iter().map(|&i| shapes[i].clone()).collect()
+    }
+
+    fn bounds(&self, ctx: &Context, shapes: &[DrawObj]) -> Rect {
+        self.indices
+            .iter()
+            .filter(|&&i| i < shapes.len())
+            .fold(Rect::NOTHING, |bounds, &i| bounds.union(shapes[i].bounding_rect(ctx)))
+    }
+
+    fn handle_at(&self, ctx: &Context, to_screen: &RectTransform, shapes: &[DrawObj], pointer: Pos2) -> Option<Align2> {
+        if self.is_empty() {
+            return None;
+        }
+        let bounds = screen_rect(to_screen, self.bounds(ctx, shapes));
+        HANDLES
+            .into_iter()
+            .find(|handle| Rect::from_center_size(handle.pos_in_rect(&bounds), vec2(HANDLE_SIZE, HANDLE_SIZE)).expand(2.0).contains(pointer))
+    }
+}
+
+// La forma più in alto (l'ultima disegnata) sotto pos
+fn topmost_at(ctx: &Context, shapes: &[DrawObj], pos: Pos2, tolerance: f32) -> Option<usize> {
+    shapes.iter().rposition(|shape| shape.hit_test(ctx, pos, tolerance))
+}
+
+// Aggiunge index alla selezione, o lo toglie se c'era già, tenendo gli indici in ordine
+fn toggle(indices: &mut Vec<usize>, index: usize) {
+    match indices.binary_search(&index) {
+        Ok(position) => {
+            indices.remove(position);
+        }
+        Err(position) => indices.insert(position, index),
+    }
+}
+
+// Le forme toccate dal rettangolo di selezione, più quelle di base (già selezionate con Shift), in ordine
+fn band_selection(ctx: &Context, shapes: &[DrawObj], band: Rect, base: &[usize]) -> Vec<usize> {
+    let mut indices = base.to_vec();
+    for (index, shape) in shapes.iter().enumerate() {
+        // Un clic sul vuoto, senza trascinare, non seleziona niente
+        if !indices.contains(&index) && band.size().max_elem() > 0.0 && band.intersects(shape.bounding_rect(ctx)) {
+            indices.push(index);
+        }
+    }
+    indices.sort_unstable();
+    indices
+}
+
+// Il riquadro dopo aver portato la maniglia in pos: i lati che la maniglia non tocca restano fermi
+fn resize_rect(bounds: Rect, handle: Align2, pos: Pos2) -> Rect {
+    let (mut min, mut max) = (bounds.min, bounds.max);
+    match handle.x() {
+        Align::Min => min.x = pos.x,
+        Align::Max => max.x = pos.x,
+        Align::Center => {}
+    }
+    match handle.y() {
+        Align::Min => min.y = pos.y,
+        Align::Max => max.y = pos.y,
+        Align::Center => {}
+    }
+    Rect::from_two_pos(min, max)
+}
+
+fn resize_cursor(handle: Align2) -> CursorIcon {
+    match (handle.x(), handle.y()) {
+        (Align::Center, _) => CursorIcon::ResizeVertical,
+        (_, Align::Center) => CursorIcon::ResizeHorizontal,
+        (x, y) if x == y => CursorIcon::ResizeNwSe,
+        _ => CursorIcon::ResizeNeSw,
+    }
+}
+
+fn screen_rect(to_screen: &RectTransform, rect: Rect) -> Rect {
+    Rect::from_two_pos(to_screen.transform_pos(rect.min), to_screen.transform_pos(rect.max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::shape_utils::Shape;
+    use egui::pos2;
+
+    fn context() -> Context {
+        let ctx = Context::default();
+        let _ = ctx.run(Default::default(), |_| {}); // I font ci sono solo dopo il primo frame
+        ctx
+    }
+
+    fn rectangle(min: Pos2, max: Pos2) -> DrawObj {
+        DrawObj::new(Shape::Rectangle(Rect::from_min_max(min, max)), Stroke::new(2.0, Color32::RED), None)
+    }
+
+    #[test]
+    fn every_handle_moves_only_its_sides() {
+        let bounds = Rect::from_min_max(pos2(10., 20.), pos2(110., 70.));
+        let pos = pos2(50., 40.);
+        let expected = [
+            (Align2::LEFT_TOP, Rect::from_min_max(pos2(50., 40.), pos2(110., 70.))),
+            (Align2::CENTER_TOP, Rect::from_min_max(pos2(10., 40.), pos2(110., 70.))),
+            (Align2::RIGHT_TOP, Rect::from_min_max(pos2(10., 40.), pos2(50., 70.))),
+            (Align2::RIGHT_CENTER, Rect::from_min_max(pos2(10., 20.), pos2(50., 70.))),
+            (Align2::RIGHT_BOTTOM, Rect::from_min_max(pos2(10., 20.), pos2(50., 40.))),
+            (Align2::CENTER_BOTTOM, Rect::from_min_max(pos2(10., 20.), pos2(110., 40.))),
+            (Align2::LEFT_BOTTOM, Rect::from_min_max(pos2(50., 20.), pos2(110., 40.))),
+            (Align2::LEFT_CENTER, Rect::from_min_max(pos2(50., 20.), pos2(110., 70.))),
+        ];
+        for (handle, rect) in expected {
+            assert_eq!(resize_rect(bounds, handle, pos), rect, "{:?}", handle);
+        }
+    }
+
+    #[test]
+    fn dragging_a_handle_past_the_opposite_side_flips_the_rect() {
+        let bounds = Rect::from_min_max(pos2(10., 20.), pos2(110., 70.));
+        assert_eq!(
+            resize_rect(bounds, Align2::LEFT_TOP, pos2(150., 90.)),
+            Rect::from_min_max(pos2(110., 70.), pos2(150., 90.))
+        );
+        assert_eq!(
+            resize_rect(bounds, Align2::RIGHT_CENTER, pos2(0., 0.)),
+            Rect::from_min_max(pos2(0., 20.), pos2(10., 70.))
+        );
+        assert_eq!(
+            resize_rect(bounds, Align2::CENTER_BOTTOM, pos2(60., 5.)),
+            Rect::from_min_max(pos2(10., 5.), pos2(110., 20.))
+        );
+    }
+
+    #[test]
+    fn topmost_shape_wins() {
+        let ctx = context();
+        let shapes = [rectangle(pos2(0., 0.), pos2(100., 100.)), rectangle(pos2(50., 50.), pos2(150., 150.))];
+        // Dove i contorni si incrociano vince il secondo, disegnato dopo
+        assert_eq!(topmost_at(&ctx, &shapes, pos2(100., 50.), 0.0), Some(1));
+        assert_eq!(topmost_at(&ctx, &shapes, pos2(100., 25.), 0.0), Some(0));
+        // Senza riempimento, l'interno non conta
+        assert_eq!(topmost_at(&ctx, &shapes, pos2(75., 75.), 0.0), None);
+        assert_eq!(topmost_at(&ctx, &shapes, pos2(200., 200.), 0.0), None);
+    }
+
+    #[test]
+    fn shift_click_toggles_and_keeps_the_order() {
+        let mut indices = vec![1, 4];
+        toggle(&mut indices, 2);
+        assert_eq!(indices, vec![1, 2, 4]);
+        toggle(&mut indices, 4);
+        assert_eq!(indices, vec![1, 2]);
+        toggle(&mut indices, 0);
+        assert_eq!(indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn rubber_band_adds_the_shapes_it_touches_to_the_base() {
+        let ctx = context();
+        let shapes = [
+            rectangle(pos2(0., 0.), pos2(10., 10.)),
+            rectangle(pos2(40., 40.), pos2(60., 60.)),
+            rectangle(pos2(100., 100.), pos2(120., 120.)),
+        ];
+        let band = Rect::from_min_max(pos2(50., 50.), pos2(105., 105.));
+        assert_eq!(band_selection(&ctx, &shapes, band, &[]), vec![1, 2]);
+        assert_eq!(band_selection(&ctx, &shapes, band, &[0]), vec![0, 1, 2]);
+        assert_eq!(band_selection(&ctx, &shapes, band, &[2]), vec![1, 2]);
+        // Un clic sul vuoto tiene solo la base
+        let click = Rect::from_min_max(pos2(80., 80.), pos2(80., 80.));
+        assert_eq!(band_selection(&ctx, &shapes, click, &[0]), vec![0]);
+    }
+}
@@ -0,0 +1,661 @@
+use super::redact_utils::RedactMode;
+use super::text_utils::{self, TextStyle};
+use egui::emath::RectTransform;
+use egui::{pos2, vec2, Align, Color32, Context, Pos2, Rect, Stroke, Vec2};
+use std::f32::consts::TAU;
+
+// Le annotazioni sono in pixel dell'immagine: lo spessore del tratto e le coordinate sono quelli dell'export,
+// e il canvas le scala per mostrarle. Così l'anteprima e l'immagine salvata coincidono.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArrowHead {
+    Open,   // Due segmenti a V
+    Filled, // Un triangolo pieno
+    Double, // Triangoli pieni ad entrambe le estremità
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Freehand(Vec<Pos2>),
+    Line { from: Pos2, to: Pos2 },
+    Arrow { from: Pos2, to: Pos2, head: ArrowHead },
+    Rectangle(Rect),
+    Ellipse(Rect), // L'ellisse inscritta nel rettangolo
+    Text { pos: Pos2, text: String, size: f32, align: Align }, // pos è il punto di ancoraggio, in alto
+    Redact { rect: Rect, mode: RedactMode }, // Cambia i pixel dello screenshot, sotto tutte le altre forme
+    // Un cerchio numerato, con una freccia opzionale verso target. Il numero è la posizione tra i passi
+    // del disegno: lo aggiorna renumber_steps.
+    Step { center: Pos2, radius: f32, number: u32, target: Option<Pos2> },
+    Highlight(Vec<Pos2>), // Un tratto a mano libera come un evidenziatore
+    // Un'area lasciata in luce: tutto quello che non sta in nessuno spotlight viene scurito
+    Spotlight { rect: Rect, ellipse: bool },
+}
+
+impl Shape {
+    // Come la forma appare nella cronologia
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shape::Freehand(_) => "freehand",
+            Shape::Line { .. } => "line",
+            Shape::Arrow { .. } => "arrow",
+            Shape::Rectangle(_) => "rectangle",
+            Shape::Ellipse(_) => "ellipse",
+            Shape::Text { .. } => "text",
+            Shape::Redact { .. } => "redaction",
+            Shape::Step { .. } => "step",
+            Shape::Highlight(_) => "highlight",
+            Shape::Spotlight { .. } => "spotlight",
+        }
+    }
+}
+
+// Quello che serve per disegnare una forma, sia con egui che nell'export
+#[derive(Clone, Debug, PartialEq)]
+pub enum Primitive {
+    Polyline { points: Vec<Pos2>, closed: bool, stroke: Stroke },
+    Highlight { points: Vec<Pos2>, stroke: Stroke }, // Una polilinea aperta che si moltiplica con quello che c'è sotto
+    Polygon { points: Vec<Pos2>, fill: Color32 }, // Sempre convesso
+    Text { pos: Pos2, text: String, style: TextStyle },
+}
+
+impl Primitive {
+    // Lo stesso primitivo sul canvas: to_screen porta dai pixel dell'immagine ai punti dello schermo.
+    // I tratti di egui hanno estremità piatte e giunzioni a spigolo: sul canvas le polilinee passano da
+    // render_utils::stroke_overlay, questa serve all'anteprima dello strumento.
+    pub fn to_egui(&self, ctx: &Context, to_screen: &RectTransform) -> egui::Shape {
+        let scale = to_screen.scale().x;
+        match self {
+            Primitive::Polyline { points, closed, stroke } => {
+                let points: Vec<Pos2> = points.iter().map(|p| to_screen.transform_pos(*p)).collect();
+                let stroke = Stroke::new(stroke.width * scale, stroke.color);
+                if *closed {
+                    egui::Shape::closed_line(points, stroke)
+                } else {
+                    egui::Shape::line(points, stroke)
+                }
+            }
+            // egui non sa moltiplicare i colori: sul canvas l'evidenziatore passa da render_utils::highlight_overlay,
+            // qui resta solo l'anteprima dello strumento, più trasparente così si vede cosa c'è sotto
+            Primitive::Highlight { points, stroke } => {
+                let points = points.iter().map(|p| to_screen.transform_pos(*p)).collect();
+                egui::Shape::line(points, Stroke::new(stroke.width * scale, stroke.color.linear_multiply(0.5)))
+            }
+            Primitive::Polygon { points, fill } => {
+                let points = points.iter().map(|p| to_screen.transform_pos(*p)).collect();
+                egui::Shape::convex_polygon(points, *fill, Stroke::NONE)
+            }
+            Primitive::Text { pos, text, style } => {
+                let size = style.size * scale;
+                let galley = ctx.fonts(|f| f.layout_job(text_utils::layout_job(text, size, style.align, style.color)));
+                let pos = text_utils::galley_pos(to_screen.transform_pos(*pos), &galley, style.valign);
+                let mut shapes = vec![];
+                if let Some(background) = style.background {
+                    let rect = text_utils::background_rect(galley.rect, size).translate(pos.to_vec2());
+                    shapes.push(egui::Shape::rect_filled(rect, 0.0, background));
+                }
+                shapes.push(egui::Shape::galley(pos, galley));
+                egui::Shape::Vec(shapes)
+            }
+        }
+    }
+
+    // Se pos (in pixel dell'immagine) cade sul primitivo, con tolerance pixel di margine
+    pub fn hit_test(&self, ctx: &Context, pos: Pos2, tolerance: f32) -> bool {
+        match self {
+            Primitive::Highlight { points, stroke } => Primitive::Polyline {
+                points: points.clone(),
+                closed: false,
+                stroke: *stroke,
+            }
+            .hit_test(ctx, pos, tolerance),
+            Primitive::Polyline { points, closed, stroke } => {
+                let reach = stroke.width / 2.0 + tolerance;
+                if points.len() == 1 {
+                    return points[0].distance(pos) <= reach;
+                }
+                let closing = if *closed && points.len() > 2 { Some([points[points.len() - 1], points[0]]) } else { None };
+                points
+                    .windows(2)
+                    .map(|segment| [segment[0], segment[1]])
+                    .chain(closing)
+                    .any(|[a, b]| distance_to_segment(pos, a, b) <= reach)
+            }
+            Primitive::Polygon { points, .. } => {
+                contains(points, pos)
+                    || (0..points.len())
+                        .any(|i| distance_to_segment(pos, points[i], points[(i + 1) % points.len()]) <= tolerance)
+            }
+            Primitive::Text { .. } => self.bounding_rect(ctx).expand(tolerance).contains(pos),
+        }
+    }
+
+    pub fn bounding_rect(&self, ctx: &Context) -> Rect {
+        match self {
+            Primitive::Polyline { points, stroke, .. } | Primitive::Highlight { points, stroke } => {
+                Rect::from_points(points).expand(stroke.width / 2.0)
+            }
+            Primitive::Polygon { points, .. } => Rect::from_points(points),
+            Primitive::Text { pos, text, style } => {
+                let galley = ctx.fonts(|f| f.layout_job(text_utils::layout_job(text, style.size, style.align, style.color)));
+                let rect = match style.background {
+                    Some(_) => text_utils::background_rect(galley.rect, style.size),
+                    None => galley.rect,
+                };
+                rect.translate(text_utils::galley_pos(*pos, &galley, style.valign).to_vec2())
+            }
+        }
+    }
+}
+
+// Un'annotazione: la forma con il tratto e, per rettangoli, ellissi e testi, il riempimento
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawObj {
+    pub shape: Shape,
+    pub stroke: Stroke,
+    pub fill: Option<Color32>,
+}
+
+impl DrawObj {
+    pub fn new(shape: Shape, stroke: Stroke, fill: Option<Color32>) -> Self {
+        Self { shape, stroke, fill }
+    }
+
+    // Una forma troppo piccola per essere vista (es. un clic senza trascinare) non viene aggiunta
+    pub fn is_empty(&self) -> bool {
+        match &self.shape {
+            Shape::Freehand(points) | Shape::Highlight(points) => points.len() < 2,
+            Shape::Line { from, to } | Shape::Arrow { from, to, .. } => from.distance(*to) < 1.0,
+            Shape::Rectangle(rect) | Shape::Ellipse(rect) | Shape::Redact { rect, .. } | Shape::Spotlight { rect, .. } => {
+                rect.width() < 1.0 || rect.height() < 1.0
+            }
+            Shape::Text { text, .. } => text.trim().is_empty(),
+            Shape::Step { .. } => false, // Basta un clic
+        }
+    }
+
+    // Aggiorna la forma che si sta trascinando: start è dove è iniziato il trascinamento
+    pub fn drag_to(&mut self, start: Pos2, pos: Pos2) {
+        match &mut self.shape {
+            Shape::Freehand(points) | Shape::Highlight(points) => {
+                if points.last() != Some(&pos) {
+                    points.push(pos);
+                }
+            }
+            Shape::Line { to, .. } | Shape::Arrow { to, .. } => *to = pos,
+            Shape::Rectangle(rect) | Shape::Ellipse(rect) | Shape::Redact { rect, .. } | Shape::Spotlight { rect, .. } => {
+                *rect = Rect::from_two_pos(start, pos)
+            }
+            Shape::Text { pos: anchor, .. } => *anchor = pos, // Il testo si sposta con il puntatore
+            // Trascinando fuori dal cerchio si aggiunge la freccia
+            Shape::Step { center, radius, target, .. } => {
+                *target = if center.distance(pos) > *radius { Some(pos) } else { None };
+            }
+        }
+    }
+
+    // Rettangoli, ellissi e testi possono avere un riempimento
+    pub fn can_fill(&self) -> bool {
+        matches!(self.shape, Shape::Rectangle(_) | Shape::Ellipse(_) | Shape::Text { .. })
+    }
+
+    pub fn hit_test(&self, ctx: &Context, pos: Pos2, tolerance: f32) -> bool {
+        if let Shape::Redact { rect, .. } | Shape::Spotlight { rect, .. } = self.shape {
+            return rect.expand(tolerance).contains(pos); // Non hanno primitivi
+        }
+        self.primitives().iter().any(|p| p.hit_test(ctx, pos, tolerance))
+    }
+
+    // Il rettangolo dei punti che definiscono la forma (per il testo, il riquadro del testo): è quello
+    // che le maniglie spostano e ridimensionano
+    pub fn bounding_rect(&self, ctx: &Context) -> Rect {
+        match &self.shape {
+            Shape::Freehand(points) | Shape::Highlight(points) => Rect::from_points(points),
+            Shape::Line { from, to } | Shape::Arrow { from, to, .. } => Rect::from_two_pos(*from, *to),
+            Shape::Rectangle(rect) | Shape::Ellipse(rect) | Shape::Redact { rect, .. } | Shape::Spotlight { rect, .. } => *rect,
+            Shape::Text { .. } => self
+                .primitives()
+                .iter()
+                .fold(Rect::NOTHING, |rect, p| rect.union(p.bounding_rect(ctx))),
+            Shape::Step { center, radius, target, .. } => {
+                let circle = Rect::from_center_size(*center, Vec2::splat(*radius * 2.0));
+                target.map_or(circle, |target| circle.union(Rect::from_min_max(target, target)))
+            }
+        }
+    }
+
+    // Porta la forma dal rettangolo from al rettangolo to. Il testo cambia dimensione con l'altezza.
+    pub fn resize(&mut self, from: Rect, to: Rect) {
+        let remap = |pos: Pos2| {
+            pos2(
+                to.min.x + (pos.x - from.min.x) * ratio(from.width(), to.width()),
+                to.min.y + (pos.y - from.min.y) * ratio(from.height(), to.height()),
+            )
+        };
+        match &mut self.shape {
+            Shape::Freehand(points) | Shape::Highlight(points) => points.iter_mut().for_each(|p| *p = remap(*p)),
+            Shape::Line { from, to } | Shape::Arrow { from, to, .. } => {
+                *from = remap(*from);
+                *to = remap(*to);
+            }
+            Shape::Rectangle(rect) | Shape::Ellipse(rect) | Shape::Redact { rect, .. } | Shape::Spotlight { rect, .. } => {
+                *rect = Rect::from_two_pos(remap(rect.min), remap(rect.max))
+            }
+            Shape::Text { pos, size, .. } => {
+                *pos = remap(*pos);
+                *size = (*size * ratio(from.height(), to.height())).max(1.0);
+            }
+            Shape::Step { center, radius, target, .. } => {
+                *center = remap(*center);
+                *target = target.map(remap);
+                *radius = (*radius * ratio(from.height(), to.height())).max(1.0);
+            }
+        }
+    }
+
+    pub fn translate(&mut self, delta: Vec2) {
+        match &mut self.shape {
+            Shape::Freehand(points) | Shape::Highlight(points) => points.iter_mut().for_each(|p| *p += delta),
+            Shape::Line { from, to } | Shape::Arrow { from, to, .. } => {
+                *from += delta;
+                *to += delta;
+            }
+            Shape::Rectangle(rect) | Shape::Ellipse(rect) | Shape::Redact { rect, .. } | Shape::Spotlight { rect, .. } => {
+                *rect = rect.translate(delta)
+            }
+            Shape::Text { pos, .. } => *pos += delta,
+            Shape::Step { center, target, .. } => {
+                *center += delta;
+                *target = target.map(|target| target + delta);
+            }
+        }
+    }
+
+    // Segue l'immagine ruotata di un quarto di giro; size è la dimensione dell'immagine prima di ruotarla.
+    // Il testo resta orizzontale: si sposta soltanto, in modo che il suo centro segua la rotazione.
+    pub fn rotate(&mut self, ctx: &Context, clockwise: bool, size: Vec2) {
+        let rotate = |pos: Pos2| {
+            if clockwise {
+                pos2(size.y - pos.y, pos.x)
+            } else {
+                pos2(pos.y, size.x - pos.x)
+            }
+        };
+        match &mut self.shape {
+            Shape::Freehand(points) | Shape::Highlight(points) => points.iter_mut().for_each(|p| *p = rotate(*p)),
+            Shape::Line { from, to } | Shape::Arrow { from, to, .. } => {
+                *from = rotate(*from);
+                *to = rotate(*to);
+            }
+            Shape::Rectangle(rect) | Shape::Ellipse(rect) | Shape::Redact { rect, .. } | Shape::Spotlight { rect, .. } => {
+                *rect = Rect::from_two_pos(rotate(rect.min), rotate(rect.max))
+            }
+            Shape::Text { .. } => {
+                let center = self.bounding_rect(ctx).center();
+                self.translate(rotate(center) - center);
+            }
+            Shape::Step { center, target, .. } => {
+                *center = rotate(*center);
+                *target = target.map(rotate);
+            }
+        }
+    }
+
+    // Il riempimento va sotto il tratto. Redazioni e spotlight non hanno primitivi: non si disegnano sopra
+    // lo screenshot, ne cambiano i pixel (vedi redact_utils e spotlight_holes).
+    pub fn primitives(&self) -> Vec<Primitive> {
+        let stroke = self.stroke;
+        match &self.shape {
+            Shape::Freehand(points) => vec![Primitive::Polyline {
+                points: points.clone(),
+                closed: false,
+                stroke,
+            }],
+            Shape::Line { from, to } => vec![Primitive::Polyline {
+                points: vec![*from, *to],
+                closed: false,
+                stroke,
+            }],
+            Shape::Arrow { from, to, head } => arrow(*from, *to, *head, stroke),
+            Shape::Rectangle(rect) => closed_shape(
+                vec![rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()],
+                stroke,
+                self.fill,
+            ),
+            Shape::Ellipse(rect) => closed_shape(ellipse_points(*rect), stroke, self.fill),
+            // Il testo ha il colore del tratto, e il riempimento fa da riquadro di sfondo
+            Shape::Text { pos, text, size, align } => vec![Primitive::Text {
+                pos: *pos,
+                text: text.clone(),
+                style: TextStyle {
+                    size: *size,
+                    align: *align,
+                    valign: Align::Min,
+                    color: stroke.color,
+                    background: self.fill,
+                },
+            }],
+            Shape::Step { center, radius, number, target } => step(*center, *radius, *number, *target, stroke),
+            Shape::Highlight(points) => vec![Primitive::Highlight { points: points.clone(), stroke }],
+            Shape::Redact { .. } | Shape::Spotlight { .. } => vec![],
+        }
+    }
+}
+
+// Le aree di tutti gli spotlight, come poligoni chiusi nello stesso verso: fuori da queste l'immagine si scurisce
+pub fn spotlight_holes<'a>(shapes: impl Iterator<Item = &'a DrawObj>) -> Vec<Vec<Pos2>> {
+    shapes
+        .filter_map(|shape| match shape.shape {
+            Shape::Spotlight { rect, ellipse: true } => Some(ellipse_points(rect)),
+            Shape::Spotlight { rect, ellipse: false } => {
+                Some(vec![rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()])
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Dà ai passi i numeri 1, 2, 3… nell'ordine in cui sono nel disegno, così eliminandone uno gli altri scalano
+pub fn renumber_steps(shapes: &mut [DrawObj]) {
+    let mut next = 1;
+    for shape in shapes.iter_mut() {
+        if let Shape::Step { number, .. } = &mut shape.shape {
+            *number = next;
+            next += 1;
+        }
+    }
+}
+
+// Il cerchio ha il colore del tratto e il numero è bianco o nero, quello che si legge meglio. La freccia
+// parte dal bordo del cerchio, con lo spessore del tratto, e va sotto il cerchio.
+fn step(center: Pos2, radius: f32, number: u32, target: Option<Pos2>, stroke: Stroke) -> Vec<Primitive> {
+    let mut primitives = vec![];
+    if let Some(target) = target {
+        let direction = (target - center).normalized();
+        primitives.extend(arrow(center + direction * radius, target, ArrowHead::Filled, stroke));
+    }
+    let circle = Rect::from_center_size(center, Vec2::splat(radius * 2.0));
+    primitives.push(Primitive::Polygon { points: ellipse_points(circle), fill: stroke.color });
+    let [r, g, b, _] = stroke.color.to_srgba_unmultiplied();
+    let luminance = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+    primitives.push(Primitive::Text {
+        pos: center,
+        text: number.to_string(),
+        style: TextStyle {
+            size: radius * 1.2,
+            align: Align::Center,
+            valign: Align::Center,
+            color: if luminance > 150.0 { Color32::BLACK } else { Color32::WHITE },
+            background: None,
+        },
+    });
+    primitives
+}
+
+fn closed_shape(points: Vec<Pos2>, stroke: Stroke, fill: Option<Color32>) -> Vec<Primitive> {
+    let mut primitives = vec![];
+    if let Some(fill) = fill {
+        primitives.push(Primitive::Polygon { points: points.clone(), fill });
+    }
+    primitives.push(Primitive::Polyline { points, closed: true, stroke });
+    primitives
+}
+
+// Abbastanza punti perché i lati non si vedano neanche sulle ellissi grandi
+fn ellipse_points(rect: Rect) -> Vec<Pos2> {
+    let radius = rect.size() / 2.0;
+    let segments = (radius.max_elem() * 0.5).clamp(16.0, 256.0) as usize;
+    (0..segments)
+        .map(|i| {
+            let angle = i as f32 / segments as f32 * TAU;
+            rect.center() + vec2(radius.x * angle.cos(), radius.y * angle.sin())
+        })
+        .collect()
+}
+
+// La punta cresce con lo spessore del tratto. Con le punte piene l'asta si ferma alla base del triangolo,
+// altrimenti con i tratti spessi sporgerebbe oltre la punta.
+fn arrow(from: Pos2, to: Pos2, head: ArrowHead, stroke: Stroke) -> Vec<Primitive> {
+    let length = from.distance(to);
+    if length < f32::EPSILON {
+        return vec![];
+    }
+    let direction = (to - from) / length;
+    let normal = vec2(-direction.y, direction.x);
+    let head_length = (stroke.width * 3.0 + 8.0).min(length * 0.5);
+    let head_width = head_length * 0.6;
+
+    let triangle = |tip: Pos2, direction: Vec2| {
+        let base = tip - direction * head_length;
+        vec![tip, base + normal * head_width, base - normal * head_width]
+    };
+
+    match head {
+        ArrowHead::Open => {
+            let base = to - direction * head_length;
+            vec![
+                Primitive::Polyline { points: vec![from, to], closed: false, stroke },
+                Primitive::Polyline {
+                    points: vec![base + normal * head_width, to, base - normal * head_width],
+                    closed: false,
+                    stroke,
+                },
+            ]
+        }
+        ArrowHead::Filled => vec![
+            Primitive::Polyline {
+                points: vec![from, to - direction * head_length * 0.9],
+                closed: false,
+                stroke,
+            },
+            Primitive::Polygon { points: triangle(to, direction), fill: stroke.color },
+        ],
+        ArrowHead::Double => vec![
+            Primitive::Polyline {
+                points: vec![from + direction * head_length * 0.9, to - direction * head_length * 0.9],
+                closed: false,
+                stroke,
+            },
+            Primitive::Polygon { points: triangle(to, direction), fill: stroke.color },
+            Primitive::Polygon { points: triangle(from, -direction), fill: stroke.color },
+        ],
+    }
+}
+
+// Tiene il punto dentro l'immagine, così le forme non escono dai bordi
+pub fn clamp_to_image(pos: Pos2, size: Vec2) -> Pos2 {
+    pos2(pos.x.clamp(0.0, size.x), pos.y.clamp(0.0, size.y))
+}
+
+// Un lato lungo zero (es. una linea orizzontale) non si può scalare: resta com'è
+fn ratio(from: f32, to: f32) -> f32 {
+    if from > f32::EPSILON {
+        to / from
+    } else {
+        1.0
+    }
+}
+
+fn distance_to_segment(pos: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let ab = b - a;
+    let length_sq = ab.length_sq();
+    if length_sq < f32::EPSILON {
+        return pos.distance(a);
+    }
+    let t = ((pos - a).dot(ab) / length_sq).clamp(0.0, 1.0);
+    pos.distance(a + ab * t)
+}
+
+// Regola pari-dispari: vale per qualsiasi poligono chiuso
+fn contains(points: &[Pos2], pos: Pos2) -> bool {
+    let mut inside = false;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        if (a.y > pos.y) != (b.y > pos.y) && pos.x < a.x + (pos.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Context {
+        let ctx = Context::default();
+        let _ = ctx.run(Default::default(), |_| {}); // I font ci sono solo dopo il primo frame
+        ctx
+    }
+
+    fn obj(shape: Shape, width: f32, fill: Option<Color32>) -> DrawObj {
+        DrawObj::new(shape, Stroke::new(width, Color32::RED), fill)
+    }
+
+    // Per ogni forma un punto che la colpisce e uno che la manca, senza tolleranza
+    #[test]
+    fn every_shape_is_hit_and_missed() {
+        let ctx = context();
+        let rect = Rect::from_min_max(pos2(20., 20.), pos2(120., 80.));
+        let cases = [
+            (obj(Shape::Freehand(vec![pos2(0., 0.), pos2(50., 50.), pos2(100., 0.)]), 4., None), pos2(50., 49.), pos2(50., 20.)),
+            (obj(Shape::Line { from: pos2(0., 0.), to: pos2(100., 100.) }, 4., None), pos2(51., 50.), pos2(70., 30.)),
+            (
+                obj(Shape::Arrow { from: pos2(0., 50.), to: pos2(100., 50.), head: ArrowHead::Filled }, 4., None),
+                pos2(97., 51.),
+                pos2(50., 70.),
+            ),
+            (obj(Shape::Rectangle(rect), 4., None), pos2(120., 50.), pos2(70., 50.)),
+            (obj(Shape::Rectangle(rect), 4., Some(Color32::BLUE)), pos2(70., 50.), pos2(130., 50.)),
+            (obj(Shape::Ellipse(rect), 4., None), pos2(120., 50.), pos2(70., 50.)),
+            (obj(Shape::Ellipse(rect), 4., Some(Color32::BLUE)), pos2(70., 50.), pos2(22., 22.)),
+            (
+                obj(Shape::Text { pos: pos2(10., 10.), text: "Hello".to_string(), size: 20., align: Align::Min }, 1., None),
+                pos2(20., 20.),
+                pos2(10., 60.),
+            ),
+            (
+                obj(Shape::Step { center: pos2(50., 50.), radius: 10., number: 1, target: Some(pos2(150., 50.)) }, 2., None),
+                pos2(120., 50.),
+                pos2(50., 70.),
+            ),
+            (obj(Shape::Step { center: pos2(50., 50.), radius: 10., number: 1, target: None }, 2., None), pos2(55., 55.), pos2(100., 50.)),
+            (obj(Shape::Highlight(vec![pos2(0., 50.), pos2(100., 50.)]), 18., None), pos2(50., 58.), pos2(50., 62.)),
+            (obj(Shape::Redact { rect, mode: RedactMode::Blur }, 1., None), pos2(70., 50.), pos2(130., 50.)),
+            (obj(Shape::Spotlight { rect, ellipse: true }, 1., None), pos2(70., 50.), pos2(10., 10.)),
+        ];
+        for (shape, hit, miss) in cases {
+            assert!(shape.hit_test(&ctx, hit, 0.0), "{:?} should be hit at {:?}", shape.shape, hit);
+            assert!(!shape.hit_test(&ctx, miss, 0.0), "{:?} should be missed at {:?}", shape.shape, miss);
+        }
+    }
+
+    // Un tratto spesso si colpisce per metà della sua larghezza, più la tolleranza
+    #[test]
+    fn thick_strokes_have_a_tolerance_band() {
+        let ctx = context();
+        let line = obj(Shape::Line { from: pos2(0., 50.), to: pos2(100., 50.) }, 20., None);
+        assert!(line.hit_test(&ctx, pos2(50., 59.5), 0.0));
+        assert!(!line.hit_test(&ctx, pos2(50., 62.), 0.0));
+        assert!(line.hit_test(&ctx, pos2(50., 62.), 3.0));
+        assert!(!line.hit_test(&ctx, pos2(50., 64.), 3.0));
+        // Anche oltre le estremità, che sono arrotondate
+        assert!(line.hit_test(&ctx, pos2(108., 50.), 0.0));
+        assert!(!line.hit_test(&ctx, pos2(112., 50.), 0.0));
+        // Redazioni e spotlight si allargano della sola tolleranza
+        let redact = obj(Shape::Redact { rect: Rect::from_min_max(pos2(0., 0.), pos2(10., 10.)), mode: RedactMode::Solid }, 1., None);
+        assert!(!redact.hit_test(&ctx, pos2(12., 5.), 0.0));
+        assert!(redact.hit_test(&ctx, pos2(12., 5.), 3.0));
+    }
+
+    #[test]
+    fn resize_maps_bounds_to_bounds() {
+        let ctx = context();
+        let from = Rect::from_min_max(pos2(0., 0.), pos2(100., 50.));
+        let to = Rect::from_min_max(pos2(10., 10.), pos2(60., 110.));
+        let mut rectangle = obj(Shape::Rectangle(from), 2., None);
+        rectangle.resize(from, to);
+        assert_eq!(rectangle.shape, Shape::Rectangle(to));
+
+        let mut step = obj(Shape::Step { center: pos2(50., 25.), radius: 10., number: 1, target: None }, 2., None);
+        step.resize(from, to);
+        assert_eq!(step.shape, Shape::Step { center: pos2(35., 60.), radius: 20., number: 1, target: None });
+
+        let mut text = obj(Shape::Text { pos: pos2(0., 0.), text: "Hi".to_string(), size: 20., align: Align::Min }, 1., None);
+        text.resize(from, to);
+        assert!(matches!(text.shape, Shape::Text { pos, size, .. } if pos == pos2(10., 10.) && size == 40.));
+        assert!(text.bounding_rect(&ctx).is_positive());
+    }
+
+    // Una linea orizzontale ha un riquadro alto zero: ridimensionandolo non si divide per zero
+    #[test]
+    fn resizing_degenerate_bounds_stays_finite() {
+        let mut line = obj(Shape::Line { from: pos2(10., 50.), to: pos2(110., 50.) }, 2., None);
+        let ctx = context();
+        let from = line.bounding_rect(&ctx);
+        assert_eq!(from.height(), 0.0);
+        line.resize(from, Rect::from_min_max(pos2(10., 50.), pos2(60., 80.)));
+        assert_eq!(line.shape, Shape::Line { from: pos2(10., 50.), to: pos2(60., 50.) });
+
+        let mut vertical = obj(Shape::Freehand(vec![pos2(5., 0.), pos2(5., 10.), pos2(5., 20.)]), 2., None);
+        let from = vertical.bounding_rect(&ctx);
+        vertical.resize(from, Rect::from_min_max(pos2(0., 0.), pos2(40., 40.)));
+        assert_eq!(vertical.shape, Shape::Freehand(vec![pos2(0., 0.), pos2(0., 20.), pos2(0., 40.)]));
+    }
+
+    // I numeri dei passi, con la x del centro per riconoscerli
+    fn steps(shapes: &[DrawObj]) -> Vec<(u32, f32)> {
+        shapes
+            .iter()
+            .filter_map(|shape| match shape.shape {
+                Shape::Step { number, center, .. } => Some((number, center.x)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deleting_a_step_renumbers_the_others_and_undo_restores_them() {
+        use crate::app::history_utils::{Command, History};
+        let step = |x: f32| obj(Shape::Step { center: pos2(x, 10.), radius: 8., number: 0, target: None }, 2., None);
+        let line = obj(Shape::Line { from: pos2(0., 0.), to: pos2(5., 5.) }, 2., None);
+        let mut shapes = vec![step(10.), line, step(20.), step(30.)];
+        let mut image = image::RgbaImage::new(1, 1);
+        renumber_steps(&mut shapes);
+        assert_eq!(steps(&shapes), vec![(1, 10.), (2, 20.), (3, 30.)]);
+
+        let mut history = History::default();
+        let deleted = shapes.remove(2);
+        history.record(Command::delete(vec![(2, deleted)]));
+        renumber_steps(&mut shapes);
+        assert_eq!(steps(&shapes), vec![(1, 10.), (2, 30.)]);
+
+        history.undo(&mut shapes, &mut image);
+        renumber_steps(&mut shapes);
+        assert_eq!(steps(&shapes), vec![(1, 10.), (2, 20.), (3, 30.)]);
+
+        history.redo(&mut shapes, &mut image);
+        renumber_steps(&mut shapes);
+        assert_eq!(steps(&shapes), vec![(1, 10.), (2, 30.)]);
+    }
+
+    #[test]
+    fn translate_moves_the_bounds() {
+        let ctx = context();
+        let delta = vec2(7., -3.);
+        let shapes = [
+            obj(Shape::Freehand(vec![pos2(0., 0.), pos2(10., 20.)]), 2., None),
+            obj(Shape::Arrow { from: pos2(0., 0.), to: pos2(30., 10.), head: ArrowHead::Open }, 2., None),
+            obj(Shape::Ellipse(Rect::from_min_max(pos2(5., 5.), pos2(25., 15.))), 2., None),
+            obj(Shape::Text { pos: pos2(10., 10.), text: "Hi".to_string(), size: 16., align: Align::Center }, 1., None),
+            obj(Shape::Step { center: pos2(50., 50.), radius: 8., number: 2, target: Some(pos2(90., 70.)) }, 2., None),
+            obj(Shape::Spotlight { rect: Rect::from_min_max(pos2(0., 0.), pos2(10., 10.)), ellipse: false }, 1., None),
+        ];
+        for shape in shapes {
+            let mut moved = shape.clone();
+            moved.translate(delta);
+            let (before, after) = (shape.bounding_rect(&ctx), moved.bounding_rect(&ctx));
+            assert!((after.min - (before.min + delta)).length() < 1e-3, "{:?}", shape.shape);
+            assert!((after.max - (before.max + delta)).length() < 1e-3, "{:?}", shape.shape);
+        }
+    }
+}
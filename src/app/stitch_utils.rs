@@ -0,0 +1,251 @@
+use image::RgbaImage;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Quante righe devono sovrapporsi almeno perché uno spostamento sia credibile
+const MIN_OVERLAP_ROWS: usize = 16;
+// Quante righe della sovrapposizione possono non corrispondere (cursori che lampeggiano, animazioni...)
+const MAX_MISMATCH_RATIO: f32 = 0.05;
+
+// Com'è cambiato un fotogramma rispetto al precedente
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overlap {
+    Identical,        // Non è stato fatto scorrere niente
+    Shifted {
+        rows: u32,    // Di quante righe è scorso il contenuto verso l'alto
+        header: u32,  // Righe in alto che non scorrono (es. la barra di una pagina web)
+        footer: u32,  // Righe in basso che non scorrono
+    },
+    NotFound,         // Nessuna sovrapposizione: si è scorso più di un'altezza, o la regione è cambiata
+}
+
+// Un hash per ogni riga dell'immagine: confrontare le righe diventa confrontare due u64
+pub fn row_hashes(image: &RgbaImage) -> Vec<u64> {
+    let row_len = image.width() as usize * 4;
+    image
+        .as_raw()
+        .chunks_exact(row_len.max(1))
+        .map(|row| {
+            let mut hasher = DefaultHasher::new();
+            row.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+// Cerca di quanto è scorso il contenuto tra prev e next, che devono avere la stessa dimensione.
+// Le righe uguali nella stessa posizione all'inizio e alla fine di entrambi sono intestazione e piè di pagina fissi,
+// e vengono escluse. Nel resto si cerca lo spostamento per cui le righe in cima a next coincidono meglio
+// con quelle in fondo a prev. Si suppone che si scorra verso il basso.
+pub fn find_overlap(prev: &[u64], next: &[u64]) -> Overlap {
+    if prev.len() != next.len() {
+        return Overlap::NotFound;
+    }
+    let height = prev.len();
+    let header = prev.iter().zip(next).take_while(|(a, b)| a == b).count();
+    if header == height {
+        return Overlap::Identical;
+    }
+    let footer = prev
+        .iter()
+        .rev()
+        .zip(next.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let prev_body = &prev[header..height - footer];
+    let next_body = &next[header..height - footer];
+    let body = prev_body.len();
+    let min_overlap = MIN_OVERLAP_ROWS.min(body / 2).max(1);
+
+    // Le zone uniformi (righe vuote) coincidono con molti spostamenti: si sceglie quello con meno differenze,
+    // e a parità il più piccolo, cioè con la sovrapposizione più lunga
+    let mut best: Option<(f32, usize)> = None;
+    for shift in 1..=body.saturating_sub(min_overlap) {
+        let overlap = body - shift;
+        let mismatches = prev_body[shift..]
+            .iter()
+            .zip(&next_body[..overlap])
+            .filter(|(a, b)| a != b)
+            .count();
+        let ratio = mismatches as f32 / overlap as f32;
+        if ratio <= MAX_MISMATCH_RATIO && best.map_or(true, |(best_ratio, _)| ratio < best_ratio) {
+            best = Some((ratio, shift));
+        }
+    }
+    match best {
+        Some((_, shift)) => Overlap::Shifted {
+            rows: shift as u32,
+            header: header as u32,
+            footer: footer as u32,
+        },
+        None => Overlap::NotFound,
+    }
+}
+
+// Unisce i fotogrammi di una cattura a scorrimento in un'unica immagine alta.
+// L'immagine finisce sempre con l'ultimo fotogramma intero, compreso il suo piè di pagina:
+// quando arriva un fotogramma nuovo si toglie il piè di pagina, si aggiungono le righe nuove e poi di nuovo il piè di pagina.
+pub struct Stitcher {
+    width: u32,
+    frame_height: u32,
+    data: Vec<u8>,
+    last: Vec<u64>,
+    pub frames: u32, // Fotogrammi effettivamente usati
+}
+
+impl Stitcher {
+    pub fn new(first: RgbaImage) -> Self {
+        Self {
+            width: first.width(),
+            frame_height: first.height(),
+            last: row_hashes(&first),
+            data: first.into_raw(),
+            frames: 1,
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        (self.data.len() / (self.width as usize * 4).max(1)) as u32
+    }
+
+    // Aggiunge un fotogramma. Se non si trova la sovrapposizione il fotogramma viene scartato.
+    pub fn push(&mut self, frame: &RgbaImage) -> Overlap {
+        if frame.width() != self.width || frame.height() != self.frame_height {
+            return Overlap::NotFound;
+        }
+        let hashes = row_hashes(frame);
+        let overlap = find_overlap(&self.last, &hashes);
+        if let Overlap::Shifted { rows, footer, .. } = overlap {
+            let row_len = self.width as usize * 4;
+            let raw = frame.as_raw();
+            let (rows, footer, height) = (rows as usize, footer as usize, self.frame_height as usize);
+            self.data.truncate(self.data.len() - footer * row_len);
+            self.data
+                .extend_from_slice(&raw[(height - footer - rows) * row_len..(height - footer) * row_len]);
+            self.data.extend_from_slice(&raw[(height - footer) * row_len..]);
+            self.last = hashes;
+            self.frames += 1;
+        }
+        overlap
+    }
+
+    pub fn finish(self) -> RgbaImage {
+        let height = self.height();
+        RgbaImage::from_raw(self.width, height, self.data).expect("the stitched image has whole rows")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const WIDTH: u32 = 8;
+
+    // Una pagina in cui ogni riga è diversa dalle altre
+    fn page(height: u32) -> RgbaImage {
+        RgbaImage::from_fn(WIDTH, height, |x, y| Rgba([y as u8, (y >> 8) as u8, x as u8, 255]))
+    }
+
+    // Quello che si vede dalla finestra quando la pagina è scorsa di offset righe
+    fn frame(page: &RgbaImage, offset: u32, height: u32) -> RgbaImage {
+        image::imageops::crop_imm(page, 0, offset, WIDTH, height).to_image()
+    }
+
+    // Una finestra con una barra fissa in alto e una in basso, e la pagina che scorre in mezzo
+    fn framed(page: &RgbaImage, offset: u32, header: u32, body: u32, footer: u32) -> RgbaImage {
+        let mut image = RgbaImage::from_pixel(WIDTH, header + body + footer, Rgba([200, 0, 0, 255]));
+        for y in header + body..header + body + footer {
+            for x in 0..WIDTH {
+                image.put_pixel(x, y, Rgba([0, 200, x as u8, 255]));
+            }
+        }
+        image::imageops::replace(&mut image, &frame(page, offset, body), 0, header as i64);
+        image
+    }
+
+    #[test]
+    fn known_overlap_is_found_and_stitched() {
+        let page = page(400);
+        let (first, second) = (frame(&page, 0, 100), frame(&page, 37, 100));
+        assert_eq!(
+            find_overlap(&row_hashes(&first), &row_hashes(&second)),
+            Overlap::Shifted { rows: 37, header: 0, footer: 0 }
+        );
+
+        let mut stitcher = Stitcher::new(first);
+        for offset in [37, 80, 150] {
+            assert!(matches!(stitcher.push(&frame(&page, offset, 100)), Overlap::Shifted { .. }));
+        }
+        assert_eq!(stitcher.frames, 4);
+        assert_eq!(stitcher.finish(), frame(&page, 0, 250));
+    }
+
+    #[test]
+    fn fixed_header_and_footer_appear_once() {
+        let page = page(400);
+        let (header, body, footer) = (10, 80, 6);
+        let mut stitcher = Stitcher::new(framed(&page, 0, header, body, footer));
+        assert_eq!(
+            stitcher.push(&framed(&page, 25, header, body, footer)),
+            Overlap::Shifted { rows: 25, header, footer }
+        );
+        let expected = framed(&page, 0, header, body + 25, footer);
+        assert_eq!(stitcher.finish(), expected);
+    }
+
+    #[test]
+    fn zero_overlap_discards_the_frame() {
+        let page = page(400);
+        let first = frame(&page, 0, 100);
+        let mut stitcher = Stitcher::new(first.clone());
+        assert_eq!(stitcher.push(&frame(&page, 100, 100)), Overlap::NotFound);
+        assert_eq!(stitcher.push(&frame(&page, 250, 100)), Overlap::NotFound);
+        assert_eq!(stitcher.frames, 1);
+        assert_eq!(stitcher.finish(), first);
+    }
+
+    #[test]
+    fn unchanged_frame_is_identical() {
+        let page = page(200);
+        let mut stitcher = Stitcher::new(frame(&page, 10, 100));
+        assert_eq!(stitcher.push(&frame(&page, 10, 100)), Overlap::Identical);
+        assert_eq!(stitcher.height(), 100);
+    }
+
+    #[test]
+    fn blank_rows_do_not_fool_the_search() {
+        // Una lunga zona vuota in mezzo alla pagina coincide con tanti spostamenti, il resto con uno solo
+        let mut page = page(400);
+        for y in 120..170 {
+            for x in 0..WIDTH {
+                page.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+        let (first, second) = (frame(&page, 90, 100), frame(&page, 102, 100));
+        assert_eq!(
+            find_overlap(&row_hashes(&first), &row_hashes(&second)),
+            Overlap::Shifted { rows: 12, header: 0, footer: 0 }
+        );
+    }
+
+    #[test]
+    fn repeated_rows_pick_the_smallest_shift() {
+        // Un motivo che si ripete ogni 10 righe: 5, 15, 25... coincidono tutti, si sceglie la sovrapposizione più lunga
+        let page = RgbaImage::from_fn(WIDTH, 400, |x, y| Rgba([(y % 10) as u8, 0, x as u8, 255]));
+        let (first, second) = (frame(&page, 0, 100), frame(&page, 5, 100));
+        assert_eq!(
+            find_overlap(&row_hashes(&first), &row_hashes(&second)),
+            Overlap::Shifted { rows: 5, header: 0, footer: 0 }
+        );
+    }
+
+    #[test]
+    fn frames_of_another_size_are_rejected() {
+        let page = page(200);
+        let mut stitcher = Stitcher::new(frame(&page, 0, 100));
+        assert_eq!(stitcher.push(&frame(&page, 10, 90)), Overlap::NotFound);
+        assert_eq!(stitcher.frames, 1);
+    }
+}
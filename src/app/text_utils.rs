@@ -0,0 +1,146 @@
+use ab_glyph::{Font, FontArc, FontVec, PxScale};
+use egui::epaint::text::{FontDefinitions, FontFamily, FontId, Fonts, Galley, LayoutJob};
+use egui::{Align, Color32, Pos2, Rect};
+use std::sync::Arc;
+use tiny_skia::{Paint, Pixmap, PremultipliedColorU8, Transform};
+
+// Il testo delle annotazioni usa i font di default di egui, sia sul canvas che nell'export. L'impaginazione
+// (righe, avanzamenti, kerning, allineamento) è sempre quella di egui, così le due versioni coincidono.
+
+const MAX_TEXTURE_SIDE: usize = 8192;
+
+// Come appare un testo: size è l'altezza del font, color quello delle lettere, background quello del riquadro.
+// align dice dove sta il punto di ancoraggio in orizzontale, valign in verticale (in alto o al centro).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    pub size: f32,
+    pub align: Align,
+    pub valign: Align,
+    pub color: Color32,
+    pub background: Option<Color32>,
+}
+
+// Il layout di un testo: size è l'altezza del font, nella stessa unità in cui verrà disegnato
+pub fn layout_job(text: &str, size: f32, align: Align, color: Color32) -> LayoutJob {
+    let mut job = LayoutJob::simple(text.to_string(), FontId::proportional(size), color, f32::INFINITY);
+    job.halign = align;
+    job
+}
+
+// Dove va disegnato il galley perché il punto di ancoraggio pos sia allineato in verticale come chiesto
+pub fn galley_pos(pos: Pos2, galley: &Galley, valign: Align) -> Pos2 {
+    match valign {
+        Align::Min => pos,
+        Align::Center => pos - egui::vec2(0.0, galley.rect.height() / 2.0),
+        Align::Max => pos - egui::vec2(0.0, galley.rect.height()),
+    }
+}
+
+// Il riquadro di sfondo intorno al testo impaginato in galley_rect
+pub fn background_rect(galley_rect: Rect, size: f32) -> Rect {
+    galley_rect.expand(size * 0.2)
+}
+
+// Disegna il testo sul pixmap dell'export. Crearlo costa (carica i font), quindi se ne usa uno per export.
+pub struct TextRasterizer {
+    fonts: Fonts,               // Per l'impaginazione: un punto è un pixel dell'immagine
+    faces: Vec<(FontArc, f32)>, // I font della famiglia proporzionale in ordine di fallback, con la loro scala
+}
+
+impl TextRasterizer {
+    pub fn new() -> Self {
+        let definitions = FontDefinitions::default();
+        let faces = definitions
+            .families
+            .get(&FontFamily::Proportional)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| definitions.font_data.get(name))
+            .filter_map(|data| {
+                let font = FontVec::try_from_vec_and_index(data.font.to_vec(), data.index).ok()?;
+                Some((FontArc::new(font), data.tweak.scale))
+            })
+            .collect();
+        Self {
+            fonts: Fonts::new(1.0, MAX_TEXTURE_SIDE, definitions),
+            faces,
+        }
+    }
+
+    pub fn layout(&self, text: &str, size: f32, align: Align, color: Color32) -> Arc<Galley> {
+        self.fonts.layout_job(layout_job(text, size, align, color))
+    }
+
+    // pos è il punto di ancoraggio del testo, come per painter.galley sul canvas.
+    // I glifi si fondono direttamente con i pixel (premoltiplicati) del pixmap, senza passare da un'immagine.
+    pub fn draw(&self, pixmap: &mut Pixmap, pos: Pos2, text: &str, style: &TextStyle) {
+        let galley = self.layout(text, style.size, style.align, style.color);
+        let pos = galley_pos(pos, &galley, style.valign);
+        if let Some(background) = style.background {
+            let rect = background_rect(galley.rect, style.size).translate(pos.to_vec2());
+            fill_rect(pixmap, rect, background);
+        }
+
+        let color = style.color.to_srgba_unmultiplied();
+        let (width, height) = (pixmap.width(), pixmap.height());
+        let pixels = pixmap.pixels_mut();
+        for row in galley.rows.iter() {
+            for glyph in row.glyphs.iter() {
+                // Come egui: il primo font che ha il carattere, alla sua dimensione arrotondata al pixel,
+                // rasterizzato nell'origine e poi spostato di un numero intero di pixel
+                let Some((font, scale)) = self.faces.iter().find(|(font, _)| font.glyph_id(glyph.chr).0 != 0) else {
+                    continue;
+                };
+                let scale = (style.size * scale).round();
+                let outline = font
+                    .glyph_id(glyph.chr)
+                    .with_scale_and_position(PxScale::from(scale), ab_glyph::point(0.0, 0.0));
+                let Some(outlined) = font.outline_glyph(outline) else {
+                    continue; // Spazi e caratteri senza forma
+                };
+                let baseline = pos + glyph.pos.to_vec2();
+                let bounds = outlined.px_bounds();
+                let left = baseline.x.round() as i32 + bounds.min.x as i32;
+                let top = baseline.y.round() as i32 + bounds.min.y as i32;
+                outlined.draw(|x, y, coverage| {
+                    let (x, y) = (left + x as i32, top + y as i32);
+                    if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                        let pixel = &mut pixels[y as usize * width as usize + x as usize];
+                        *pixel = blend(*pixel, color, coverage);
+                    }
+                });
+            }
+        }
+    }
+}
+
+// Il riquadro di sfondo, allineato ai pixel come sul canvas
+fn fill_rect(pixmap: &mut Pixmap, rect: Rect, color: Color32) {
+    let rect = tiny_skia::Rect::from_ltrb(rect.min.x.round(), rect.min.y.round(), rect.max.x.round(), rect.max.y.round());
+    let Some(rect) = rect else {
+        return;
+    };
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(r, g, b, a);
+    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+}
+
+// Source-over di un colore non premoltiplicato, con l'alpha moltiplicato per coverage
+fn blend(dst: PremultipliedColorU8, color: [u8; 4], coverage: f32) -> PremultipliedColorU8 {
+    let src_a = color[3] as f32 / 255.0 * coverage.clamp(0.0, 1.0);
+    if src_a <= 0.0 {
+        return dst;
+    }
+    let keep = 1.0 - src_a;
+    let channel = |src: u8, dst: u8| (src as f32 * src_a + dst as f32 * keep).round().min(255.0) as u8;
+    let a = channel(255, dst.alpha());
+    // Arrotondando, un canale premoltiplicato non deve superare l'alpha
+    PremultipliedColorU8::from_rgba(
+        channel(color[0], dst.red()).min(a),
+        channel(color[1], dst.green()).min(a),
+        channel(color[2], dst.blue()).min(a),
+        a,
+    )
+    .unwrap_or(dst)
+}
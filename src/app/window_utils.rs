@@ -0,0 +1,263 @@
+use super::coords_utils::PhysicalRect;
+
+// Una finestra di primo livello, con le coordinate in pixel fisici del desktop virtuale
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub id: u32,
+    pub title: String,
+    pub client: PhysicalRect,   // L'area della finestra, senza le decorazioni del window manager
+    pub frame: Extents,         // Decorazioni disegnate dal window manager intorno a client
+    pub shadow: Extents,        // Ombra disegnata dall'applicazione stessa, dentro client (finestre GTK con CSD)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Extents {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+impl WindowInfo {
+    // Il rettangolo da catturare, con o senza decorazioni e ombra
+    pub fn bounds(&self, include_decorations: bool, include_shadow: bool) -> PhysicalRect {
+        let mut min = (self.client.x, self.client.y);
+        let mut max = (self.client.right(), self.client.bottom());
+        if include_decorations {
+            min.0 -= self.frame.left as i32;
+            min.1 -= self.frame.top as i32;
+            max.0 += self.frame.right as i32;
+            max.1 += self.frame.bottom as i32;
+        }
+        if !include_shadow {
+            min.0 += self.shadow.left as i32;
+            min.1 += self.shadow.top as i32;
+            max.0 -= self.shadow.right as i32;
+            max.1 -= self.shadow.bottom as i32;
+        }
+        PhysicalRect::from_min_max(min, max)
+    }
+}
+
+// Elenca le finestre visibili, dalla più in alto alla più in basso. Le finestre di QuickCapture sono escluse.
+// Su X11 la lista arriva dal window manager (_NET_CLIENT_LIST_STACKING), sugli altri sistemi non è supportato.
+pub fn list_windows() -> Vec<WindowInfo> {
+    #[cfg(target_os = "linux")]
+    {
+        match x11::list_windows(None) {
+            Ok(windows) => windows,
+            Err(e) => {
+                println!("Could not list the windows: {}", e);
+                vec![]
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        vec![]
+    }
+}
+
+// La finestra più in alto che contiene il punto (in pixel fisici)
+pub fn window_at(
+    windows: &[WindowInfo],
+    x: i32,
+    y: i32,
+    include_decorations: bool,
+    include_shadow: bool,
+) -> Option<&WindowInfo> {
+    windows
+        .iter()
+        .find(|w| w.bounds(include_decorations, include_shadow).contains(x, y))
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use super::{Extents, WindowInfo};
+    use crate::app::coords_utils::PhysicalRect;
+    use std::error::Error;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+    use x11rb::rust_connection::RustConnection;
+
+    // display è quello di DISPLAY se None
+    pub fn list_windows(display: Option<&str>) -> Result<Vec<WindowInfo>, Box<dyn Error>> {
+        let (conn, screen_num) = x11rb::connect(display)?;
+        let root = conn.setup().roots[screen_num].root;
+        let atom = |name: &str| -> Result<Atom, Box<dyn Error>> {
+            Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+        };
+        let stacking = atom("_NET_CLIENT_LIST_STACKING")?;
+        let frame_extents = atom("_NET_FRAME_EXTENTS")?;
+        let gtk_frame_extents = atom("_GTK_FRAME_EXTENTS")?;
+        let wm_pid = atom("_NET_WM_PID")?;
+        let wm_name = atom("_NET_WM_NAME")?;
+        let wm_state = atom("_NET_WM_STATE")?;
+        let hidden = atom("_NET_WM_STATE_HIDDEN")?;
+
+        // Dal basso verso l'alto: la giriamo per avere prima quelle in primo piano
+        let mut clients = cardinals(&conn, root, stacking)?;
+        clients.reverse();
+
+        // Una finestra può chiudersi mentre la interroghiamo: le sue richieste falliscono e la si salta,
+        // senza perdere le altre. Solo un errore della connessione fa fallire tutta la lista.
+        let mut windows = vec![];
+        for window in clients {
+            let Ok(pid) = cardinals(&conn, window, wm_pid) else { continue };
+            if pid.first() == Some(&std::process::id()) {
+                continue;
+            }
+            let Ok(state) = cardinals(&conn, window, wm_state) else { continue };
+            if state.contains(&hidden) {
+                continue; // Minimizzata
+            }
+            let Ok(geometry) = conn.get_geometry(window)?.reply() else { continue };
+            let Ok(position) = conn.translate_coordinates(window, root, 0, 0)?.reply() else { continue };
+            let Ok(title) = conn.get_property(false, window, wm_name, AtomEnum::ANY, 0, 1024)?.reply() else {
+                continue;
+            };
+            let Ok(frame) = extents(&conn, window, frame_extents) else { continue };
+            let Ok(shadow) = extents(&conn, window, gtk_frame_extents) else { continue };
+            windows.push(WindowInfo {
+                id: window,
+                title: String::from_utf8_lossy(&title.value).to_string(),
+                client: PhysicalRect {
+                    x: position.dst_x as i32,
+                    y: position.dst_y as i32,
+                    width: geometry.width as u32,
+                    height: geometry.height as u32,
+                },
+                frame,
+                shadow,
+            });
+        }
+        Ok(windows)
+    }
+
+    fn cardinals(conn: &RustConnection, window: Window, property: Atom) -> Result<Vec<u32>, Box<dyn Error>> {
+        let reply = conn
+            .get_property(false, window, property, AtomEnum::ANY, 0, u32::MAX)?
+            .reply()?;
+        Ok(reply.value32().map(|values| values.collect()).unwrap_or_default())
+    }
+
+    // _NET_FRAME_EXTENTS e _GTK_FRAME_EXTENTS sono entrambe nell'ordine left, right, top, bottom
+    fn extents(conn: &RustConnection, window: Window, property: Atom) -> Result<Extents, Box<dyn Error>> {
+        match cardinals(conn, window, property)?.as_slice() {
+            [left, right, top, bottom] => Ok(Extents {
+                left: *left,
+                right: *right,
+                top: *top,
+                bottom: *bottom,
+            }),
+            _ => Ok(Extents::default()),
+        }
+    }
+}
+
+// Prova list_windows su un server X vero: avvia Xvfb, crea delle finestre e scrive a mano le proprietà che
+// altrimenti scriverebbe il window manager. Se Xvfb non è installato il test non fa niente.
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use std::time::{Duration, Instant};
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, CreateWindowAux, PropMode, WindowClass};
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::wrapper::ConnectionExt as _;
+    use x11rb::COPY_DEPTH_FROM_PARENT;
+
+    struct Xvfb(Child);
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    // Il primo display libero, con la connessione già aperta
+    fn start_xvfb() -> Option<(Xvfb, String, RustConnection, usize)> {
+        let number = (90..200).find(|n| {
+            !Path::new(&format!("/tmp/.X{}-lock", n)).exists() && !Path::new(&format!("/tmp/.X11-unix/X{}", n)).exists()
+        })?;
+        let display = format!(":{}", number);
+        let child = Command::new("Xvfb")
+            .args([display.as_str(), "-screen", "0", "1280x1024x24", "-nolisten", "tcp"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let xvfb = Xvfb(child);
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(5) {
+            if let Ok((conn, screen_num)) = x11rb::connect(Some(&display)) {
+                return Some((xvfb, display, conn, screen_num));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+
+    #[test]
+    #[ignore = "needs Xvfb in PATH: cargo test -- --ignored"]
+    fn list_windows_on_xvfb() {
+        let (_xvfb, display, conn, screen_num) = start_xvfb().expect("Xvfb did not start");
+        let root = conn.setup().roots[screen_num].root;
+        let atom = |name: &str| conn.intern_atom(false, name.as_bytes()).unwrap().reply().unwrap().atom;
+        let (wm_name, utf8) = (atom("_NET_WM_NAME"), atom("UTF8_STRING"));
+        let (wm_state, hidden) = (atom("_NET_WM_STATE"), atom("_NET_WM_STATE_HIDDEN"));
+
+        let create = |x: i16, y: i16, width: u16, height: u16, title: &str| {
+            let window = conn.generate_id().unwrap();
+            conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                x,
+                y,
+                width,
+                height,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                0,
+                &CreateWindowAux::new(),
+            )
+            .unwrap();
+            conn.change_property8(PropMode::REPLACE, window, wm_name, utf8, title.as_bytes()).unwrap();
+            conn.map_window(window).unwrap();
+            window
+        };
+        let bottom = create(10, 20, 300, 200, "Bottom");
+        let top = create(100, 150, 400, 250, "Top");
+        let minimized = create(0, 0, 50, 50, "Minimized");
+        conn.change_property32(PropMode::REPLACE, minimized, wm_state, AtomEnum::ATOM, &[hidden]).unwrap();
+        conn.change_property32(PropMode::REPLACE, top, atom("_NET_FRAME_EXTENTS"), AtomEnum::CARDINAL, &[1, 2, 30, 4])
+            .unwrap();
+        // Una finestra che non esiste più: va saltata senza far fallire la lista
+        let closed = conn.generate_id().unwrap();
+        conn.change_property32(
+            PropMode::REPLACE,
+            root,
+            atom("_NET_CLIENT_LIST_STACKING"),
+            AtomEnum::WINDOW,
+            &[bottom, closed, minimized, top],
+        )
+        .unwrap();
+        conn.sync().unwrap();
+
+        let windows = x11::list_windows(Some(&display)).unwrap();
+        let titles: Vec<&str> = windows.iter().map(|w| w.title.as_str()).collect();
+        assert_eq!(titles, vec!["Top", "Bottom"]);
+        assert_eq!(windows[0].id, top);
+        assert_eq!(windows[0].client, PhysicalRect { x: 100, y: 150, width: 400, height: 250 });
+        assert_eq!(windows[0].frame, Extents { left: 1, right: 2, top: 30, bottom: 4 });
+        assert_eq!(windows[1].client, PhysicalRect { x: 10, y: 20, width: 300, height: 200 });
+        assert_eq!(windows[1].frame, Extents::default());
+        assert_eq!(window_at(&windows, 200, 160, false, false).map(|w| w.id), Some(top));
+        assert_eq!(window_at(&windows, 200, 140, true, false).map(|w| w.id), Some(top));
+        assert_eq!(window_at(&windows, 20, 30, true, false).map(|w| w.id), Some(bottom));
+    }
+}
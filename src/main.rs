@@ -45,6 +45,9 @@ impl eframe::App for QuickCaptureApp {
             Views::Save => {
                 self.save_view(ctx, _frame);
             },
+            Views::Recording => {
+                self.recording_view(ctx, _frame);
+            },
         }
 
     }